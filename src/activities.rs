@@ -5,7 +5,10 @@ use tracing::info;
 
 use tui_input::Input;
 
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -13,7 +16,7 @@ use crate::app::{App, AppUpdate, AppView, SubState};
 use crate::broadcast;
 use crate::errors::AppError;
 
-const ACTIVITIES_TOML_PATH: &str = "activities.toml";
+pub const ACTIVITIES_TOML_PATH: &str = "activities.toml";
 
 pub struct Activities {
     pub current_activity: u8,
@@ -48,6 +51,13 @@ fn formatted_activities(activities: &BTreeMap<u8, String>) -> BTreeMap<u8, Strin
     formatted
 }
 
+/// Reads just the activity names, for when the app isn't running
+pub fn read_activity_names(path: &Path) -> Result<BTreeMap<u8, String>, AppError> {
+    let buffer = std::fs::read_to_string(path)?;
+    let file: ActivitiesFile = toml::from_str(&buffer)?;
+    Ok(file.activities)
+}
+
 impl Default for ActivitiesFile {
     fn default() -> Self {
         let mut map = BTreeMap::new();
@@ -145,6 +155,9 @@ impl Activities {
     pub fn selected(&self) -> Option<&String> {
         self.file.formatted.get(&self.current_activity)
     }
+    pub fn names(&self) -> &BTreeMap<u8, String> {
+        &self.file.activities
+    }
     pub fn query_from_input(&mut self) {
         let pattern = self.input.to_string().to_lowercase();
         let filtered: Vec<u8> = self
@@ -355,9 +368,15 @@ impl App {
         }
     }
     pub fn broadcast_activity(&mut self, activity: u8) {
+        let name = self
+            .activities
+            .names()
+            .get(&activity)
+            .cloned()
+            .unwrap_or_default();
         broadcast!(
             self.broadcast_tx,
-            AppUpdate::ActivitySelected(activity),
+            AppUpdate::ActivitySelected(activity, name),
             "Failed to send activity update!"
         );
     }
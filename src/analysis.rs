@@ -0,0 +1,535 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::session_logs::{csv_reader, parse_timestamp, start_from_file_name, TIMESTAMP_FORMAT};
+use crate::summary::{BatteryDrain, HrvSummary};
+
+// Gaps longer than this between rows (disconnections) don't count towards zones or activities
+const MAX_ROW_GAP: Duration = Duration::from_secs(5);
+
+// Columns added in later versions are optional, so older logs can still be analyzed
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    Timestamp: String,
+    BPM: u16,
+    #[serde(default)]
+    Artifacts: u32,
+    #[serde(default)]
+    Battery: u8,
+    #[serde(default)]
+    Activity: u8,
+    #[serde(default)]
+    Zone: Option<u8>,
+    #[serde(default)]
+    RMSSD: f32,
+    #[serde(default)]
+    SDNN: f32,
+    #[serde(default)]
+    pNN50: f32,
+    #[serde(default)]
+    StressIndex: f32,
+    #[serde(default)]
+    BreathsPerMin: Option<f32>,
+}
+
+struct Row {
+    timestamp: NaiveDateTime,
+    // Time until the next row, zero if it was a disconnection
+    weight: Duration,
+    data: CsvRow,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ActivityBreakdown {
+    pub index: u8,
+    pub name: String,
+    pub secs: u64,
+    pub average_bpm: f32,
+    pub max_bpm: u16,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SessionAnalysis {
+    pub file: String,
+    /// Local time, as logged
+    pub start: String,
+    pub duration_secs: u64,
+    /// Excludes disconnections
+    pub recorded_secs: u64,
+    pub samples: usize,
+    pub min_bpm: u16,
+    pub average_bpm: f32,
+    pub max_bpm: u16,
+    /// Indexed by zone, empty if the log predates zones
+    pub time_in_zones_secs: Vec<u64>,
+    pub activities: Vec<ActivityBreakdown>,
+    pub hrv: Option<HrvSummary>,
+    pub battery: Option<BatteryDrain>,
+}
+
+/// How a metric moved across sessions, oldest to newest
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Trend {
+    pub metric: String,
+    pub first: f32,
+    pub last: f32,
+    pub change: f32,
+    /// Least squares slope, per session
+    pub slope: f32,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AnalysisReport {
+    /// Oldest first
+    pub sessions: Vec<SessionAnalysis>,
+    /// Empty with less than two sessions
+    pub trends: Vec<Trend>,
+}
+
+impl SessionAnalysis {
+    /// Returns None if the log doesn't have any data in it
+    pub fn read<R: Read>(
+        file: String,
+        reader: R,
+        activity_names: &BTreeMap<u8, String>,
+    ) -> Result<Option<Self>, AppError> {
+        let session_start = Path::new(&file)
+            .file_stem()
+            .and_then(|stem| start_from_file_name(&stem.to_string_lossy()))
+            .unwrap_or_default();
+        let mut reader = csv_reader(reader)?;
+        let mut rows: Vec<Row> = Vec::new();
+        for data in reader.deserialize() {
+            let data: CsvRow = data?;
+            if data.BPM == 0 {
+                continue;
+            }
+            let timestamp = parse_timestamp(&data.Timestamp, session_start)?;
+            if let Some(previous) = rows.last_mut() {
+                let gap = (timestamp - previous.timestamp)
+                    .to_std()
+                    .unwrap_or_default();
+                previous.weight = if gap > MAX_ROW_GAP {
+                    Duration::ZERO
+                } else {
+                    gap
+                };
+            }
+            rows.push(Row {
+                timestamp,
+                weight: Duration::ZERO,
+                data,
+            });
+        }
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+            return Ok(None);
+        };
+        let duration = (last.timestamp - first.timestamp)
+            .to_std()
+            .unwrap_or_default();
+        let recorded: Duration = rows.iter().map(|row| row.weight).sum();
+        let bpms = || rows.iter().map(|row| row.data.BPM);
+
+        let mut time_in_zones = Vec::new();
+        for row in &rows {
+            if let Some(zone) = row.data.Zone {
+                let zone = zone as usize;
+                if time_in_zones.len() <= zone {
+                    time_in_zones.resize(zone + 1, Duration::ZERO);
+                }
+                time_in_zones[zone] += row.weight;
+            }
+        }
+
+        let mut activities: BTreeMap<u8, Vec<&Row>> = BTreeMap::new();
+        for row in &rows {
+            activities.entry(row.data.Activity).or_default().push(row);
+        }
+        let activities = activities
+            .into_iter()
+            .map(|(index, rows)| ActivityBreakdown {
+                index,
+                name: activity_names
+                    .get(&index)
+                    .cloned()
+                    .unwrap_or_else(|| format!("Activity {index}")),
+                secs: rows
+                    .iter()
+                    .map(|row| row.weight)
+                    .sum::<Duration>()
+                    .as_secs(),
+                average_bpm: mean(rows.iter().map(|row| row.data.BPM as f32)),
+                max_bpm: rows
+                    .iter()
+                    .map(|row| row.data.BPM)
+                    .max()
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        // Rows before the first full HRV window have zeroes
+        let hrv_rows: Vec<&CsvRow> = rows
+            .iter()
+            .map(|row| &row.data)
+            .filter(|data| data.RMSSD > 0.0)
+            .collect();
+        let hrv = (!hrv_rows.is_empty()).then(|| {
+            let rmssd = || hrv_rows.iter().map(|data| data.RMSSD);
+            let breaths: Vec<f32> = hrv_rows.iter().filter_map(|d| d.BreathsPerMin).collect();
+            HrvSummary {
+                average_rmssd_ms: mean(rmssd()),
+                min_rmssd_ms: rmssd().fold(f32::MAX, f32::min),
+                max_rmssd_ms: rmssd().fold(0.0, f32::max),
+                average_sdnn_ms: mean(hrv_rows.iter().map(|data| data.SDNN)),
+                average_pnn50: mean(hrv_rows.iter().map(|data| data.pNN50)),
+                average_stress_index: mean(hrv_rows.iter().map(|data| data.StressIndex)),
+                average_breaths_per_min: (!breaths.is_empty())
+                    .then(|| mean(breaths.iter().copied())),
+                artifacts: rows.iter().map(|row| row.data.Artifacts).sum(),
+            }
+        });
+
+        // 0 is logged when the monitor doesn't report its battery
+        let battery_levels = || rows.iter().map(|row| row.data.Battery).filter(|b| *b > 0);
+        let battery = battery_levels()
+            .next()
+            .zip(battery_levels().next_back())
+            .map(|(start_percent, end_percent)| {
+                let hours = duration.as_secs_f32() / 3600.0;
+                let drained = start_percent.saturating_sub(end_percent) as f32;
+                BatteryDrain {
+                    start_percent,
+                    end_percent,
+                    percent_per_hour: if hours > 0.0 { drained / hours } else { 0.0 },
+                }
+            });
+
+        Ok(Some(Self {
+            file,
+            start: first.timestamp.format(TIMESTAMP_FORMAT).to_string(),
+            duration_secs: duration.as_secs(),
+            recorded_secs: recorded.as_secs(),
+            samples: rows.len(),
+            min_bpm: bpms().min().unwrap_or_default(),
+            average_bpm: mean(bpms().map(|bpm| bpm as f32)),
+            max_bpm: bpms().max().unwrap_or_default(),
+            time_in_zones_secs: time_in_zones.iter().map(Duration::as_secs).collect(),
+            activities,
+            hrv,
+            battery,
+        }))
+    }
+    pub fn load(path: &Path, activity_names: &BTreeMap<u8, String>) -> Result<Self, AppError> {
+        let file = File::open(path)?;
+        Self::read(path.display().to_string(), file, activity_names)?
+            .ok_or_else(|| AppError::EmptySessionLog(path.to_owned()))
+    }
+}
+
+impl AnalysisReport {
+    pub fn build(mut sessions: Vec<SessionAnalysis>) -> Self {
+        // Timestamps are zero-padded, so they sort chronologically as strings
+        sessions.sort_by(|a, b| a.start.cmp(&b.start));
+        let mut trends = Vec::new();
+        if sessions.len() >= 2 {
+            let mut add_trend = |metric: &str, values: Vec<f32>| {
+                if let Some(trend) = trend(metric, &values) {
+                    trends.push(trend);
+                }
+            };
+            add_trend(
+                "Duration (min)",
+                sessions
+                    .iter()
+                    .map(|s| s.duration_secs as f32 / 60.0)
+                    .collect(),
+            );
+            add_trend(
+                "Average BPM",
+                sessions.iter().map(|s| s.average_bpm).collect(),
+            );
+            add_trend(
+                "Max BPM",
+                sessions.iter().map(|s| s.max_bpm as f32).collect(),
+            );
+            // Only over sessions that have HRV data
+            add_trend(
+                "Average RMSSD (ms)",
+                sessions
+                    .iter()
+                    .filter_map(|s| s.hrv.as_ref().map(|hrv| hrv.average_rmssd_ms))
+                    .collect(),
+            );
+        }
+        Self { sessions, trends }
+    }
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        for session in &self.sessions {
+            out += &format!("{}\n", session.file);
+            let mut rows = vec![
+                ("Started".to_string(), session.start.clone()),
+                (
+                    "Duration".into(),
+                    format!(
+                        "{} ({} recorded)",
+                        format_secs(session.duration_secs),
+                        format_secs(session.recorded_secs)
+                    ),
+                ),
+                (
+                    "BPM (min/avg/max)".into(),
+                    format!(
+                        "{} / {:.1} / {}",
+                        session.min_bpm, session.average_bpm, session.max_bpm
+                    ),
+                ),
+            ];
+            let recorded = session.recorded_secs.max(1) as f32;
+            for (zone, secs) in session.time_in_zones_secs.iter().enumerate() {
+                rows.push((
+                    format!("Zone {zone}"),
+                    format!(
+                        "{} ({:.0}%)",
+                        format_secs(*secs),
+                        *secs as f32 / recorded * 100.0
+                    ),
+                ));
+            }
+            for activity in &session.activities {
+                rows.push((
+                    format!("Activity \"{}\"", activity.name),
+                    format!(
+                        "{}, {:.1} avg BPM, {} max BPM",
+                        format_secs(activity.secs),
+                        activity.average_bpm,
+                        activity.max_bpm
+                    ),
+                ));
+            }
+            if let Some(hrv) = &session.hrv {
+                rows.push((
+                    "RMSSD (min/avg/max)".into(),
+                    format!(
+                        "{:.1} / {:.1} / {:.1} ms",
+                        hrv.min_rmssd_ms, hrv.average_rmssd_ms, hrv.max_rmssd_ms
+                    ),
+                ));
+                rows.push((
+                    "SDNN / pNN50 (avg)".into(),
+                    format!("{:.1} ms / {:.1}%", hrv.average_sdnn_ms, hrv.average_pnn50),
+                ));
+                rows.push((
+                    "Stress Index (avg)".into(),
+                    format!("{:.1}", hrv.average_stress_index),
+                ));
+                if let Some(breaths) = hrv.average_breaths_per_min {
+                    rows.push(("Breathing Rate (avg)".into(), format!("{breaths:.1}/min")));
+                }
+                rows.push(("Artifacts".into(), hrv.artifacts.to_string()));
+            }
+            if let Some(battery) = &session.battery {
+                rows.push((
+                    "Battery".into(),
+                    format!(
+                        "{}% → {}% ({:.1}%/h)",
+                        battery.start_percent, battery.end_percent, battery.percent_per_hour
+                    ),
+                ));
+            }
+            out += &aligned(&rows);
+            out += "\n";
+        }
+        if !self.trends.is_empty() {
+            out += &format!("Trends across {} sessions\n", self.sessions.len());
+            let rows: Vec<(String, String)> = self
+                .trends
+                .iter()
+                .map(|trend| {
+                    (
+                        trend.metric.clone(),
+                        format!(
+                            "{:.1} → {:.1} ({:+.1}, {:+.2} per session)",
+                            trend.first, trend.last, trend.change, trend.slope
+                        ),
+                    )
+                })
+                .collect();
+            out += &aligned(&rows);
+        }
+        out
+    }
+}
+
+/// Analyzes each log, failing on the first one that can't be read
+pub fn analyze_files<P: AsRef<Path>>(
+    paths: &[P],
+    activity_names: &BTreeMap<u8, String>,
+) -> Result<AnalysisReport, AppError> {
+    let sessions = paths
+        .iter()
+        .map(|path| SessionAnalysis::load(path.as_ref(), activity_names))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(AnalysisReport::build(sessions))
+}
+
+fn trend(metric: &str, values: &[f32]) -> Option<Trend> {
+    if values.len() < 2 {
+        return None;
+    }
+    let (first, last) = (values[0], values[values.len() - 1]);
+    let n = values.len() as f32;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = mean(values.iter().copied());
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (x, y) in values.iter().enumerate() {
+        let dx = x as f32 - mean_x;
+        covariance += dx * (y - mean_y);
+        variance += dx * dx;
+    }
+    Some(Trend {
+        metric: metric.into(),
+        first,
+        last,
+        change: last - first,
+        slope: covariance / variance,
+    })
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f32
+    }
+}
+
+fn aligned(rows: &[(String, String)]) -> String {
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or_default();
+    rows.iter()
+        .map(|(label, value)| format!("  {label:<width$}  {value}\n"))
+        .collect()
+}
+
+fn format_secs(secs: u64) -> String {
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+Timestamp,BPM,RR,Artifacts,Battery,TwitchUp,TwitchDown,Activity,Zone,RMSSD,SDNN,pNN50,StressIndex,LF,HF,LFHF,BreathsPerMin
+2024-05-01 10:00:00,80,750,0,90,0,0,0,1,0,0,0,0,0,0,0,
+2024-05-01 10:00:01,90,660,1,90,0,0,0,1,40,50,20,100,0,0,0,
+2024-05-01 10:00:02,120,500,0,90,0,0,1,2,20,30,10,200,0,0,0,15
+2024-05-01 10:00:30,130,460,0,0,0,0,1,3,30,40,15,150,0,0,0,
+2024-05-01 10:00:33,100,600,2,88,0,0,1,2,20,30,10,200,0,0,0,
+";
+
+    fn names() -> BTreeMap<u8, String> {
+        BTreeMap::from([(1, "Dancing".to_string())])
+    }
+
+    fn analyze(csv: &str) -> SessionAnalysis {
+        SessionAnalysis::read("test.csv".into(), csv.as_bytes(), &names())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn session_stats() {
+        let session = analyze(CSV);
+        assert_eq!(session.duration_secs, 33);
+        // The 28s gap is a disconnection
+        assert_eq!(session.recorded_secs, 5);
+        assert_eq!((session.min_bpm, session.max_bpm), (80, 130));
+        assert_eq!(session.average_bpm, 104.0);
+        assert_eq!(session.time_in_zones_secs, vec![0, 2, 0, 3]);
+
+        let battery = session.battery.unwrap();
+        assert_eq!((battery.start_percent, battery.end_percent), (90, 88));
+    }
+
+    #[test]
+    fn activity_breakdown() {
+        let session = analyze(CSV);
+        assert_eq!(
+            session.activities,
+            vec![
+                ActivityBreakdown {
+                    index: 0,
+                    name: "Activity 0".into(),
+                    secs: 2,
+                    average_bpm: 85.0,
+                    max_bpm: 90,
+                },
+                ActivityBreakdown {
+                    index: 1,
+                    name: "Dancing".into(),
+                    secs: 3,
+                    average_bpm: 350.0 / 3.0,
+                    max_bpm: 130,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hrv_skips_warmup() {
+        let hrv = analyze(CSV).hrv.unwrap();
+        assert_eq!(hrv.average_rmssd_ms, 27.5);
+        assert_eq!((hrv.min_rmssd_ms, hrv.max_rmssd_ms), (20.0, 40.0));
+        assert_eq!(hrv.average_breaths_per_min, Some(15.0));
+        assert_eq!(hrv.artifacts, 3);
+    }
+
+    #[test]
+    fn old_logs() {
+        let session = analyze("Timestamp,BPM,RR\n2024-05-01 10:00:00,80,750\n");
+        assert!(session.time_in_zones_secs.is_empty());
+        assert_eq!(session.hrv, None);
+        assert_eq!(session.battery, None);
+    }
+
+    #[test]
+    fn trends_oldest_first() {
+        let mut newer = analyze(CSV);
+        newer.start = "2024-05-03 10:00:00".into();
+        newer.average_bpm = 110.0;
+        let mut middle = analyze(CSV);
+        middle.start = "2024-05-02 10:00:00".into();
+        middle.average_bpm = 100.0;
+        let mut older = analyze(CSV);
+        older.average_bpm = 96.0;
+
+        let report = AnalysisReport::build(vec![newer, older, middle]);
+        let average = report
+            .trends
+            .iter()
+            .find(|trend| trend.metric == "Average BPM")
+            .unwrap();
+        assert_eq!(
+            (average.first, average.last, average.change),
+            (96.0, 110.0, 14.0)
+        );
+        assert_eq!(average.slope, 7.0);
+
+        let single = AnalysisReport::build(vec![analyze(CSV)]);
+        assert!(single.trends.is_empty());
+    }
+}
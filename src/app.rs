@@ -1,7 +1,6 @@
-use chrono::{DateTime, Local};
 use ratatui::widgets::TableState;
 use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -25,7 +24,11 @@ use crate::errors::AppError;
 use crate::heart_rate::ble::HEART_RATE_SERVICE_UUID;
 use crate::heart_rate::dummy::dummy_thread;
 use crate::heart_rate::websocket::websocket_thread;
-use crate::logging::prometheus_logging_thread;
+use crate::hrv::{hrv_thread, HrvMetrics};
+use crate::logging::{
+    influx_logging_thread, prometheus_logging_thread, sqlite_logging_thread, webhook_logging_thread,
+};
+use crate::session_logs::SessionBrowser;
 use crate::ui::table_state_scroll;
 use crate::updates::{UpdateHandle, UpdateReply};
 use crate::vrcx::VrcxStartup;
@@ -34,13 +37,17 @@ use crate::{
     heart_rate::ble::start_notification_thread,
     heart_rate::HeartRateStatus,
     logging::file_logging_thread,
+    mqtt::mqtt_thread,
     osc::osc_thread,
     scan::{bluetooth_event_thread, get_characteristics},
     settings::Settings,
-    structs::{Characteristic, DeviceInfo},
+    stats::{stats_thread, SessionStats},
+    structs::{Characteristic, DeviceInfo, HrSource},
+    summary::{SessionRecorder, SessionSummary},
     widgets::heart_rate_display::{
         CHART_BPM_MAX_ELEMENTS, CHART_BPM_VERT_MARGIN, CHART_RR_MAX_ELEMENTS, CHART_RR_VERT_MARGIN,
     },
+    zones::HrZones,
 };
 
 pub enum AppRx {
@@ -62,8 +69,14 @@ pub enum DeviceUpdate {
 #[derive(Debug, Clone)]
 pub enum AppUpdate {
     HeartRateStatus(HeartRateStatus),
-    ActivitySelected(u8),
+    /// Index and name
+    ActivitySelected(u8, String),
+    DeviceConnected(HrSource),
     WebsocketReady(std::net::SocketAddr),
+    SessionStats(SessionStats),
+    Hrv(HrvMetrics),
+    /// Session CSV the file logger just started writing to
+    SessionLogOpened(PathBuf),
     Error(ErrorPopup),
 }
 
@@ -73,6 +86,18 @@ impl From<HeartRateStatus> for AppUpdate {
     }
 }
 
+impl From<SessionStats> for AppUpdate {
+    fn from(stats: SessionStats) -> Self {
+        AppUpdate::SessionStats(stats)
+    }
+}
+
+impl From<HrvMetrics> for AppUpdate {
+    fn from(hrv: HrvMetrics) -> Self {
+        AppUpdate::Hrv(hrv)
+    }
+}
+
 impl From<ErrorPopup> for AppUpdate {
     fn from(error: ErrorPopup) -> Self {
         AppUpdate::Error(error)
@@ -90,6 +115,7 @@ pub enum AppView {
     BleDeviceSelection,
     WaitingForWebsocket,
     HeartRateView,
+    SessionBrowser,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
@@ -160,8 +186,15 @@ pub struct App {
     pub ble_thread_handle: Option<JoinHandle<()>>,
     pub hr_thread_handle: Option<JoinHandle<()>>,
     pub osc_thread_handle: Option<JoinHandle<()>>,
+    pub mqtt_thread_handle: Option<JoinHandle<()>>,
     pub file_logging_handle: Option<JoinHandle<()>>,
     pub prometheus_handle: Option<JoinHandle<()>>,
+    pub sqlite_handle: Option<JoinHandle<()>>,
+    pub influx_handle: Option<JoinHandle<()>>,
+    pub webhook_handle: Option<JoinHandle<()>>,
+    pub parquet_handle: Option<JoinHandle<()>>,
+    pub stats_thread_handle: Option<JoinHandle<()>>,
+    pub hrv_thread_handle: Option<JoinHandle<()>>,
     pub dummy_thread_handle: Option<JoinHandle<()>>,
     pub websocket_thread_handle: Option<JoinHandle<()>>,
     // Raw histories
@@ -170,8 +203,17 @@ pub struct App {
     // Used for the graphs in the heart rate view
     pub bpm_dataset: Vec<(f64, f64)>,
     pub rr_dataset: Vec<(f64, f64)>,
-    pub session_high_bpm: (f64, DateTime<Local>),
-    pub session_low_bpm: (f64, DateTime<Local>),
+    pub session_stats: SessionStats,
+    pub hrv: HrvMetrics,
+    pub show_hrv_spectrum: bool,
+    session_recorder: SessionRecorder,
+    // Latest CSV of this session, the summary is named after it
+    session_log_path: Option<PathBuf>,
+    // Shown in place of everything else once the app is closing
+    pub session_summary: Option<SessionSummary>,
+    pub session_browser: Option<SessionBrowser>,
+    // Built during init(), since bad zone settings need to show an error
+    pub hr_zones: Option<HrZones>,
     // Usually same as session but can have a margin applied
     pub chart_high_bpm: f64,
     pub chart_mid_bpm: f64,
@@ -229,6 +271,11 @@ impl App {
                 Settings::default()
             }
         };
+        // Disconnections are only hidden when they'd be sent over OSC
+        let session_recorder = SessionRecorder::new(
+            settings.osc.enabled && settings.osc.hide_disconnections,
+            Duration::from_secs(settings.osc.max_hide_disconnection_sec as u64),
+        );
         Self {
             ble_tx,
             ble_rx,
@@ -259,12 +306,25 @@ impl App {
             ble_thread_handle: None,
             hr_thread_handle: None,
             osc_thread_handle: None,
+            mqtt_thread_handle: None,
             file_logging_handle: None,
             prometheus_handle: None,
+            sqlite_handle: None,
+            influx_handle: None,
+            webhook_handle: None,
+            parquet_handle: None,
+            stats_thread_handle: None,
+            hrv_thread_handle: None,
             dummy_thread_handle: None,
             websocket_thread_handle: None,
-            session_high_bpm: (0.0, Local::now()),
-            session_low_bpm: (0.0, Local::now()),
+            session_stats: SessionStats::default(),
+            hrv: HrvMetrics::default(),
+            show_hrv_spectrum: false,
+            session_recorder,
+            session_log_path: None,
+            session_summary: None,
+            session_browser: None,
+            hr_zones: None,
             chart_high_bpm: 0.0,
             chart_low_bpm: 0.0,
             chart_mid_bpm: 0.0,
@@ -297,10 +357,24 @@ impl App {
         };
         // self.handle_error_update(ErrorPopup::Fatal(format!("{:?}", self.activities)));
         // return;
+        let zones = match HrZones::build(&self.settings.zones, self.settings.profile.age) {
+            Ok(zones) => zones,
+            Err(e) => {
+                self.handle_error_update(ErrorPopup::detailed("Invalid HR Zone settings!", e));
+                return;
+            }
+        };
+        self.hr_zones = Some(zones.clone());
+        self.session_recorder.record_activity(activity.unwrap_or(0));
+        self.start_stats_thread(zones.clone());
+        self.start_hrv_thread();
         if self.settings.osc.enabled {
-            self.start_osc_thread(activity);
+            self.start_osc_thread(activity, zones.clone());
         }
-        self.start_logging_threads(activity.unwrap_or(0));
+        if self.settings.mqtt.enabled {
+            self.start_mqtt_thread(activity.unwrap_or(0));
+        }
+        self.start_logging_threads(activity.unwrap_or(0), zones);
         // HR source selection
         if let Some(subcommands) = arg_config.subcommands.as_ref() {
             match subcommands {
@@ -310,6 +384,8 @@ impl App {
                     self.start_dummy_thread(dummy.speed, dummy.vhs);
                 }
                 SubCommands::WebSocket(ws) => self.start_websocket_thread(ws.port),
+                // Run on their own, without building an App
+                SubCommands::Analyze(_) | SubCommands::Export(_) => {}
             }
             return;
         }
@@ -377,7 +453,7 @@ impl App {
                     AppUpdate::HeartRateStatus(data) => {
                         if data.heart_rate_bpm > 0 || !data.rr_intervals.is_empty() {
                             // Assume we have proper data now
-                            self.view = AppView::HeartRateView;
+                            self.set_view(AppView::HeartRateView);
                             if self.sub_state == SubState::ConnectingForHeartRate {
                                 self.sub_state = SubState::None;
                             }
@@ -387,13 +463,27 @@ impl App {
                             }
                             self.append_to_history(&data);
                         }
+                        self.session_recorder.record_status(&data);
                         self.heart_rate_status = data;
                     }
                     AppUpdate::Error(error) => self.handle_error_update(error),
                     AppUpdate::WebsocketReady(local_addr) => {
                         self.websocket_url = Some(local_addr.to_string());
+                        self.broadcast_source(HrSource {
+                            kind: "websocket",
+                            name: "WebSocket".into(),
+                            address: local_addr.to_string(),
+                        });
+                    }
+                    AppUpdate::DeviceConnected(_) => {}
+                    AppUpdate::SessionStats(stats) => self.session_stats = stats,
+                    AppUpdate::SessionLogOpened(path) => self.session_log_path = Some(path),
+                    AppUpdate::Hrv(hrv) => {
+                        self.session_recorder.record_hrv(&hrv);
+                        self.hrv = hrv;
                     }
-                    AppUpdate::ActivitySelected(_) => {
+                    AppUpdate::ActivitySelected(index, _) => {
+                        self.session_recorder.record_activity(index);
                         if let Err(err) = self.activities.save().await {
                             self.handle_error_update(ErrorPopup::detailed(
                                 "Failed to save activities!",
@@ -588,15 +678,15 @@ impl App {
         // Not leaving as Duration as it's being used to check an abs difference
         let rr_twitch_threshold =
             Duration::from_millis(self.settings.osc.twitch_rr_threshold_ms as u64).as_secs_f32();
-        let rr_ignore_after_empty = self.settings.ble.rr_ignore_after_empty as usize;
+        let artifact_settings = self.settings.artifacts.clone();
         debug!("Spawning notification thread, AppView: {:?}", self.view);
         self.hr_thread_handle = Some(tokio::spawn(async move {
             start_notification_thread(
                 hr_tx_clone,
                 restart_tx_clone,
                 device,
-                rr_ignore_after_empty,
                 rr_twitch_threshold,
+                artifact_settings,
                 ble_packet_timeout,
                 shutdown_requested_clone,
             )
@@ -615,7 +705,7 @@ impl App {
             || device.address == self.settings.ble.saved_address
     }
 
-    pub fn start_osc_thread(&mut self, initial_activity: Option<u8>) {
+    pub fn start_osc_thread(&mut self, initial_activity: Option<u8>, zones: HrZones) {
         let osc_settings = self.settings.osc.clone();
         let broadcast_rx = self.broadcast_tx.subscribe();
         let broadcast_tx = self.broadcast_tx.clone();
@@ -628,21 +718,97 @@ impl App {
                 broadcast_tx,
                 initial_activity,
                 osc_settings,
+                zones,
+                shutdown_requested_clone,
+            )
+            .await
+        }));
+    }
+
+    pub fn start_mqtt_thread(&mut self, initial_activity: u8) {
+        let mqtt_settings = self.settings.mqtt.clone();
+        let initial_activity_name = self
+            .activities
+            .names()
+            .get(&initial_activity)
+            .cloned()
+            .unwrap_or_default();
+        let broadcast_rx = self.broadcast_tx.subscribe();
+        let broadcast_tx = self.broadcast_tx.clone();
+        let shutdown_requested_clone = self.cancel_actors.clone();
+
+        debug!("Spawning MQTT thread");
+        self.mqtt_thread_handle = Some(tokio::spawn(async move {
+            mqtt_thread(
+                broadcast_rx,
+                broadcast_tx,
+                initial_activity_name,
+                mqtt_settings,
+                shutdown_requested_clone,
+            )
+            .await
+        }));
+    }
+
+    pub fn start_stats_thread(&mut self, zones: HrZones) {
+        let stats_settings = self.settings.stats.clone();
+        let profile = self.settings.profile.clone();
+        let broadcast_rx = self.broadcast_tx.subscribe();
+        let broadcast_tx = self.broadcast_tx.clone();
+        let shutdown_requested_clone = self.cancel_actors.clone();
+
+        debug!("Spawning Stats thread");
+        self.stats_thread_handle = Some(tokio::spawn(async move {
+            stats_thread(
+                broadcast_rx,
+                broadcast_tx,
+                stats_settings,
+                profile,
+                zones,
                 shutdown_requested_clone,
             )
             .await
         }));
     }
 
-    pub fn start_logging_threads(&mut self, initial_activity: u8) {
+    pub fn start_hrv_thread(&mut self) {
+        let hrv_settings = self.settings.hrv.clone();
+        let broadcast_rx = self.broadcast_tx.subscribe();
+        let broadcast_tx = self.broadcast_tx.clone();
+        let shutdown_requested_clone = self.cancel_actors.clone();
+
+        debug!("Spawning HRV thread");
+        self.hrv_thread_handle = Some(tokio::spawn(async move {
+            hrv_thread(
+                broadcast_rx,
+                broadcast_tx,
+                hrv_settings,
+                shutdown_requested_clone,
+            )
+            .await
+        }));
+    }
+
+    pub fn start_logging_threads(&mut self, initial_activity: u8, zones: HrZones) {
+        let initial_activity_name = self
+            .activities
+            .names()
+            .get(&initial_activity)
+            .cloned()
+            .unwrap_or_default();
         let file_logging_enabled = self.settings.misc.log_sessions_to_csv
             || self.settings.misc.write_bpm_to_file
-            || self.settings.misc.write_rr_to_file;
+            || self.settings.misc.write_rr_to_file
+            || self.settings.misc.log_rr_series
+            || self.settings.csv.to_stdout;
         if file_logging_enabled {
             let misc_settings_clone = self.settings.misc.clone();
+            let csv_settings_clone = self.settings.csv.clone();
+            let initial_activity_name = initial_activity_name.clone();
             let shutdown_requested_clone = self.cancel_actors.clone();
             let broadcast_rx = self.broadcast_tx.subscribe();
             let broadcast_tx = self.broadcast_tx.clone();
+            let zones = zones.clone();
 
             debug!("Spawning Data Logging thread");
             self.file_logging_handle = Some(tokio::spawn(async move {
@@ -650,15 +816,78 @@ impl App {
                     broadcast_rx,
                     broadcast_tx,
                     initial_activity,
+                    initial_activity_name,
                     misc_settings_clone,
+                    csv_settings_clone,
+                    zones,
+                    shutdown_requested_clone,
+                )
+                .await
+            }));
+        }
+
+        if self.settings.parquet.enabled {
+            #[cfg(feature = "parquet")]
+            {
+                let parquet_settings_clone = self.settings.parquet.clone();
+                let misc_settings_clone = self.settings.misc.clone();
+                let csv_settings_clone = self.settings.csv.clone();
+                let initial_activity_name = initial_activity_name.clone();
+                let shutdown_requested_clone = self.cancel_actors.clone();
+                let broadcast_rx = self.broadcast_tx.subscribe();
+                let broadcast_tx = self.broadcast_tx.clone();
+                let zones = zones.clone();
+
+                debug!("Spawning Parquet thread");
+                self.parquet_handle = Some(tokio::spawn(async move {
+                    crate::logging::parquet_logging_thread(
+                        broadcast_rx,
+                        broadcast_tx,
+                        initial_activity,
+                        initial_activity_name,
+                        parquet_settings_clone,
+                        misc_settings_clone,
+                        csv_settings_clone,
+                        zones,
+                        shutdown_requested_clone,
+                    )
+                    .await
+                }));
+            }
+            // Not worth stopping the other loggers (or a headless run) over
+            #[cfg(not(feature = "parquet"))]
+            tracing::warn!(
+                "Parquet logging needs iron-heart to be built with `--features parquet`, skipping it"
+            );
+        }
+
+        if self.settings.sqlite.enabled {
+            let sqlite_settings_clone = self.settings.sqlite.clone();
+            let initial_activity_name = initial_activity_name.clone();
+            let shutdown_requested_clone = self.cancel_actors.clone();
+            let broadcast_rx = self.broadcast_tx.subscribe();
+            let broadcast_tx = self.broadcast_tx.clone();
+            let zones = zones.clone();
+
+            debug!("Spawning SQLite thread");
+            self.sqlite_handle = Some(tokio::spawn(async move {
+                sqlite_logging_thread(
+                    broadcast_rx,
+                    broadcast_tx,
+                    initial_activity,
+                    initial_activity_name,
+                    sqlite_settings_clone,
+                    zones,
                     shutdown_requested_clone,
                 )
                 .await
             }));
         }
 
-        if self.settings.prometheus.enabled {
+        if self.settings.prometheus.enabled || self.settings.prometheus.scrape_enabled {
             let prometheus_settings_clone = self.settings.prometheus.clone();
+            let initial_activity_name = initial_activity_name.clone();
+            let zones = zones.clone();
             let shutdown_requested_clone = self.cancel_actors.clone();
             let broadcast_rx = self.broadcast_tx.subscribe();
             let broadcast_tx = self.broadcast_tx.clone();
@@ -669,7 +898,53 @@ impl App {
                     broadcast_rx,
                     broadcast_tx,
                     initial_activity,
+                    initial_activity_name,
                     prometheus_settings_clone,
+                    zones,
+                    shutdown_requested_clone,
+                )
+                .await
+            }));
+        }
+
+        if self.settings.influx.enabled {
+            let influx_settings_clone = self.settings.influx.clone();
+            let initial_activity_name = initial_activity_name.clone();
+            let shutdown_requested_clone = self.cancel_actors.clone();
+            let broadcast_rx = self.broadcast_tx.subscribe();
+            let broadcast_tx = self.broadcast_tx.clone();
+            let zones = zones.clone();
+
+            debug!("Spawning InfluxDB thread");
+            self.influx_handle = Some(tokio::spawn(async move {
+                influx_logging_thread(
+                    broadcast_rx,
+                    broadcast_tx,
+                    initial_activity,
+                    initial_activity_name,
+                    influx_settings_clone,
+                    zones,
+                    shutdown_requested_clone,
+                )
+                .await
+            }));
+        }
+
+        if self.settings.webhook.enabled {
+            let webhook_settings_clone = self.settings.webhook.clone();
+            let shutdown_requested_clone = self.cancel_actors.clone();
+            let broadcast_rx = self.broadcast_tx.subscribe();
+            let broadcast_tx = self.broadcast_tx.clone();
+
+            debug!("Spawning Webhook thread");
+            self.webhook_handle = Some(tokio::spawn(async move {
+                webhook_logging_thread(
+                    broadcast_rx,
+                    broadcast_tx,
+                    initial_activity,
+                    initial_activity_name,
+                    webhook_settings_clone,
+                    zones,
                     shutdown_requested_clone,
                 )
                 .await
@@ -681,6 +956,12 @@ impl App {
         let broadcast_tx = self.broadcast_tx.clone();
         let shutdown_requested_clone = self.cancel_actors.clone();
         let dummy_settings_clone = self.settings.dummy.clone();
+        let artifact_settings = self.settings.artifacts.clone();
+        self.broadcast_source(HrSource {
+            kind: "dummy",
+            name: "Dummy".into(),
+            address: String::new(),
+        });
         debug!("Spawning Dummy thread");
         self.view = AppView::HeartRateView;
         self.chart_high_rr = self.settings.tui.chart_rr_max;
@@ -688,6 +969,7 @@ impl App {
             dummy_thread(
                 broadcast_tx,
                 dummy_settings_clone,
+                artifact_settings,
                 seconds_override,
                 vhs_prefill,
                 shutdown_requested_clone,
@@ -709,6 +991,7 @@ impl App {
         // Not leaving as Duration as it's being used to check an abs difference
         let rr_twitch_threshold =
             Duration::from_millis(self.settings.osc.twitch_rr_threshold_ms as u64).as_secs_f32();
+        let artifact_settings = self.settings.artifacts.clone();
         debug!("Spawning Websocket thread");
         self.view = AppView::WaitingForWebsocket;
         self.websocket_thread_handle = Some(tokio::spawn(async move {
@@ -717,6 +1000,7 @@ impl App {
                 websocket_settings_clone,
                 port_override,
                 rr_twitch_threshold,
+                artifact_settings,
                 ws_packet_timeout,
                 shutdown_requested_clone,
             )
@@ -757,6 +1041,13 @@ impl App {
             }
         }
 
+        if let Some(handle) = self.mqtt_thread_handle.take() {
+            debug!("Joining MQTT thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join MQTT thread: {:?}", err);
+            }
+        }
+
         if let Some(handle) = self.file_logging_handle.take() {
             debug!("Joining File Logging thread");
             if let Err(err) = timeout(duration, handle).await {
@@ -771,6 +1062,48 @@ impl App {
             }
         }
 
+        if let Some(handle) = self.parquet_handle.take() {
+            debug!("Joining Parquet thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join Parquet thread: {:?}", err);
+            }
+        }
+
+        if let Some(handle) = self.sqlite_handle.take() {
+            debug!("Joining SQLite thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join SQLite thread: {:?}", err);
+            }
+        }
+
+        if let Some(handle) = self.influx_handle.take() {
+            debug!("Joining InfluxDB thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join InfluxDB thread: {:?}", err);
+            }
+        }
+
+        if let Some(handle) = self.webhook_handle.take() {
+            debug!("Joining Webhook thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join Webhook thread: {:?}", err);
+            }
+        }
+
+        if let Some(handle) = self.stats_thread_handle.take() {
+            debug!("Joining Stats thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join Stats thread: {:?}", err);
+            }
+        }
+
+        if let Some(handle) = self.hrv_thread_handle.take() {
+            debug!("Joining HRV thread");
+            if let Err(err) = timeout(duration, handle).await {
+                error!("Failed to join HRV thread: {:?}", err);
+            }
+        }
+
         if let Some(handle) = self.dummy_thread_handle.take() {
             debug!("Joining Dummy thread");
             if let Err(err) = timeout(duration, handle).await {
@@ -779,6 +1112,30 @@ impl App {
         }
     }
 
+    /// Builds the session summary after the threads have stopped, writing it next to the CSV logs
+    ///
+    /// Returns true if there was a session to summarize
+    pub async fn finish_session(&mut self) -> bool {
+        let Some(summary) = self.session_recorder.finish(
+            &self.session_stats,
+            self.activities.names(),
+            chrono::Local::now(),
+        ) else {
+            return false;
+        };
+        // Goes next to the session's latest CSV, so there's nowhere to put it without one
+        let csv_stem = self.session_log_path.as_deref().and_then(Path::file_stem);
+        if let (true, Some(stem)) = (self.settings.misc.write_session_summary, csv_stem) {
+            let folder = PathBuf::from(&self.settings.misc.log_sessions_csv_path);
+            match summary.write_files(&folder, &stem.to_string_lossy()).await {
+                Ok(path) => info!("Session summary written to {}", path.display()),
+                Err(e) => error!("Failed to write session summary: {e}"),
+            }
+        }
+        self.session_summary = Some(summary);
+        true
+    }
+
     /// Wrapper for save_settings that handles errors and returns just a success bool
     pub fn try_save_settings(&mut self) -> bool {
         if let Err(e) = self.save_settings() {
@@ -849,8 +1206,9 @@ impl App {
         self.heart_rate_history.is_empty() && self.rr_history.is_empty()
     }
 
-    fn update_session_stats(&mut self, new_bpm: f64, new_rr: Option<&Duration>) {
-        if self.session_low_bpm.0 == 0.0 || self.session_high_bpm.0 == 0.0 {
+    // Session high/low/etc come from the stats thread, this just keeps the charts in bounds
+    fn update_chart_bounds(&mut self, new_bpm: f64, new_rr: Option<&Duration>) {
+        if self.chart_high_bpm == 0.0 {
             let margin = if self.ignore_margins_for_vhs {
                 0.0
             } else {
@@ -858,12 +1216,6 @@ impl App {
             };
             self.chart_low_bpm = new_bpm - margin;
             self.chart_high_bpm = new_bpm + margin;
-            self.session_low_bpm = (new_bpm, Local::now());
-            self.session_high_bpm = (new_bpm, Local::now());
-        } else if new_bpm > self.session_high_bpm.0 {
-            self.session_high_bpm = (new_bpm, Local::now());
-        } else if new_bpm < self.session_low_bpm.0 {
-            self.session_low_bpm = (new_bpm, Local::now());
         }
         self.chart_high_bpm = self.chart_high_bpm.max(new_bpm);
         self.chart_low_bpm = self.chart_low_bpm.min(new_bpm);
@@ -938,13 +1290,13 @@ impl App {
         let bpm = hr_data.heart_rate_bpm as f64;
         let rr_max = self.settings.tui.chart_rr_max;
         if bpm > 0.0 {
-            self.update_session_stats(bpm, hr_data.rr_intervals.last());
+            self.update_chart_bounds(bpm, hr_data.latest_clean_rr().as_ref());
 
             self.heart_rate_history.push_back(bpm);
             if self.heart_rate_history.len() > CHART_BPM_MAX_ELEMENTS {
                 self.heart_rate_history.pop_front();
             }
-            for rr in &hr_data.rr_intervals {
+            for rr in hr_data.clean_rr_intervals() {
                 if rr.as_secs_f64() > rr_max {
                     continue;
                 }
@@ -958,6 +1310,15 @@ impl App {
         }
     }
 
+    /// Lets loggers know what the heart rate's coming from
+    fn broadcast_source(&self, source: HrSource) {
+        broadcast!(
+            self.broadcast_tx,
+            AppUpdate::DeviceConnected(source),
+            "Failed to send device update!"
+        );
+    }
+
     pub fn handle_error_update(&mut self, error: ErrorPopup) {
         // Never override a fatal error popup
         match self.error_message {
@@ -984,6 +1345,7 @@ impl App {
     /// Terminal interval tick
     pub fn term_tick(&mut self) {
         (self.frame_count, _) = self.frame_count.overflowing_add(1);
+        self.poll_session_browser();
     }
 
     pub fn scroll_up(&mut self) {
@@ -1016,6 +1378,9 @@ impl App {
             AppView::BleDeviceSelection if self.is_idle_on_ble_selection() => {
                 table_state_scroll(true, &mut self.table_state, self.discovered_devices.len());
             }
+            AppView::SessionBrowser if self.sub_state == SubState::None => {
+                self.session_browser_scroll(true);
+            }
             _ => {}
         }
     }
@@ -1049,6 +1414,9 @@ impl App {
             AppView::BleDeviceSelection if self.is_idle_on_ble_selection() => {
                 table_state_scroll(false, &mut self.table_state, self.discovered_devices.len());
             }
+            AppView::SessionBrowser if self.sub_state == SubState::None => {
+                self.session_browser_scroll(false);
+            }
             _ => {}
         }
     }
@@ -1057,6 +1425,9 @@ impl App {
             SubState::ActivitySelection | SubState::ActivityCreation => {
                 self.activities_esc_pressed();
             }
+            SubState::None if self.view == AppView::SessionBrowser => {
+                self.close_session_browser();
+            }
             _ => {}
         }
     }
@@ -1222,7 +1593,7 @@ impl App {
             }
             DeviceUpdate::Error(error) => {
                 error!("BLE Thread Error: {:?}", error.clone());
-                if self.view == AppView::HeartRateView && self.datasets_empty() {
+                if self.underlying_view() == AppView::HeartRateView && self.datasets_empty() {
                     // Ignoring the intermittent ones when we're in the inbetween state
                 } else {
                     // Don't override a fatal error
@@ -1230,7 +1601,7 @@ impl App {
                         self.error_message = Some(error);
                     }
                 }
-                if self.view == AppView::HeartRateView
+                if self.underlying_view() == AppView::HeartRateView
                     || self.sub_state == SubState::ConnectingForHeartRate
                 {
                     broadcast!(
@@ -1246,14 +1617,22 @@ impl App {
                     self.sub_state = SubState::CharacteristicView;
                 } else {
                     // If it wasn't for characteristics, it's probably for HR
-                    self.view = AppView::HeartRateView;
+                    self.set_view(AppView::HeartRateView);
                 }
 
-                if self.view == AppView::HeartRateView {
+                if self.underlying_view() == AppView::HeartRateView {
                     if id == self.get_selected_device().unwrap().id {
                         info!("Connected to device {:?}, stopping BLE scan", id);
                         self.ble_scan_paused.store(true, Ordering::SeqCst);
                     }
+                    if let Some(device) = self.get_selected_device() {
+                        let source = HrSource {
+                            kind: "ble",
+                            name: device.name.clone(),
+                            address: device.address.clone(),
+                        };
+                        self.broadcast_source(source);
+                    }
                     self.try_save_device(None);
                 }
             }
@@ -1261,7 +1640,8 @@ impl App {
                 self.error_message = Some(ErrorPopup::Intermittent(
                     "Disconnected from device!".to_string(),
                 ));
-                if (self.view == AppView::HeartRateView || self.is_idle_on_ble_selection())
+                if (self.underlying_view() == AppView::HeartRateView
+                    || self.is_idle_on_ble_selection())
                     && disconnected_id == self.get_selected_device().unwrap().id
                 {
                     info!(
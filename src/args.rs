@@ -1,4 +1,6 @@
 use argh::FromArgs;
+
+use crate::settings::ExportFormat;
 use std::path::PathBuf;
 
 #[derive(FromArgs, Debug)]
@@ -16,6 +18,9 @@ pub struct TopLevelCmd {
     /// skip vrcx and auto-update prompts
     #[argh(switch)]
     pub skip_prompts: bool,
+    /// write the session CSV to stdout instead of showing the TUI, for piping
+    #[argh(switch)]
+    pub csv_stdout: bool,
     #[argh(subcommand)]
     pub subcommands: Option<SubCommands>,
 }
@@ -26,6 +31,8 @@ pub enum SubCommands {
     Ble(BleCmd),
     WebSocket(WebSocketCmd),
     Dummy(DummyCmd),
+    Analyze(AnalyzeCmd),
+    Export(ExportCmd),
 }
 
 /// connect to a BLE device with the HR Measure characteristic
@@ -53,3 +60,33 @@ pub struct DummyCmd {
     #[argh(switch)]
     pub vhs: bool,
 }
+
+/// print stats, zones, HRV and trends from session CSV logs, without starting the app
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "analyze")]
+pub struct AnalyzeCmd {
+    /// session CSV logs to analyze
+    #[argh(positional)]
+    pub files: Vec<PathBuf>,
+    /// output JSON instead of a table
+    #[argh(switch, short = 'j')]
+    pub json: bool,
+    /// activities file used to name activities, otherwise uses the app's activities.toml if present
+    #[argh(option, short = 'a')]
+    pub activities: Option<PathBuf>,
+}
+
+/// convert session CSV logs to FIT, TCX or GPX files for fitness platforms
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "export")]
+pub struct ExportCmd {
+    /// session CSV logs to convert
+    #[argh(positional)]
+    pub files: Vec<PathBuf>,
+    /// format to write (fit, tcx or gpx), can be given more than once
+    #[argh(option, short = 'f')]
+    pub format: Vec<ExportFormat>,
+    /// folder to write to, otherwise next to each log
+    #[argh(option, short = 'o')]
+    pub out_dir: Option<PathBuf>,
+}
@@ -18,6 +18,11 @@ pub enum AppError {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("Failed to serve Prometheus metrics on \"{addr}\": {source}")]
+    MetricsListen {
+        addr: String,
+        source: std::io::Error,
+    },
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Error parsing IP Address: {0}")]
@@ -34,8 +39,26 @@ pub enum AppError {
     TomlSer(#[from] toml::ser::Error),
     #[error("TOML Read Error: {0}")]
     TomlDe(#[from] toml::de::Error),
+    #[error("JSON Error: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("CSV Error: {0}")]
     Csv(#[from] csv_async::Error),
+    #[error("CSV Read Error: {0}")]
+    CsvRead(#[from] csv::Error),
+    #[error("Invalid [csv] settings: {0}")]
+    CsvSchema(String),
+    #[error("SQLite Error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("\"{path}\" was made by a newer version of the app (schema v{version})")]
+    SqliteNewerSchema { path: PathBuf, version: u32 },
+    #[cfg(feature = "parquet")]
+    #[error("Parquet Error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "parquet")]
+    #[error("Arrow Error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[error("Timestamp Parse Error: {0}")]
+    TimestampParse(#[from] chrono::ParseError),
     #[error("Parse Int Error: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
     #[error("Updater Error: {0}")]
@@ -46,6 +69,8 @@ pub enum AppError {
     Reqwest(#[from] reqwest::Error),
     #[error("Prometheus Error: {0}")]
     Prometheus(#[from] prometheus::Error),
+    #[error("Snappy Error: {0}")]
+    Snappy(#[from] snap::Error),
     #[error("ASCII characters (32-127) only")]
     InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
     #[error("ASCII characters (32-127) only")]
@@ -57,6 +82,18 @@ pub enum AppError {
     OscPrefix(String),
     #[error("Invalid OSC Address: \"{0}\" - \"{1}\"")]
     OscAddress(String, String),
+    #[error("Invalid OSC Mapping: \"{0}\" - {1}")]
+    OscMapping(String, String),
+    #[error("Invalid HR Zones: {0}")]
+    Zones(String),
+    #[error("No heart rate data in \"{0}\"")]
+    EmptySessionLog(PathBuf),
+    #[error("No session logs given to analyze")]
+    NothingToAnalyze,
+    #[error("No session logs given to export")]
+    NothingToExport,
+    #[error("No export format given, use --format fit, tcx or gpx")]
+    NoExportFormat,
     #[error("Failed to get event")]
     NoEvent,
     #[error("Bad HTTP Status: \"{0}\"")]
@@ -69,6 +106,12 @@ pub enum AppError {
     NotPortable,
     #[error("Tried to get non-existant Prometheus metric")]
     MissingMetric,
+    #[error("[influx] measurement can't be empty")]
+    EmptyInfluxMeasurement,
+    #[error("Invalid [webhook] body: {0}")]
+    WebhookBody(String),
+    #[error("Invalid [mqtt] settings: {0}")]
+    MqttSettings(String),
     #[error("Header missing separating \":\"")]
     MissingDelimiter,
     #[error("Empty IP Address")]
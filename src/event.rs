@@ -93,6 +93,11 @@ impl EventHandler {
     /// During shutdown, close and drain the receiver so no stray events make it on the way out
     pub fn close(&mut self) {
         self.receiver.close();
+        self.discard_pending();
+    }
+
+    /// Drops any events that are waiting to be received
+    pub fn discard_pending(&mut self) {
         while self.receiver.try_recv().is_ok() {
             continue;
         }
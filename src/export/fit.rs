@@ -0,0 +1,415 @@
+//! Just enough of the Garmin FIT protocol to write a heart rate only activity.
+//!
+//! Field numbers and values are from the FIT SDK's Profile.xlsx.
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::time::Duration;
+
+use super::Workout;
+
+const PROTOCOL_VERSION: u8 = 0x20;
+const PROFILE_VERSION: u16 = 2132;
+const HEADER_SIZE: u8 = 14;
+// Seconds between the Unix epoch and FIT's, 1989-12-31T00:00:00Z
+const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+const DEFINITION_HEADER: u8 = 0x40;
+const LITTLE_ENDIAN: u8 = 0;
+
+// Base types
+const ENUM: u8 = 0x00;
+const UINT8: u8 = 0x02;
+const UINT16: u8 = 0x84;
+const UINT32: u8 = 0x86;
+
+// Global message numbers
+const FILE_ID: u16 = 0;
+const SESSION: u16 = 18;
+const LAP: u16 = 19;
+const RECORD: u16 = 20;
+const EVENT: u16 = 21;
+const ACTIVITY: u16 = 34;
+
+// Field numbers shared by most messages
+const TIMESTAMP: u8 = 253;
+const EVENT_FIELD: u8 = 0;
+const EVENT_TYPE_FIELD: u8 = 1;
+
+// `event` and `event_type` values
+const EVENT_TIMER: u32 = 0;
+const EVENT_SESSION: u32 = 8;
+const EVENT_LAP: u32 = 9;
+const EVENT_ACTIVITY: u32 = 26;
+const EVENT_TYPE_START: u32 = 0;
+const EVENT_TYPE_STOP: u32 = 1;
+const EVENT_TYPE_STOP_ALL: u32 = 4;
+
+const FILE_TYPE_ACTIVITY: u32 = 4;
+const MANUFACTURER_DEVELOPMENT: u32 = 255;
+const SPORT_GENERIC: u32 = 0;
+
+// 255 is the "invalid" value for uint8 fields
+const MAX_HEART_RATE: u16 = 254;
+
+// Field number, base type, and value
+type Field = (u8, u8, u32);
+// Global message number, and its fields' numbers and base types
+type Definition = (u16, Vec<(u8, u8)>);
+
+#[derive(Default)]
+struct FitWriter {
+    data: Vec<u8>,
+    // What each local message type is currently defined as
+    definitions: [Option<Definition>; 16],
+}
+
+impl FitWriter {
+    /// Writes a data message, preceded by its definition if the local type
+    /// wasn't already defined with the same layout
+    fn message(&mut self, local_type: u8, global: u16, fields: &[Field]) {
+        let layout: Vec<(u8, u8)> = fields
+            .iter()
+            .map(|(number, base_type, _)| (*number, *base_type))
+            .collect();
+        let definition = &mut self.definitions[local_type as usize];
+        if definition.as_ref() != Some(&(global, layout.clone())) {
+            self.data.push(DEFINITION_HEADER | local_type);
+            self.data.push(0);
+            self.data.push(LITTLE_ENDIAN);
+            self.data.extend_from_slice(&global.to_le_bytes());
+            self.data.push(fields.len() as u8);
+            for (number, base_type) in &layout {
+                self.data
+                    .extend_from_slice(&[*number, base_type_size(*base_type), *base_type]);
+            }
+            *definition = Some((global, layout));
+        }
+        self.data.push(local_type);
+        for (_, base_type, value) in fields {
+            let bytes = value.to_le_bytes();
+            self.data
+                .extend_from_slice(&bytes[..base_type_size(*base_type) as usize]);
+        }
+    }
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(HEADER_SIZE as usize + self.data.len() + 2);
+        file.push(HEADER_SIZE);
+        file.push(PROTOCOL_VERSION);
+        file.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        file.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b".FIT");
+        let header_crc = crc(&file);
+        file.extend_from_slice(&header_crc.to_le_bytes());
+        file.extend_from_slice(&self.data);
+        let file_crc = crc(&file);
+        file.extend_from_slice(&file_crc.to_le_bytes());
+        file
+    }
+}
+
+pub fn encode(workout: &Workout) -> Vec<u8> {
+    let local_offset = Local
+        .from_utc_datetime(&workout.end.naive_utc())
+        .offset()
+        .local_minus_utc();
+    encode_at(workout, local_offset)
+}
+
+/// `local_offset` is the UTC offset in seconds the activity ended at
+fn encode_at(workout: &Workout, local_offset: i32) -> Vec<u8> {
+    let start = fit_time(&workout.start);
+    let end = fit_time(&workout.end);
+    let elapsed = millis(workout.elapsed());
+    let timer = millis(workout.timer);
+    let average_bpm = workout.average_bpm.min(MAX_HEART_RATE) as u32;
+    let max_bpm = workout.max_bpm.min(MAX_HEART_RATE) as u32;
+
+    let mut writer = FitWriter::default();
+    writer.message(
+        0,
+        FILE_ID,
+        &[
+            (0, ENUM, FILE_TYPE_ACTIVITY),
+            (1, UINT16, MANUFACTURER_DEVELOPMENT),
+            (2, UINT16, 0),
+            (4, UINT32, start),
+        ],
+    );
+    let timer_event = |time, event_type| {
+        [
+            (TIMESTAMP, UINT32, time),
+            (EVENT_FIELD, ENUM, EVENT_TIMER),
+            (EVENT_TYPE_FIELD, ENUM, event_type),
+        ]
+    };
+    writer.message(1, EVENT, &timer_event(start, EVENT_TYPE_START));
+    for sample in &workout.samples {
+        writer.message(
+            2,
+            RECORD,
+            &[
+                (TIMESTAMP, UINT32, fit_time(&sample.time)),
+                (3, UINT8, sample.bpm.min(MAX_HEART_RATE) as u32),
+            ],
+        );
+    }
+    writer.message(1, EVENT, &timer_event(end, EVENT_TYPE_STOP_ALL));
+    writer.message(
+        3,
+        LAP,
+        &[
+            (TIMESTAMP, UINT32, end),
+            (EVENT_FIELD, ENUM, EVENT_LAP),
+            (EVENT_TYPE_FIELD, ENUM, EVENT_TYPE_STOP),
+            (2, UINT32, start),
+            (7, UINT32, elapsed),
+            (8, UINT32, timer),
+            (15, UINT8, average_bpm),
+            (16, UINT8, max_bpm),
+        ],
+    );
+    writer.message(
+        4,
+        SESSION,
+        &[
+            (TIMESTAMP, UINT32, end),
+            (EVENT_FIELD, ENUM, EVENT_SESSION),
+            (EVENT_TYPE_FIELD, ENUM, EVENT_TYPE_STOP),
+            (2, UINT32, start),
+            (5, ENUM, SPORT_GENERIC),
+            (7, UINT32, elapsed),
+            (8, UINT32, timer),
+            (16, UINT8, average_bpm),
+            (17, UINT8, max_bpm),
+            (25, UINT16, 0),
+            (26, UINT16, 1),
+        ],
+    );
+    writer.message(
+        5,
+        ACTIVITY,
+        &[
+            (TIMESTAMP, UINT32, end),
+            (0, UINT32, timer),
+            (1, UINT16, 1),
+            (2, ENUM, 0),
+            (3, ENUM, EVENT_ACTIVITY),
+            (4, ENUM, EVENT_TYPE_STOP),
+            (5, UINT32, end.wrapping_add_signed(local_offset)),
+        ],
+    );
+    writer.finish()
+}
+
+fn base_type_size(base_type: u8) -> u8 {
+    match base_type {
+        ENUM | UINT8 => 1,
+        UINT16 => 2,
+        UINT32 => 4,
+        _ => unreachable!("Unsupported base type {base_type:#x}"),
+    }
+}
+
+fn fit_time(time: &DateTime<Utc>) -> u32 {
+    (time.timestamp() - FIT_EPOCH_OFFSET).max(0) as u32
+}
+
+// Durations are stored in seconds, scaled by 1000
+fn millis(duration: Duration) -> u32 {
+    duration.as_millis().min(u32::MAX as u128) as u32
+}
+
+/// CRC-16 as specified by the FIT SDK (which works out to CRC-16/ARC)
+fn crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    bytes.iter().fold(0, |mut crc, byte| {
+        for nibble in [byte & 0xF, byte >> 4] {
+            let tmp = TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ tmp ^ TABLE[nibble as usize];
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::workout;
+    use fitparser::profile::MesgNum;
+    use fitparser::{FitDataRecord, Value};
+    use std::collections::HashMap;
+
+    type Message = (u16, HashMap<u8, u32>);
+
+    /// Reads back everything `encode` can write, checking the framing along the way
+    fn decode(file: &[u8]) -> Vec<Message> {
+        assert_eq!(file[0], HEADER_SIZE);
+        assert_eq!(&file[8..12], b".FIT");
+        assert_eq!(crc(&file[..12]), u16::from_le_bytes([file[12], file[13]]));
+        let data_size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        assert_eq!(file.len(), HEADER_SIZE as usize + data_size + 2);
+        // Running the CRC over the data and its CRC comes out to 0
+        assert_eq!(crc(file), 0);
+
+        let mut definitions: HashMap<u8, (u16, Vec<(u8, u8)>)> = HashMap::new();
+        let mut messages = Vec::new();
+        let mut data = &file[HEADER_SIZE as usize..file.len() - 2];
+        while let Some((&header, rest)) = data.split_first() {
+            assert_eq!(
+                header & 0xA0,
+                0,
+                "unexpected compressed or developer data header"
+            );
+            let local_type = header & 0x0F;
+            if header & DEFINITION_HEADER != 0 {
+                assert_eq!(rest[1], LITTLE_ENDIAN);
+                let global = u16::from_le_bytes([rest[2], rest[3]]);
+                let count = rest[4] as usize;
+                let fields = rest[5..5 + count * 3]
+                    .chunks(3)
+                    .map(|field| (field[0], field[1]))
+                    .collect();
+                definitions.insert(local_type, (global, fields));
+                data = &rest[5 + count * 3..];
+            } else {
+                let (global, fields) = &definitions[&local_type];
+                let mut values = HashMap::new();
+                let mut offset = 0;
+                for (number, size) in fields {
+                    let mut bytes = [0; 4];
+                    bytes[..*size as usize].copy_from_slice(&rest[offset..offset + *size as usize]);
+                    values.insert(*number, u32::from_le_bytes(bytes));
+                    offset += *size as usize;
+                }
+                messages.push((*global, values));
+                data = &rest[offset..];
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn round_trip() {
+        let workout = workout();
+        let messages = decode(&encode(&workout));
+
+        let globals: Vec<u16> = messages.iter().map(|(global, _)| *global).collect();
+        assert_eq!(globals[..2], [FILE_ID, EVENT]);
+        assert_eq!(
+            globals[globals.len() - 4..],
+            [EVENT, LAP, SESSION, ACTIVITY]
+        );
+
+        let records: Vec<(u32, u32)> = messages
+            .iter()
+            .filter(|(global, _)| *global == RECORD)
+            .map(|(_, fields)| (fields[&TIMESTAMP], fields[&3]))
+            .collect();
+        let expected: Vec<(u32, u32)> = workout
+            .samples
+            .iter()
+            .map(|sample| (fit_time(&sample.time), sample.bpm as u32))
+            .collect();
+        assert_eq!(records, expected);
+        // 2024-05-01T10:00:00Z
+        assert_eq!(records[0].0, 1_714_557_600 - FIT_EPOCH_OFFSET as u32);
+
+        let (_, session) = messages
+            .iter()
+            .find(|(global, _)| *global == SESSION)
+            .unwrap();
+        assert_eq!(session[&2], fit_time(&workout.start));
+        assert_eq!(session[&7], 31_000);
+        assert_eq!(session[&8], 3_000);
+        assert_eq!((session[&16], session[&17]), (104, 130));
+        assert_eq!(session[&26], 1);
+    }
+
+    #[test]
+    fn golden_file() {
+        let golden = include_bytes!("../../tests/fixtures/export/workout.fit");
+        let workout = workout();
+        assert_eq!(encode_at(&workout, 0), golden);
+
+        // Read back by a decoder that isn't ours, which also checks both CRCs
+        let messages = fitparser::from_bytes(golden).unwrap();
+        let value = |message: &FitDataRecord, name: &str| {
+            message
+                .fields()
+                .iter()
+                .find(|field| field.name() == name)
+                .map(|field| field.value().clone())
+        };
+        let kinds: Vec<MesgNum> = messages.iter().map(|message| message.kind()).collect();
+        assert_eq!(kinds[..2], [MesgNum::FileId, MesgNum::Event]);
+        assert_eq!(
+            kinds[kinds.len() - 4..],
+            [
+                MesgNum::Event,
+                MesgNum::Lap,
+                MesgNum::Session,
+                MesgNum::Activity
+            ]
+        );
+
+        let records: Vec<(i64, u8)> = messages
+            .iter()
+            .filter(|message| message.kind() == MesgNum::Record)
+            .map(
+                |record| match (value(record, "timestamp"), value(record, "heart_rate")) {
+                    (Some(Value::Timestamp(time)), Some(Value::UInt8(bpm))) => {
+                        (time.timestamp(), bpm)
+                    }
+                    other => panic!("Unexpected record fields {other:?}"),
+                },
+            )
+            .collect();
+        let expected: Vec<(i64, u8)> = workout
+            .samples
+            .iter()
+            .map(|sample| (sample.time.timestamp(), sample.bpm as u8))
+            .collect();
+        assert_eq!(records, expected);
+
+        let session = messages
+            .iter()
+            .find(|message| message.kind() == MesgNum::Session)
+            .unwrap();
+        match value(session, "start_time") {
+            Some(Value::Timestamp(time)) => assert_eq!(time.timestamp(), workout.start.timestamp()),
+            other => panic!("Unexpected start_time {other:?}"),
+        }
+        assert_eq!(
+            value(session, "total_elapsed_time"),
+            Some(Value::Float64(31.0))
+        );
+        assert_eq!(
+            value(session, "total_timer_time"),
+            Some(Value::Float64(3.0))
+        );
+        assert_eq!(value(session, "avg_heart_rate"), Some(Value::UInt8(104)));
+        assert_eq!(value(session, "max_heart_rate"), Some(Value::UInt8(130)));
+        assert_eq!(value(session, "num_laps"), Some(Value::UInt16(1)));
+    }
+
+    #[test]
+    fn heart_rate_clamped() {
+        let mut workout = workout();
+        workout.samples[0].bpm = 300;
+        let messages = decode(&encode(&workout));
+        let (_, first_record) = messages
+            .iter()
+            .find(|(global, _)| *global == RECORD)
+            .unwrap();
+        assert_eq!(first_record[&3], 254);
+    }
+}
@@ -0,0 +1,115 @@
+//! GPX 1.1 with heart rate in Garmin's TrackPointExtension
+
+use std::fmt::Write;
+
+use super::{xml_escape, xml_time, Workout};
+
+pub fn encode(workout: &Workout) -> String {
+    let mut xml = String::new();
+    xml.push_str(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="iron-heart" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd">
+"#,
+    );
+    // Writing to a String can't fail
+    let _ = writeln!(
+        xml,
+        "  <metadata><time>{}</time></metadata>",
+        xml_time(&workout.start)
+    );
+    xml.push_str("  <trk>\n");
+    let _ = writeln!(xml, "    <name>{}</name>", xml_escape(&workout.name));
+    xml.push_str("    <trkseg>\n");
+    for sample in &workout.samples {
+        // GPX needs a position for every point, and there isn't one
+        let _ = writeln!(
+            xml,
+            "      <trkpt lat=\"0\" lon=\"0\"><time>{}</time><extensions><gpxtpx:TrackPointExtension><gpxtpx:hr>{}</gpxtpx:hr></gpxtpx:TrackPointExtension></extensions></trkpt>",
+            xml_time(&sample.time),
+            sample.bpm
+        );
+    }
+    xml.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{workout, xml_text, xml_values};
+
+    const GPX: &str = "http://www.topografix.com/GPX/1/1";
+    const TRACK_POINT_EXTENSION: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v1";
+
+    #[test]
+    fn round_trip() {
+        let workout = workout();
+        let xml = encode(&workout);
+
+        // The first time is the metadata's
+        let times = xml_values(&xml, "time");
+        assert_eq!(times[0], "2024-05-01T10:00:00Z");
+        let decoded: Vec<(String, u16)> = times[1..]
+            .iter()
+            .cloned()
+            .zip(
+                xml_values(&xml, "gpxtpx:hr")
+                    .iter()
+                    .map(|hr| hr.parse().unwrap()),
+            )
+            .collect();
+        let expected: Vec<(String, u16)> = workout
+            .samples
+            .iter()
+            .map(|sample| (xml_time(&sample.time), sample.bpm))
+            .collect();
+        assert_eq!(decoded, expected);
+        assert_eq!(xml_values(&xml, "name"), ["nih-test &amp; co"]);
+    }
+
+    #[test]
+    fn golden_file() {
+        let golden = include_str!("../../tests/fixtures/export/workout.gpx");
+        assert_eq!(encode(&workout()), golden);
+
+        let document = roxmltree::Document::parse(golden).unwrap();
+        let gpx = document.root_element();
+        assert!(gpx.has_tag_name((GPX, "gpx")));
+        assert_eq!(gpx.attribute("version"), Some("1.1"));
+        let metadata = gpx
+            .children()
+            .find(|node| node.has_tag_name((GPX, "metadata")));
+        assert_eq!(
+            xml_text(metadata.unwrap(), (GPX, "time")),
+            Some("2024-05-01T10:00:00Z")
+        );
+        let track = gpx.children().find(|node| node.has_tag_name((GPX, "trk")));
+        assert_eq!(
+            xml_text(track.unwrap(), (GPX, "name")),
+            Some("nih-test & co")
+        );
+
+        let points: Vec<(&str, &str)> = gpx
+            .descendants()
+            .filter(|node| node.has_tag_name((GPX, "trkpt")))
+            .map(|point| {
+                assert_eq!(point.attribute("lat"), Some("0"));
+                assert_eq!(point.attribute("lon"), Some("0"));
+                (
+                    xml_text(point, (GPX, "time")).unwrap(),
+                    xml_text(point, (TRACK_POINT_EXTENSION, "hr")).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            points,
+            [
+                ("2024-05-01T10:00:00Z", "80"),
+                ("2024-05-01T10:00:01Z", "90"),
+                ("2024-05-01T10:00:02Z", "120"),
+                ("2024-05-01T10:00:30Z", "130"),
+                ("2024-05-01T10:00:31Z", "100"),
+            ]
+        );
+    }
+}
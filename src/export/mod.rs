@@ -0,0 +1,208 @@
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::session_logs::SessionLog;
+use crate::settings::ExportFormat;
+
+mod fit;
+mod gpx;
+mod tcx;
+
+// Gaps longer than this between samples (disconnections) don't count as time moving
+const MAX_SAMPLE_GAP: Duration = Duration::from_secs(5);
+const XML_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorkoutSample {
+    pub time: DateTime<Utc>,
+    pub bpm: u16,
+}
+
+/// A session as a single-lap workout, which is all the encoders need
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workout {
+    pub name: String,
+    pub samples: Vec<WorkoutSample>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Excludes disconnections
+    pub timer: Duration,
+    pub average_bpm: u16,
+    pub max_bpm: u16,
+}
+
+impl Workout {
+    pub fn new(name: String, samples: Vec<WorkoutSample>) -> Option<Self> {
+        let start = samples.first()?.time;
+        let end = samples.last()?.time;
+        let timer = samples
+            .windows(2)
+            .map(|pair| (pair[1].time - pair[0].time).to_std().unwrap_or_default())
+            .filter(|gap| *gap <= MAX_SAMPLE_GAP)
+            .sum();
+        let bpm_sum: u32 = samples.iter().map(|sample| sample.bpm as u32).sum();
+        Some(Self {
+            name,
+            start,
+            end,
+            timer,
+            average_bpm: (bpm_sum as f32 / samples.len() as f32).round() as u16,
+            max_bpm: samples.iter().map(|sample| sample.bpm).max()?,
+            samples,
+        })
+    }
+    /// CSV logs are in local time
+    pub fn from_session_log(log: &SessionLog) -> Option<Self> {
+        let start = Local
+            .from_local_datetime(&log.start)
+            .earliest()?
+            .with_timezone(&Utc);
+        let samples = log
+            .samples
+            .iter()
+            .map(|sample| WorkoutSample {
+                time: start + Duration::from_secs_f64(sample.secs),
+                bpm: sample.bpm,
+            })
+            .collect();
+        Self::new(log.name.clone(), samples)
+    }
+    pub fn elapsed(&self) -> Duration {
+        (self.end - self.start).to_std().unwrap_or_default()
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Fit => "fit",
+            ExportFormat::Tcx => "tcx",
+            ExportFormat::Gpx => "gpx",
+        }
+    }
+    pub fn encode(&self, workout: &Workout) -> Vec<u8> {
+        match self {
+            ExportFormat::Fit => fit::encode(workout),
+            ExportFormat::Tcx => tcx::encode(workout).into_bytes(),
+            ExportFormat::Gpx => gpx::encode(workout).into_bytes(),
+        }
+    }
+}
+
+/// Writes the CSV log as each format, next to it unless `out_dir` is given.
+///
+/// Returns the paths written.
+pub fn export_session_log(
+    csv_path: &Path,
+    formats: &[ExportFormat],
+    out_dir: Option<&Path>,
+) -> Result<Vec<PathBuf>, AppError> {
+    let workout = SessionLog::load(csv_path)?
+        .as_ref()
+        .and_then(Workout::from_session_log)
+        .ok_or_else(|| AppError::EmptySessionLog(csv_path.to_owned()))?;
+    let out_dir = out_dir
+        .or_else(|| csv_path.parent())
+        .unwrap_or(Path::new("."));
+    if !out_dir.as_os_str().is_empty() && !out_dir.exists() {
+        std::fs::create_dir_all(out_dir).map_err(|e| AppError::CreateDir {
+            path: out_dir.to_owned(),
+            source: e,
+        })?;
+    }
+    let mut written = Vec::new();
+    for format in formats {
+        let path = out_dir.join(format!("{}.{}", workout.name, format.extension()));
+        std::fs::write(&path, format.encode(&workout)).map_err(|e| AppError::WriteFile {
+            path: path.clone(),
+            source: e,
+        })?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn xml_time(time: &DateTime<Utc>) -> String {
+    time.format(XML_TIME_FORMAT).to_string()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn workout() -> Workout {
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap();
+        let samples = [(0, 80), (1, 90), (2, 120), (30, 130), (31, 100)]
+            .into_iter()
+            .map(|(secs, bpm)| WorkoutSample {
+                time: start + Duration::from_secs(secs),
+                bpm,
+            })
+            .collect();
+        Workout::new("nih-test & co".into(), samples).unwrap()
+    }
+
+    /// Pulls the text out of every `<tag>...</tag>`, enough to read back what the encoders wrote
+    pub fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+        let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+        xml.split(&open)
+            .skip(1)
+            .map(|rest| rest.split(&close).next().unwrap().to_string())
+            .collect()
+    }
+
+    /// Text of the first element under `node` with this namespace and name
+    pub fn xml_text<'a>(node: roxmltree::Node<'a, '_>, name: (&str, &str)) -> Option<&'a str> {
+        node.descendants()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+    }
+
+    #[test]
+    fn workout_totals() {
+        let workout = workout();
+        assert_eq!(workout.elapsed(), Duration::from_secs(31));
+        // The 28s gap was a disconnection
+        assert_eq!(workout.timer, Duration::from_secs(3));
+        assert_eq!((workout.average_bpm, workout.max_bpm), (104, 130));
+    }
+
+    #[test]
+    fn exports_next_to_log() {
+        let folder = tempfile::tempdir().unwrap();
+        let csv_path = folder.path().join("nih-2024-05-01.csv");
+        std::fs::write(
+            &csv_path,
+            "Timestamp,BPM,RR\n2024-05-01 10:00:00,80,750\n2024-05-01 10:00:01,82,730\n",
+        )
+        .unwrap();
+        let written = export_session_log(
+            &csv_path,
+            &[ExportFormat::Fit, ExportFormat::Tcx, ExportFormat::Gpx],
+            None,
+        )
+        .unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "nih-2024-05-01.fit",
+                "nih-2024-05-01.tcx",
+                "nih-2024-05-01.gpx"
+            ]
+        );
+        assert!(written.iter().all(|path| path.exists()));
+    }
+}
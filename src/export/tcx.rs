@@ -0,0 +1,147 @@
+//! Garmin Training Center XML, the single lap/no distance subset of it that a session needs
+
+use std::fmt::Write;
+
+use super::{xml_escape, xml_time, Workout};
+
+pub fn encode(workout: &Workout) -> String {
+    let mut xml = String::new();
+    let start = xml_time(&workout.start);
+    xml.push_str(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2 http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd">
+  <Activities>
+    <Activity Sport="Other">
+"#,
+    );
+    // Writing to a String can't fail
+    let _ = writeln!(xml, "      <Id>{start}</Id>");
+    let _ = writeln!(xml, "      <Lap StartTime=\"{start}\">");
+    let _ = writeln!(
+        xml,
+        "        <TotalTimeSeconds>{:.1}</TotalTimeSeconds>",
+        workout.timer.as_secs_f32()
+    );
+    xml.push_str("        <DistanceMeters>0</DistanceMeters>\n");
+    xml.push_str("        <Calories>0</Calories>\n");
+    let _ = writeln!(
+        xml,
+        "        <AverageHeartRateBpm><Value>{}</Value></AverageHeartRateBpm>",
+        workout.average_bpm
+    );
+    let _ = writeln!(
+        xml,
+        "        <MaximumHeartRateBpm><Value>{}</Value></MaximumHeartRateBpm>",
+        workout.max_bpm
+    );
+    xml.push_str("        <Intensity>Active</Intensity>\n");
+    xml.push_str("        <TriggerMethod>Manual</TriggerMethod>\n");
+    xml.push_str("        <Track>\n");
+    for sample in &workout.samples {
+        let _ = writeln!(
+            xml,
+            "          <Trackpoint><Time>{}</Time><HeartRateBpm><Value>{}</Value></HeartRateBpm></Trackpoint>",
+            xml_time(&sample.time),
+            sample.bpm
+        );
+    }
+    xml.push_str("        </Track>\n");
+    xml.push_str("      </Lap>\n");
+    let _ = writeln!(xml, "      <Notes>{}</Notes>", xml_escape(&workout.name));
+    xml.push_str("    </Activity>\n  </Activities>\n</TrainingCenterDatabase>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{workout, xml_text, xml_values};
+
+    const TCX: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2";
+
+    #[test]
+    fn round_trip() {
+        let workout = workout();
+        let xml = encode(&workout);
+
+        let times = xml_values(&xml, "Time");
+        // Trackpoint values, after the lap's average and max
+        let values = xml_values(&xml, "Value");
+        assert_eq!(values[..2], ["104", "130"]);
+        let decoded: Vec<(String, u16)> = times
+            .into_iter()
+            .zip(values[2..].iter().map(|value| value.parse().unwrap()))
+            .collect();
+        let expected: Vec<(String, u16)> = workout
+            .samples
+            .iter()
+            .map(|sample| (xml_time(&sample.time), sample.bpm))
+            .collect();
+        assert_eq!(decoded, expected);
+
+        assert_eq!(xml_values(&xml, "Id"), ["2024-05-01T10:00:00Z"]);
+        assert_eq!(xml_values(&xml, "TotalTimeSeconds"), ["3.0"]);
+        assert_eq!(xml_values(&xml, "Notes"), ["nih-test &amp; co"]);
+    }
+
+    #[test]
+    fn golden_file() {
+        let golden = include_str!("../../tests/fixtures/export/workout.tcx");
+        assert_eq!(encode(&workout()), golden);
+
+        let document = roxmltree::Document::parse(golden).unwrap();
+        let database = document.root_element();
+        assert!(database.has_tag_name((TCX, "TrainingCenterDatabase")));
+        let activity = database
+            .descendants()
+            .find(|node| node.has_tag_name((TCX, "Activity")))
+            .unwrap();
+        assert_eq!(activity.attribute("Sport"), Some("Other"));
+        assert_eq!(
+            xml_text(activity, (TCX, "Id")),
+            Some("2024-05-01T10:00:00Z")
+        );
+        let notes = activity
+            .children()
+            .find(|node| node.has_tag_name((TCX, "Notes")));
+        assert_eq!(notes.and_then(|notes| notes.text()), Some("nih-test & co"));
+
+        let lap = activity
+            .children()
+            .find(|node| node.has_tag_name((TCX, "Lap")))
+            .unwrap();
+        assert_eq!(lap.attribute("StartTime"), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(xml_text(lap, (TCX, "TotalTimeSeconds")), Some("3.0"));
+        let lap_bpm = |name| {
+            let element = lap.children().find(|node| node.has_tag_name((TCX, name)));
+            xml_text(element.unwrap(), (TCX, "Value"))
+        };
+        assert_eq!(lap_bpm("AverageHeartRateBpm"), Some("104"));
+        assert_eq!(lap_bpm("MaximumHeartRateBpm"), Some("130"));
+
+        let points: Vec<(&str, &str)> = lap
+            .descendants()
+            .filter(|node| node.has_tag_name((TCX, "Trackpoint")))
+            .map(|point| {
+                let bpm = point
+                    .children()
+                    .find(|node| node.has_tag_name((TCX, "HeartRateBpm")))
+                    .unwrap();
+                (
+                    xml_text(point, (TCX, "Time")).unwrap(),
+                    xml_text(bpm, (TCX, "Value")).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            points,
+            [
+                ("2024-05-01T10:00:00Z", "80"),
+                ("2024-05-01T10:00:01Z", "90"),
+                ("2024-05-01T10:00:02Z", "120"),
+                ("2024-05-01T10:00:30Z", "130"),
+                ("2024-05-01T10:00:31Z", "100"),
+            ]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::atomic::Ordering;
 
-use crate::app::{App, ErrorPopup, SubState};
+use crate::app::{App, AppView, ErrorPopup, SubState};
 use crate::AppResult;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use tui_input::backend::crossterm::EventHandler;
@@ -69,6 +69,12 @@ pub fn handle_key_events(app: &mut App, key_event: KeyEvent) -> AppResult<()> {
             KeyCode::Char('c') | KeyCode::Char('C') if app.is_idle_on_ble_selection() => {
                 app.connect_for_characteristics();
             }
+            KeyCode::Char('c') if app.view == AppView::SessionBrowser => {
+                app.session_browser_toggle_compare();
+            }
+            KeyCode::Char('l') if app.error_message.is_none() => {
+                app.open_session_browser();
+            }
             KeyCode::Char('s') if app.is_idle_on_ble_selection() => {
                 let current_state = app.ble_scan_paused.load(Ordering::SeqCst);
                 app.ble_scan_paused.store(!current_state, Ordering::SeqCst);
@@ -77,6 +83,9 @@ pub fn handle_key_events(app: &mut App, key_event: KeyEvent) -> AppResult<()> {
             KeyCode::Char('a') => {
                 app.activities_select_prompt();
             }
+            KeyCode::Char('h') => {
+                app.show_hrv_spectrum = !app.show_hrv_spectrum;
+            }
             KeyCode::Char('j') => {
                 app.scroll_down();
             }
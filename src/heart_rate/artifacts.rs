@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::{HeartRateStatus, RrFlag};
+use crate::settings::{ArtifactMethod, ArtifactSettings};
+
+// Anything outside of this can't be a real heart beat (30 - 200 BPM)
+const MIN_RR: Duration = Duration::from_millis(300);
+const MAX_RR: Duration = Duration::from_millis(2000);
+// How many recent beats the Kubios and median methods compare against
+const REFERENCE_BEATS: usize = 5;
+// If this many beats in a row look wrong, the heart rate most likely really changed,
+// so we start trusting the new values instead of rejecting everything from here on
+const RESYNC_AFTER: u32 = 5;
+
+/// Checks RR intervals from a source for artifacts (missed/extra beats, noise)
+/// before they're broadcast, so every listener works off the same clean beats.
+///
+/// Detected artifacts are either replaced with an estimate ([`RrFlag::Corrected`])
+/// or left as-is and marked [`RrFlag::Flagged`], depending on the settings.
+pub struct ArtifactFilter {
+    method: ArtifactMethod,
+    threshold: f32,
+    kubios_threshold: Duration,
+    correct: bool,
+    recent: VecDeque<Duration>,
+    artifacts_in_a_row: u32,
+}
+
+impl ArtifactFilter {
+    pub fn new(settings: &ArtifactSettings) -> Self {
+        Self {
+            method: settings.method,
+            threshold: settings.threshold_percent as f32 / 100.0,
+            kubios_threshold: Duration::from_millis(settings.kubios_threshold_ms as u64),
+            correct: settings.correct,
+            recent: VecDeque::with_capacity(REFERENCE_BEATS),
+            artifacts_in_a_row: 0,
+        }
+    }
+    /// Fills in `rr_flags`, correcting `rr_intervals` in place if enabled
+    pub fn process(&mut self, hr_status: &mut HeartRateStatus) {
+        if hr_status.heart_rate_bpm == 0 {
+            self.reset();
+        }
+        hr_status.rr_flags = hr_status
+            .rr_intervals
+            .iter_mut()
+            .map(|rr| self.check(rr))
+            .collect();
+    }
+    /// Beats from before a disconnection aren't a useful reference
+    pub fn reset(&mut self) {
+        self.recent.clear();
+        self.artifacts_in_a_row = 0;
+    }
+    fn check(&mut self, rr: &mut Duration) -> RrFlag {
+        if self.method == ArtifactMethod::None {
+            return RrFlag::Normal;
+        }
+        let in_range = (MIN_RR..=MAX_RR).contains(rr);
+        let reference = self.reference();
+        let is_artifact = !in_range || reference.is_some_and(|r| self.deviates(*rr, r));
+
+        if !is_artifact {
+            self.artifacts_in_a_row = 0;
+            self.push(*rr);
+            return RrFlag::Normal;
+        }
+
+        self.artifacts_in_a_row += 1;
+        if in_range && self.artifacts_in_a_row >= RESYNC_AFTER {
+            self.recent.clear();
+            self.artifacts_in_a_row = 0;
+            self.push(*rr);
+            return RrFlag::Normal;
+        }
+
+        match reference {
+            Some(estimate) if self.correct => {
+                *rr = estimate;
+                RrFlag::Corrected
+            }
+            _ => RrFlag::Flagged,
+        }
+    }
+    /// What the method expects the next beat to look like
+    fn reference(&self) -> Option<Duration> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        match self.method {
+            ArtifactMethod::None => None,
+            ArtifactMethod::Malik => self.recent.back().copied(),
+            ArtifactMethod::Kubios => {
+                Some(self.recent.iter().sum::<Duration>() / self.recent.len() as u32)
+            }
+            ArtifactMethod::Median => {
+                let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+                sorted.sort();
+                Some(sorted[sorted.len() / 2])
+            }
+        }
+    }
+    fn deviates(&self, rr: Duration, reference: Duration) -> bool {
+        let difference = rr.abs_diff(reference);
+        match self.method {
+            ArtifactMethod::None => false,
+            ArtifactMethod::Malik | ArtifactMethod::Median => {
+                difference.as_secs_f32() / reference.as_secs_f32() > self.threshold
+            }
+            // Kubios' thresholds are given for 60 BPM, and scale with the average RR
+            ArtifactMethod::Kubios => {
+                difference > self.kubios_threshold.mul_f32(reference.as_secs_f32())
+            }
+        }
+    }
+    fn push(&mut self, rr: Duration) {
+        if self.recent.len() == REFERENCE_BEATS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(method: ArtifactMethod, correct: bool) -> ArtifactFilter {
+        ArtifactFilter::new(&ArtifactSettings {
+            method,
+            threshold_percent: 20,
+            kubios_threshold_ms: 250,
+            correct,
+        })
+    }
+
+    fn run(filter: &mut ArtifactFilter, rr_ms: &[u64]) -> HeartRateStatus {
+        let mut hr_status = HeartRateStatus {
+            heart_rate_bpm: 75,
+            rr_intervals: rr_ms.iter().map(|ms| Duration::from_millis(*ms)).collect(),
+            ..Default::default()
+        };
+        filter.process(&mut hr_status);
+        hr_status
+    }
+
+    fn ms(hr_status: &HeartRateStatus) -> Vec<u64> {
+        hr_status
+            .rr_intervals
+            .iter()
+            .map(|rr| rr.as_millis() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn malik_flags_missed_beat() {
+        let mut filter = filter(ArtifactMethod::Malik, false);
+        let hr_status = run(&mut filter, &[800, 820, 1640, 810]);
+        assert_eq!(
+            hr_status.rr_flags,
+            vec![
+                RrFlag::Normal,
+                RrFlag::Normal,
+                RrFlag::Flagged,
+                RrFlag::Normal
+            ]
+        );
+        // Left alone since we're not correcting
+        assert_eq!(ms(&hr_status), vec![800, 820, 1640, 810]);
+        assert_eq!(
+            hr_status.clean_rr_intervals().count(),
+            3,
+            "Flagged beat should be skipped"
+        );
+    }
+
+    #[test]
+    fn median_corrects() {
+        let mut filter = filter(ArtifactMethod::Median, true);
+        run(&mut filter, &[800, 790, 900, 810]);
+        // Median of the last beats is 810
+        let hr_status = run(&mut filter, &[400, 805]);
+        assert_eq!(hr_status.rr_flags, vec![RrFlag::Corrected, RrFlag::Normal]);
+        assert_eq!(ms(&hr_status), vec![810, 805]);
+    }
+
+    #[test]
+    fn kubios_scales_with_heart_rate() {
+        // At 120 BPM (500ms), a medium (250ms) threshold becomes 125ms
+        let mut filter = filter(ArtifactMethod::Kubios, false);
+        let hr_status = run(&mut filter, &[500, 500, 600, 700]);
+        assert_eq!(hr_status.rr_flags[2], RrFlag::Normal);
+        assert_eq!(hr_status.rr_flags[3], RrFlag::Flagged);
+    }
+
+    #[test]
+    fn out_of_range_without_reference() {
+        let mut filter = filter(ArtifactMethod::Malik, true);
+        // Nothing to estimate from yet, so it can only be flagged
+        let hr_status = run(&mut filter, &[5000, 800]);
+        assert_eq!(hr_status.rr_flags, vec![RrFlag::Flagged, RrFlag::Normal]);
+    }
+
+    #[test]
+    fn resyncs_after_real_change() {
+        let mut filter = filter(ArtifactMethod::Malik, true);
+        run(&mut filter, &[1000, 1000]);
+        let hr_status = run(&mut filter, &[600; 6]);
+        assert_eq!(&hr_status.rr_flags[..4], &[RrFlag::Corrected; 4]);
+        assert_eq!(&hr_status.rr_flags[4..], &[RrFlag::Normal; 2]);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut filter = filter(ArtifactMethod::None, true);
+        let hr_status = run(&mut filter, &[800, 3000]);
+        assert_eq!(hr_status.rr_flags, vec![RrFlag::Normal; 2]);
+        assert_eq!(ms(&hr_status), vec![800, 3000]);
+    }
+}
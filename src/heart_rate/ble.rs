@@ -1,6 +1,7 @@
 use super::{BatteryLevel, HeartRateStatus};
 use crate::app::{AppUpdate, ErrorPopup};
 use crate::errors::AppError;
+use crate::settings::ArtifactSettings;
 use crate::structs::DeviceInfo;
 
 use btleplug::api::{Characteristic, Peripheral, ValueNotification};
@@ -15,6 +16,7 @@ use uuid::Uuid;
 
 use crate::broadcast;
 
+use super::artifacts::ArtifactFilter;
 use super::measurement::parse_hrm;
 use super::twitcher::Twitcher;
 
@@ -28,14 +30,14 @@ pub const BATTERY_LEVEL_CHARACTERISTIC_UUID: Uuid =
 
 struct BleMonitorActor {
     peripheral: DeviceInfo,
-    rr_cooldown_amount: usize,
     no_packet_timeout: Duration,
     battery_characteristic: Option<Characteristic>,
     cancel_token: CancellationToken,
 
     battery_level: BatteryLevel,
     twitcher: Twitcher,
-    rr_left_to_burn: usize,
+    artifact_filter: ArtifactFilter,
+    last_rr: Option<Duration>,
 }
 
 // TODO Consider letting this thread be restarted
@@ -187,31 +189,28 @@ impl BleMonitorActor {
     fn handle_ble_hr(&mut self, data: &ValueNotification) -> HeartRateStatus {
         let timestamp = chrono::Local::now();
         let new_hr_status = parse_hrm(&data.value);
-        // An oddity I've noticed, is if we don't get an RR interval each update,
-        // there's a decent chance that the next one we do get will be weirdly high.
-        // So we'll just ignore the first few values we get after an empty set.
-        let new_interval_count = new_hr_status.rr_intervals.len();
-        let rr_intervals = if new_interval_count > self.rr_left_to_burn {
-            new_hr_status.rr_intervals[self.rr_left_to_burn..].to_vec()
-        } else {
-            Vec::new()
-        };
-        self.rr_left_to_burn = if self.rr_left_to_burn == 0 && new_hr_status.rr_intervals.is_empty()
-        {
-            self.rr_cooldown_amount
-        } else {
-            self.rr_left_to_burn.saturating_sub(new_interval_count)
-        };
-        let (twitch_up, twitch_down) = self.twitcher.handle(new_hr_status.bpm, &rr_intervals);
 
-        HeartRateStatus {
+        // Some monitors send a weirdly high RR after a packet without any,
+        // the artifact filter catches those along with everything else
+        let mut hr_status = HeartRateStatus {
             heart_rate_bpm: new_hr_status.bpm,
-            rr_intervals,
+            rr_intervals: new_hr_status.rr_intervals,
             battery_level: self.battery_level,
-            twitch_up,
-            twitch_down,
+            energy_expended: new_hr_status.energy_expended,
             timestamp,
+            ..Default::default()
+        };
+        self.artifact_filter.process(&mut hr_status);
+        if let Some(rr) = hr_status.latest_clean_rr() {
+            self.last_rr = Some(rr);
         }
+        hr_status.last_rr = self.last_rr;
+
+        let clean_rr: Vec<Duration> = hr_status.clean_rr_intervals().collect();
+        (hr_status.twitch_up, hr_status.twitch_down) =
+            self.twitcher.handle(hr_status.heart_rate_bpm, &clean_rr);
+
+        hr_status
     }
     async fn get_monitor_battery(&mut self, device: &btleplug::platform::Peripheral) {
         if let Some(characteristic) = self.battery_characteristic.as_ref() {
@@ -226,12 +225,13 @@ impl BleMonitorActor {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_notification_thread(
     broadcast_tx: BSender<AppUpdate>,
     restart_tx: Sender<()>,
     peripheral: DeviceInfo,
-    rr_cooldown_amount: usize,
     twitch_threshold: f32,
+    artifact_settings: ArtifactSettings,
     no_packet_timeout: Duration,
     cancel_token: CancellationToken,
 ) {
@@ -243,8 +243,8 @@ pub async fn start_notification_thread(
         cancel_token,
         battery_level,
         twitcher: Twitcher::new(twitch_threshold),
-        rr_cooldown_amount,
-        rr_left_to_burn: rr_cooldown_amount,
+        artifact_filter: ArtifactFilter::new(&artifact_settings),
+        last_rr: None,
     };
 
     if let Err(e) = ble_monitor.connect(&broadcast_tx, restart_tx).await {
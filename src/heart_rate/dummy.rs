@@ -1,7 +1,8 @@
+use super::artifacts::ArtifactFilter;
 use super::{rr_from_bpm, BatteryLevel, HeartRateStatus};
 use crate::app::{AppUpdate, ErrorPopup};
 use crate::broadcast;
-use crate::settings::DummySettings;
+use crate::settings::{ArtifactSettings, DummySettings};
 
 use std::time::Duration;
 use tracing::info;
@@ -14,6 +15,7 @@ use tokio::sync::broadcast::Sender as BSender;
 pub async fn dummy_thread(
     broadcast_tx: BSender<AppUpdate>,
     dummy_settings: DummySettings,
+    artifact_settings: ArtifactSettings,
     seconds_override: Option<f32>,
     vhs_mode: bool,
     cancel_token: CancellationToken,
@@ -27,6 +29,8 @@ pub async fn dummy_thread(
 
     let mut loops: u16 = 0;
     let mut positive_direction = true;
+    // Made up beats won't have artifacts, but they're flagged the same as real ones
+    let mut artifact_filter = ArtifactFilter::new(&artifact_settings);
     let mut hr_status = HeartRateStatus {
         heart_rate_bpm: low_bpm.saturating_sub(1),
         battery_level: BatteryLevel::Level(100),
@@ -46,6 +50,7 @@ pub async fn dummy_thread(
             low_bpm
         };
         hr_status.rr_intervals = vec![rr_from_bpm(hr_status.heart_rate_bpm)];
+        artifact_filter.process(&mut hr_status);
         if hr_status.heart_rate_bpm == bound {
             positive_direction = !positive_direction;
             loops += 1;
@@ -14,7 +14,7 @@ pub struct HeartRateMeasurement {
     pub is_sensor_contact_detected: Option<bool>,
     // Note that this _could_ overflow for very very long rides, but that makes
     // an otherwise snapshot-only measurement need prior context.  This is in
-    // Joules.
+    // kilojoules.
     pub energy_expended: Option<u16>,
     // This is list of the time (in seconds) measured between R-Wave detections.
     // It is an array, because there may be many intervals recorded during a
@@ -1,3 +1,4 @@
+pub mod artifacts;
 pub mod ble;
 pub mod dummy;
 pub mod measurement;
@@ -26,11 +27,40 @@ impl From<BatteryLevel> for u8 {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RrFlag {
+    #[default]
+    Normal,
+    /// Detected as an artifact and replaced with an estimate
+    Corrected,
+    /// Detected as an artifact and left as-is, shouldn't be trusted
+    Flagged,
+}
+
+impl RrFlag {
+    /// Name used in logs and exports
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RrFlag::Normal => "normal",
+            RrFlag::Corrected => "corrected",
+            RrFlag::Flagged => "flagged",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct HeartRateStatus {
     pub heart_rate_bpm: u16,
     pub rr_intervals: Vec<Duration>,
+    // Matches up with rr_intervals, filled in by the source's ArtifactFilter
+    // (Missing flags are treated as Normal)
+    pub rr_flags: Vec<RrFlag>,
+    /// Latest clean RR interval, carried over from earlier packets.
+    /// Only for displaying, `rr_intervals` just has the beats that are new in this packet
+    pub last_rr: Option<Duration>,
     pub battery_level: BatteryLevel,
+    /// Running total in kJ, if the monitor reports it
+    pub energy_expended: Option<u16>,
     // Twitches are calculated by HR sources so that
     // all listeners see twitches at the same time
     pub twitch_up: bool,
@@ -38,6 +68,20 @@ pub struct HeartRateStatus {
     pub timestamp: DateTime<Local>,
 }
 
+impl HeartRateStatus {
+    /// RR intervals that can be trusted (including corrected ones)
+    pub fn clean_rr_intervals(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.rr_intervals
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.rr_flags.get(*i) != Some(&RrFlag::Flagged))
+            .map(|(_, rr)| *rr)
+    }
+    pub fn latest_clean_rr(&self) -> Option<Duration> {
+        self.clean_rr_intervals().last()
+    }
+}
+
 // Only used as a backup if the HRM doesn't support
 // sending RR intervals
 // (Or when mimicking)
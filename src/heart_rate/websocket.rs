@@ -1,9 +1,10 @@
+use super::artifacts::ArtifactFilter;
 use super::twitcher::Twitcher;
 use super::{BatteryLevel, HeartRateStatus};
 use crate::app::{AppUpdate, ErrorPopup};
 use crate::broadcast;
 use crate::errors::AppError;
-use crate::settings::WebSocketSettings;
+use crate::settings::{ArtifactSettings, WebSocketSettings};
 
 use serde::Deserialize;
 use std::net::{SocketAddr, SocketAddrV4};
@@ -34,6 +35,7 @@ struct WebsocketActor {
     listener: TcpListener,
     hr_status: HeartRateStatus,
     twitcher: Twitcher,
+    artifact_filter: ArtifactFilter,
     no_packet_timeout: Duration,
 }
 
@@ -42,6 +44,7 @@ impl WebsocketActor {
         websocket_settings: WebSocketSettings,
         port_override: Option<u16>,
         rr_twitch_threshold: f32,
+        artifact_settings: &ArtifactSettings,
         no_packet_timeout: Duration,
     ) -> Result<(Self, SocketAddr), AppError> {
         let port = port_override.unwrap_or(websocket_settings.port);
@@ -61,6 +64,7 @@ impl WebsocketActor {
                 listener,
                 hr_status,
                 twitcher: Twitcher::new(rr_twitch_threshold),
+                artifact_filter: ArtifactFilter::new(artifact_settings),
                 no_packet_timeout,
             },
             local_addr,
@@ -193,16 +197,24 @@ impl WebsocketActor {
             if let Some(battery) = new_status.battery {
                 self.hr_status.battery_level = BatteryLevel::Level(battery);
             }
+            // Listeners count every entry in rr_intervals as a new beat,
+            // so the last RR is only kept around in last_rr if a message doesn't have one
             if let Some(rr) = new_status.latest_rr_ms {
-                while !self.hr_status.rr_intervals.is_empty() {
-                    self.hr_status.rr_intervals.pop();
+                self.hr_status.rr_intervals = vec![Duration::from_millis(rr)];
+                self.artifact_filter.process(&mut self.hr_status);
+                if let Some(rr) = self.hr_status.latest_clean_rr() {
+                    self.hr_status.last_rr = Some(rr);
+                }
+            } else {
+                self.hr_status.rr_intervals.clear();
+                self.hr_status.rr_flags.clear();
+                if new_status.bpm == 0 {
+                    self.artifact_filter.reset();
                 }
-                self.hr_status.rr_intervals.push(Duration::from_millis(rr));
             }
 
-            let (twitch_up, twitch_down) = self
-                .twitcher
-                .handle(new_status.bpm, &self.hr_status.rr_intervals);
+            let clean_rr: Vec<Duration> = self.hr_status.clean_rr_intervals().collect();
+            let (twitch_up, twitch_down) = self.twitcher.handle(new_status.bpm, &clean_rr);
             self.hr_status.twitch_up = twitch_up;
             self.hr_status.twitch_down = twitch_down;
             self.hr_status.timestamp = now;
@@ -225,6 +237,7 @@ pub async fn websocket_thread(
     websocket_settings: WebSocketSettings,
     port_override: Option<u16>,
     rr_twitch_threshold: f32,
+    artifact_settings: ArtifactSettings,
     no_packet_timeout: Duration,
     cancel_token: CancellationToken,
 ) {
@@ -232,6 +245,7 @@ pub async fn websocket_thread(
         websocket_settings,
         port_override,
         rr_twitch_threshold,
+        &artifact_settings,
         no_packet_timeout,
     )
     .await
@@ -253,3 +267,62 @@ pub async fn websocket_thread(
         broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hrv::HrvEngine;
+    use crate::settings::{ArtifactMethod, HrvSettings};
+
+    async fn actor() -> WebsocketActor {
+        let artifact_settings = ArtifactSettings {
+            method: ArtifactMethod::None,
+            ..Default::default()
+        };
+        let (actor, _) = WebsocketActor::build(
+            WebSocketSettings::default(),
+            Some(0),
+            0.1,
+            &artifact_settings,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+        actor
+    }
+
+    fn send(actor: &mut WebsocketActor, json: &str) -> HeartRateStatus {
+        let Some((AppUpdate::HeartRateStatus(status), true)) =
+            actor.handle_ws_message(Some(Ok(Message::text(json.to_owned()))))
+        else {
+            panic!("Expected a heart rate update for {json}");
+        };
+        status
+    }
+
+    #[tokio::test]
+    async fn messages_without_rr_add_no_beats() {
+        let mut actor = actor().await;
+        let mut hrv = HrvEngine::new(&HrvSettings {
+            window_secs: 60,
+            frequency_window_secs: 120,
+            respiration_window_secs: 60,
+        });
+        let mut metrics = None;
+        for _ in 0..10 {
+            let status = send(&mut actor, r#"{"bpm": 75, "latest_rr_ms": 800}"#);
+            metrics = hrv.update(&status).or(metrics);
+        }
+        assert_eq!(metrics.unwrap().beats, 10);
+
+        for _ in 0..5 {
+            let status = send(&mut actor, r#"{"bpm": 75}"#);
+            assert!(status.rr_intervals.is_empty());
+            assert_eq!(status.last_rr, Some(Duration::from_millis(800)));
+            assert!(hrv.update(&status).is_none());
+        }
+
+        let status = send(&mut actor, r#"{"bpm": 75, "latest_rr_ms": 810}"#);
+        assert_eq!(hrv.update(&status).unwrap().beats, 11);
+    }
+}
@@ -0,0 +1,195 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Standard short-term HRV bands, in Hz
+pub const LF_BAND: (f64, f64) = (0.04, 0.15);
+pub const HF_BAND: (f64, f64) = (0.15, 0.4);
+// Spacing and upper limit of the frequencies the spectrum is evaluated at
+pub(super) const FREQUENCY_STEP: f64 = 0.002;
+const MAX_FREQUENCY: f64 = 0.5;
+
+/// Frequency-domain heart rate variability
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrequencyMetrics {
+    /// Low frequency (0.04 - 0.15 Hz) power in ms²
+    pub lf_power: f32,
+    /// High frequency (0.15 - 0.4 Hz) power in ms²
+    pub hf_power: f32,
+    pub lf_hf_ratio: f32,
+    /// Power spectral density as (Hz, ms²/Hz), shared so passing the metrics around stays cheap
+    pub spectrum: Arc<[(f64, f64)]>,
+}
+
+impl FrequencyMetrics {
+    pub fn from_rr(rr_ms: &[f32]) -> Self {
+        let spectrum = power_spectrum(rr_ms);
+        let lf_power = band_power(&spectrum, LF_BAND) as f32;
+        let hf_power = band_power(&spectrum, HF_BAND) as f32;
+        let lf_hf_ratio = if hf_power > 0.0 {
+            lf_power / hf_power
+        } else {
+            0.0
+        };
+        Self {
+            lf_power,
+            hf_power,
+            lf_hf_ratio,
+            spectrum: spectrum.into(),
+        }
+    }
+}
+
+/// Lomb-Scargle periodogram of `values` (mean already removed) sampled at `times` (s)
+pub fn lomb_scargle(times: &[f64], values: &[f64], frequency: f64) -> f64 {
+    let omega = 2.0 * PI * frequency;
+    // Time offset that makes the result independent of shifting all the samples in time
+    let (sin_sum, cos_sum) = times.iter().fold((0.0, 0.0), |(s, c), t| {
+        let (sin, cos) = (2.0 * omega * t).sin_cos();
+        (s + sin, c + cos)
+    });
+    let tau = sin_sum.atan2(cos_sum) / (2.0 * omega);
+
+    let (mut yc, mut ys, mut cc, mut ss) = (0.0, 0.0, 0.0, 0.0);
+    for (t, y) in times.iter().zip(values) {
+        let (sin, cos) = (omega * (t - tau)).sin_cos();
+        yc += y * cos;
+        ys += y * sin;
+        cc += cos * cos;
+        ss += sin * sin;
+    }
+    let cos_term = if cc > 0.0 { yc * yc / cc } else { 0.0 };
+    let sin_term = if ss > 0.0 { ys * ys / ss } else { 0.0 };
+    (cos_term + sin_term) / 2.0
+}
+
+/// One-sided power spectral density of an RR series, as (Hz, ms²/Hz)
+///
+/// Each interval is placed at the time its beat landed, and the periodogram is scaled
+/// so integrating over all frequencies gives back the variance of the series.
+pub fn power_spectrum(rr_ms: &[f32]) -> Vec<(f64, f64)> {
+    if rr_ms.len() < 2 {
+        return Vec::new();
+    }
+    let mean = rr_ms.iter().map(|rr| *rr as f64).sum::<f64>() / rr_ms.len() as f64;
+    let values: Vec<f64> = rr_ms.iter().map(|rr| *rr as f64 - mean).collect();
+    let times: Vec<f64> = rr_ms
+        .iter()
+        .scan(0.0, |elapsed, rr| {
+            *elapsed += *rr as f64 / 1000.0;
+            Some(*elapsed)
+        })
+        .collect();
+    // Twice the mean sampling interval (s)
+    let scale = 2.0 * mean / 1000.0;
+    let steps = (MAX_FREQUENCY / FREQUENCY_STEP).round() as usize;
+    (1..=steps)
+        .map(|step| {
+            let frequency = step as f64 * FREQUENCY_STEP;
+            (frequency, lomb_scargle(&times, &values, frequency) * scale)
+        })
+        .collect()
+}
+
+/// Total power (ms²) of the spectrum within `[low, high)` Hz
+pub fn band_power(spectrum: &[(f64, f64)], (low, high): (f64, f64)) -> f64 {
+    spectrum
+        .iter()
+        .filter(|(frequency, _)| (low..high).contains(frequency))
+        .map(|(_, density)| density * FREQUENCY_STEP)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RR intervals whose length follows the given sinusoids (Hz, amplitude in ms) over time
+    fn modulated_rr(components: &[(f64, f64)], secs: f64) -> Vec<f32> {
+        let mut rr_ms = Vec::new();
+        let mut elapsed = 0.0;
+        while elapsed < secs {
+            let rr = 1000.0
+                + components
+                    .iter()
+                    .map(|(hz, amplitude)| amplitude * (2.0 * PI * hz * elapsed).sin())
+                    .sum::<f64>();
+            elapsed += rr / 1000.0;
+            rr_ms.push(rr as f32);
+        }
+        rr_ms
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * tolerance,
+            "{actual} not within {}% of {expected}",
+            tolerance * 100.0
+        );
+    }
+
+    #[test]
+    fn matches_periodogram_when_evenly_sampled() {
+        // With even sampling, Lomb-Scargle is the classic periodogram |X(f)|² / N
+        let values: Vec<f64> = (0..64).map(|i| ((i * 37 % 11) as f64) - 5.0).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let values: Vec<f64> = values.iter().map(|v| v - mean).collect();
+        let times: Vec<f64> = (0..values.len()).map(|i| i as f64).collect();
+        for k in [3, 10, 17] {
+            let frequency = k as f64 / values.len() as f64;
+            let (re, im) = times
+                .iter()
+                .zip(&values)
+                .fold((0.0, 0.0), |(re, im), (t, y)| {
+                    let (sin, cos) = (2.0 * PI * frequency * t).sin_cos();
+                    (re + y * cos, im - y * sin)
+                });
+            let expected = (re * re + im * im) / values.len() as f64;
+            let actual = lomb_scargle(&times, &values, frequency);
+            assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+        }
+    }
+
+    #[test]
+    fn lf_sinusoid() {
+        // A sinusoid's power is amplitude² / 2
+        let metrics = FrequencyMetrics::from_rr(&modulated_rr(&[(0.1, 50.0)], 180.0));
+        assert_close(metrics.lf_power, 1250.0, 0.1);
+        assert!(metrics.hf_power < metrics.lf_power * 0.05);
+    }
+
+    #[test]
+    fn hf_sinusoid() {
+        let metrics = FrequencyMetrics::from_rr(&modulated_rr(&[(0.25, 20.0)], 180.0));
+        assert_close(metrics.hf_power, 200.0, 0.1);
+        assert!(metrics.lf_power < metrics.hf_power * 0.05);
+    }
+
+    #[test]
+    fn lf_hf_ratio() {
+        // 800ms² of LF against 200ms² of HF
+        let metrics = FrequencyMetrics::from_rr(&modulated_rr(&[(0.1, 40.0), (0.3, 20.0)], 240.0));
+        assert_close(metrics.lf_hf_ratio, 4.0, 0.1);
+    }
+
+    #[test]
+    fn whole_ms_rr_series() {
+        // Two sines rounded to whole milliseconds, the rounding shouldn't move the band powers
+        let rr_ms: Vec<f32> = include_str!("../../tests/fixtures/synthetic_rr_two_sines.txt")
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.is_empty())
+            .map(|line| line.parse().unwrap())
+            .collect();
+        let metrics = FrequencyMetrics::from_rr(&rr_ms);
+        assert_close(metrics.lf_power, 1012.5, 0.05);
+        assert_close(metrics.hf_power, 312.5, 0.05);
+        assert_close(metrics.lf_hf_ratio, 3.24, 0.05);
+    }
+
+    #[test]
+    fn flat_series() {
+        let metrics = FrequencyMetrics::from_rr(&[800.0; 120]);
+        assert_eq!(metrics.lf_power, 0.0);
+        assert_eq!(metrics.hf_power, 0.0);
+        assert_eq!(metrics.lf_hf_ratio, 0.0);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::app::{AppUpdate, ErrorPopup};
+use crate::broadcast;
+use crate::errors::AppError;
+use crate::heart_rate::{HeartRateStatus, RrFlag};
+use crate::settings::HrvSettings;
+
+pub use frequency::{FrequencyMetrics, HF_BAND, LF_BAND};
+
+use frequency::power_spectrum;
+
+mod frequency;
+mod respiration;
+
+// Not much point in reporting anything with fewer beats than this
+const MIN_BEATS: usize = 10;
+// Anything outside of this can't be a real heart beat (30 - 200 BPM),
+// checked here too in case the source's artifact filter is disabled
+const MIN_RR: Duration = Duration::from_millis(300);
+const MAX_RR: Duration = Duration::from_millis(2000);
+// Histogram bin width used for Baevsky's stress index
+const SI_BIN_WIDTH_SECS: f32 = 0.05;
+// Frequency-domain analysis needs a few minutes of beats to resolve the LF band
+const MIN_FREQUENCY_WINDOW_SECS: u16 = 120;
+const MAX_FREQUENCY_WINDOW_SECS: u16 = 300;
+// Long enough to catch a few slow breaths, short enough to follow changes in breathing
+const MIN_RESPIRATION_WINDOW_SECS: u16 = 30;
+const MAX_RESPIRATION_WINDOW_SECS: u16 = 120;
+// Worth of new beats needed before the spectrum and breathing rate are worked out again
+const SPECTRUM_REFRESH: Duration = Duration::from_secs(1);
+
+/// Heart rate variability over the configured windows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HrvMetrics {
+    pub rmssd_ms: f32,
+    pub sdnn_ms: f32,
+    /// Percentage (0-100) of successive differences above 50ms
+    pub pnn50: f32,
+    /// Baevsky's stress index
+    pub stress_index: f32,
+    /// Beats in the current window
+    pub beats: usize,
+    /// RR intervals thrown out this session
+    pub artifacts: u32,
+    /// None until a full frequency window of beats has been collected
+    pub frequency: Option<FrequencyMetrics>,
+    /// Estimated breaths per minute, None until there's enough beats or without a clear rhythm
+    pub respiration_rate: Option<f32>,
+}
+
+pub struct HrvEngine {
+    window: Duration,
+    intervals: VecDeque<Duration>,
+    window_total: Duration,
+    // Separate (longer) window for the frequency-domain metrics
+    frequency_window: Duration,
+    frequency_intervals: VecDeque<Duration>,
+    frequency_total: Duration,
+    frequency_window_filled: bool,
+    // Last spectrum and breathing rate, and how long the beats since then add up to
+    frequency: Option<FrequencyMetrics>,
+    respiration_rate: Option<f32>,
+    since_spectrum: Duration,
+    // Taken from the end of the frequency window
+    respiration_window: Duration,
+    artifacts: u32,
+}
+
+impl HrvEngine {
+    pub fn new(settings: &HrvSettings) -> Self {
+        Self {
+            window: Duration::from_secs(settings.window_secs as u64),
+            intervals: VecDeque::new(),
+            window_total: Duration::ZERO,
+            frequency_window: Duration::from_secs(
+                settings
+                    .frequency_window_secs
+                    .clamp(MIN_FREQUENCY_WINDOW_SECS, MAX_FREQUENCY_WINDOW_SECS)
+                    as u64,
+            ),
+            frequency_intervals: VecDeque::new(),
+            frequency_total: Duration::ZERO,
+            frequency_window_filled: false,
+            frequency: None,
+            respiration_rate: None,
+            since_spectrum: Duration::ZERO,
+            respiration_window: Duration::from_secs(
+                settings
+                    .respiration_window_secs
+                    .clamp(MIN_RESPIRATION_WINDOW_SECS, MAX_RESPIRATION_WINDOW_SECS)
+                    as u64,
+            ),
+            artifacts: 0,
+        }
+    }
+    /// Returns fresh metrics if any new beats came in, and there's enough for them to mean something
+    pub fn update(&mut self, hr_status: &HeartRateStatus) -> Option<HrvMetrics> {
+        if hr_status.heart_rate_bpm == 0 {
+            // Can't compare beats across a gap
+            self.intervals.clear();
+            self.window_total = Duration::ZERO;
+            self.frequency_intervals.clear();
+            self.frequency_total = Duration::ZERO;
+            self.frequency_window_filled = false;
+            self.frequency = None;
+            self.respiration_rate = None;
+            self.since_spectrum = Duration::ZERO;
+            return None;
+        }
+        let mut accepted_any = false;
+        for (i, rr) in hr_status.rr_intervals.iter().enumerate() {
+            let flagged = hr_status.rr_flags.get(i) == Some(&RrFlag::Flagged);
+            if flagged || !(MIN_RR..=MAX_RR).contains(rr) {
+                self.artifacts += 1;
+                continue;
+            }
+            accepted_any = true;
+            self.since_spectrum += *rr;
+            self.intervals.push_back(*rr);
+            self.window_total += *rr;
+            while self.window_total > self.window && self.intervals.len() > MIN_BEATS {
+                let oldest = self.intervals.pop_front().expect("Window can't be empty");
+                self.window_total -= oldest;
+            }
+            self.frequency_intervals.push_back(*rr);
+            self.frequency_total += *rr;
+            while self.frequency_total > self.frequency_window {
+                let oldest = self
+                    .frequency_intervals
+                    .pop_front()
+                    .expect("Window can't be empty");
+                self.frequency_total -= oldest;
+                if !self.frequency_window_filled {
+                    // Don't wait for the next refresh to show LF/HF
+                    self.frequency_window_filled = true;
+                    self.since_spectrum = SPECTRUM_REFRESH;
+                }
+            }
+        }
+        if !accepted_any || self.intervals.len() < MIN_BEATS {
+            return None;
+        }
+        self.refresh_spectrum();
+        Some(self.metrics())
+    }
+    /// The periodograms are by far the slowest part, so only redo them once enough new beats came in
+    fn refresh_spectrum(&mut self) {
+        if self.since_spectrum < SPECTRUM_REFRESH {
+            return;
+        }
+        self.since_spectrum = Duration::ZERO;
+        if self.frequency_window_filled {
+            let rr_ms: Vec<f32> = self
+                .frequency_intervals
+                .iter()
+                .map(|rr| rr.as_secs_f32() * 1000.0)
+                .collect();
+            self.frequency = Some(FrequencyMetrics::from_rr(&rr_ms));
+        }
+        self.respiration_rate = self.estimate_respiration_rate();
+    }
+    fn metrics(&self) -> HrvMetrics {
+        let rr_ms: Vec<f32> = self
+            .intervals
+            .iter()
+            .map(|rr| rr.as_secs_f32() * 1000.0)
+            .collect();
+        HrvMetrics {
+            rmssd_ms: rmssd(&rr_ms),
+            sdnn_ms: sdnn(&rr_ms),
+            pnn50: pnn50(&rr_ms),
+            stress_index: stress_index(&rr_ms),
+            beats: rr_ms.len(),
+            artifacts: self.artifacts,
+            frequency: self.frequency.clone(),
+            respiration_rate: self.respiration_rate,
+        }
+    }
+    fn estimate_respiration_rate(&self) -> Option<f32> {
+        if self.frequency_total < self.respiration_window {
+            return None;
+        }
+        let mut total = Duration::ZERO;
+        let mut rr_ms: Vec<f32> = self
+            .frequency_intervals
+            .iter()
+            .rev()
+            .take_while(|rr| {
+                total += **rr;
+                total <= self.respiration_window
+            })
+            .map(|rr| rr.as_secs_f32() * 1000.0)
+            .collect();
+        rr_ms.reverse();
+        respiration::respiration_rate(&power_spectrum(&rr_ms))
+    }
+}
+
+fn successive_differences(rr_ms: &[f32]) -> impl Iterator<Item = f32> + '_ {
+    rr_ms.windows(2).map(|w| w[1] - w[0])
+}
+
+pub fn rmssd(rr_ms: &[f32]) -> f32 {
+    if rr_ms.len() < 2 {
+        return 0.0;
+    }
+    let sum_squares: f32 = successive_differences(rr_ms).map(|d| d * d).sum();
+    (sum_squares / (rr_ms.len() - 1) as f32).sqrt()
+}
+
+pub fn sdnn(rr_ms: &[f32]) -> f32 {
+    if rr_ms.len() < 2 {
+        return 0.0;
+    }
+    let mean = rr_ms.iter().sum::<f32>() / rr_ms.len() as f32;
+    let variance =
+        rr_ms.iter().map(|rr| (rr - mean).powi(2)).sum::<f32>() / (rr_ms.len() - 1) as f32;
+    variance.sqrt()
+}
+
+pub fn pnn50(rr_ms: &[f32]) -> f32 {
+    if rr_ms.len() < 2 {
+        return 0.0;
+    }
+    let over_50 = successive_differences(rr_ms)
+        .filter(|d| d.abs() > 50.0)
+        .count();
+    over_50 as f32 / (rr_ms.len() - 1) as f32 * 100.0
+}
+
+/// Baevsky's stress index: AMo / (2 * Mo * MxDMn)
+///
+/// Mo is the most common RR interval (s), AMo is the percentage of intervals in that bin,
+/// and MxDMn is the spread between the longest and shortest interval (s).
+pub fn stress_index(rr_ms: &[f32]) -> f32 {
+    let rr_secs: Vec<f32> = rr_ms.iter().map(|rr| rr / 1000.0).collect();
+    let (Some(min), Some(max)) = (
+        rr_secs.iter().copied().reduce(f32::min),
+        rr_secs.iter().copied().reduce(f32::max),
+    ) else {
+        return 0.0;
+    };
+    let spread = max - min;
+    if spread <= 0.0 {
+        return 0.0;
+    }
+    let bin_count = (spread / SI_BIN_WIDTH_SECS).floor() as usize + 1;
+    let mut bins = vec![0usize; bin_count];
+    for rr in &rr_secs {
+        let bin = ((rr - min) / SI_BIN_WIDTH_SECS).floor() as usize;
+        bins[bin.min(bin_count - 1)] += 1;
+    }
+    let (modal_bin, modal_count) = bins
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, count)| **count)
+        .expect("At least one bin");
+    let mode = min + (modal_bin as f32 + 0.5) * SI_BIN_WIDTH_SECS;
+    let amplitude_of_mode = *modal_count as f32 / rr_secs.len() as f32 * 100.0;
+    amplitude_of_mode / (2.0 * mode * spread)
+}
+
+struct HrvActor {
+    engine: HrvEngine,
+    broadcast_tx: BSender<AppUpdate>,
+}
+
+impl HrvActor {
+    async fn rx_loop(
+        &mut self,
+        mut broadcast_rx: BReceiver<AppUpdate>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), AppError> {
+        loop {
+            tokio::select! {
+                hr_data = broadcast_rx.recv() => {
+                    match hr_data {
+                        Ok(AppUpdate::HeartRateStatus(data)) => {
+                            if let Some(metrics) = self.engine.update(&data) {
+                                broadcast!(self.broadcast_tx, metrics);
+                            }
+                        },
+                        Ok(_) => {},
+                        Err(RecvError::Closed) => {
+                            error!("HRV: Channel closed");
+                            break;
+                        },
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("HRV: Lagged! Missed {count} messages");
+                        }
+                    }
+                }
+                _ = cancel_token.cancelled() => {
+                    info!("Shutting down HRV thread! ({} RR artifacts rejected)", self.engine.artifacts);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn hrv_thread(
+    broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    hrv_settings: HrvSettings,
+    cancel_token: CancellationToken,
+) {
+    let mut hrv = HrvActor {
+        engine: HrvEngine::new(&hrv_settings),
+        broadcast_tx: broadcast_tx.clone(),
+    };
+
+    if let Err(e) = hrv.rx_loop(broadcast_rx, cancel_token).await {
+        error!("HRV Error: {e}");
+        let message = "HRV Error";
+        broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const RR: [f32; 6] = [800.0, 810.0, 790.0, 860.0, 800.0, 820.0];
+
+    fn engine(window_secs: u16) -> HrvEngine {
+        HrvEngine::new(&HrvSettings {
+            window_secs,
+            frequency_window_secs: 120,
+            respiration_window_secs: 60,
+        })
+    }
+
+    fn status(rr_ms: &[u64]) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: 75,
+            rr_intervals: rr_ms.iter().map(|ms| Duration::from_millis(*ms)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn time_domain() {
+        // Differences: 10, -20, 70, -60, 20
+        let expected_rmssd = ((100.0 + 400.0 + 4900.0 + 3600.0 + 400.0) / 5.0f32).sqrt();
+        assert!((rmssd(&RR) - expected_rmssd).abs() < 0.01);
+        assert_eq!(pnn50(&RR), 40.0);
+        // Mean is 813.33
+        assert!((sdnn(&RR) - 25.03).abs() < 0.01);
+    }
+
+    #[test]
+    fn stress_index_known() {
+        // Spread of 70ms makes two bins: 790-839 has 5 beats, 840-889 has 1
+        // Mo = 0.815, AMo = 83.33%, MxDMn = 0.07
+        let expected = (5.0 / 6.0 * 100.0) / (2.0 * 0.815 * 0.07);
+        assert!((stress_index(&RR) - expected).abs() < 0.1);
+        // No variability at all, don't divide by zero
+        assert_eq!(stress_index(&[800.0; 5]), 0.0);
+    }
+
+    #[test]
+    fn waits_for_enough_beats() {
+        let mut engine = engine(60);
+        assert!(engine.update(&status(&[800; 9])).is_none());
+        let metrics = engine.update(&status(&[800])).unwrap();
+        assert_eq!(metrics.beats, 10);
+        assert_eq!(metrics.rmssd_ms, 0.0);
+    }
+
+    #[test]
+    fn rejects_artifacts() {
+        let mut engine = engine(60);
+        engine.update(&status(&[800, 810, 790, 805, 795]));
+        // A missed beat (double length) and an extra beat flagged by the source,
+        // and an impossible value that wasn't
+        let mut with_artifacts = status(&[1600, 400, 3000, 800, 810, 790, 805, 795]);
+        with_artifacts.rr_flags = vec![RrFlag::Flagged, RrFlag::Flagged];
+        engine.update(&with_artifacts);
+        let metrics = engine.update(&status(&[800])).unwrap();
+        assert_eq!(metrics.artifacts, 3);
+        assert_eq!(metrics.beats, 11);
+        assert!(metrics.rmssd_ms < 20.0);
+    }
+
+    #[test]
+    fn window_and_disconnect() {
+        let mut engine = engine(10);
+        engine.update(&status(&[1000; 20]));
+        let metrics = engine.update(&status(&[1000])).unwrap();
+        assert_eq!(metrics.beats, 10);
+        // Disconnecting starts over
+        let mut disconnected = status(&[]);
+        disconnected.heart_rate_bpm = 0;
+        assert!(engine.update(&disconnected).is_none());
+        assert!(engine.update(&status(&[1000])).is_none());
+    }
+
+    #[test]
+    fn frequency_waits_for_full_window() {
+        let mut engine = engine(60);
+        let metrics = engine.update(&status(&[1000; 100])).unwrap();
+        assert!(metrics.frequency.is_none());
+        let metrics = engine.update(&status(&[1000; 21])).unwrap();
+        let frequency = metrics.frequency.unwrap();
+        assert_eq!(frequency.lf_power, 0.0);
+        assert!(!frequency.spectrum.is_empty());
+    }
+
+    #[test]
+    fn spectrum_refreshes_once_per_second_of_beats() {
+        let mut engine = engine(60);
+        let first = engine
+            .update(&status(&[1000; 121]))
+            .unwrap()
+            .frequency
+            .unwrap();
+        // Half a second of beats reuses the same spectrum
+        let metrics = engine.update(&status(&[500])).unwrap();
+        assert!(Arc::ptr_eq(
+            &first.spectrum,
+            &metrics.frequency.unwrap().spectrum
+        ));
+        let metrics = engine.update(&status(&[500])).unwrap();
+        assert!(!Arc::ptr_eq(
+            &first.spectrum,
+            &metrics.frequency.unwrap().spectrum
+        ));
+    }
+}
@@ -0,0 +1,84 @@
+use super::frequency::{FREQUENCY_STEP, HF_BAND};
+
+// How far the peak has to stand above the band's average before we trust it
+const MIN_PEAK_PROMINENCE: f64 = 2.0;
+
+/// Estimates breaths per minute from respiratory sinus arrhythmia
+/// (heart rate rising while breathing in, and falling while breathing out).
+///
+/// Picks the strongest frequency in the HF band (9 - 24 breaths per minute) of `spectrum`,
+/// returning None if there isn't a clear peak. Slower breathing lands in the LF band,
+/// where it can't be told apart from blood pressure regulation.
+pub fn respiration_rate(spectrum: &[(f64, f64)]) -> Option<f32> {
+    let (low, high) = HF_BAND;
+    let band: Vec<usize> = (0..spectrum.len())
+        .filter(|&i| (low..=high).contains(&spectrum[i].0))
+        .collect();
+    let peak = *band
+        .iter()
+        .max_by(|&&a, &&b| spectrum[a].1.total_cmp(&spectrum[b].1))?;
+    // Highest at the edge just means the power is coming from outside the band
+    if Some(&peak) == band.first() || Some(&peak) == band.last() {
+        return None;
+    }
+    let band_average = band.iter().map(|&i| spectrum[i].1).sum::<f64>() / band.len() as f64;
+    if spectrum[peak].1 <= 0.0 || spectrum[peak].1 < band_average * MIN_PEAK_PROMINENCE {
+        return None;
+    }
+
+    // Fit a parabola through the peak and its neighbours, since the real rate
+    // is almost never exactly on one of the frequencies we checked
+    let mut frequency = spectrum[peak].0;
+    if let (Some(before), Some(after)) = (
+        peak.checked_sub(1).map(|i| spectrum[i].1),
+        spectrum.get(peak + 1).map(|(_, density)| *density),
+    ) {
+        let curvature = before - 2.0 * spectrum[peak].1 + after;
+        if curvature < 0.0 {
+            frequency += 0.5 * (before - after) / curvature * FREQUENCY_STEP;
+        }
+    }
+    Some((frequency * 60.0) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frequency::power_spectrum;
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn breathing_spectrum(breaths_per_minute: f64, secs: f64) -> Vec<(f64, f64)> {
+        let hz = breaths_per_minute / 60.0;
+        let mut rr_ms = Vec::new();
+        let mut elapsed = 0.0;
+        while elapsed < secs {
+            let rr = 900.0 + 40.0 * (2.0 * PI * hz * elapsed).sin();
+            elapsed += rr / 1000.0;
+            rr_ms.push(rr as f32);
+        }
+        power_spectrum(&rr_ms)
+    }
+
+    #[test]
+    fn normal_breathing() {
+        let rate = respiration_rate(&breathing_spectrum(15.0, 60.0)).unwrap();
+        assert!((rate - 15.0).abs() < 0.5, "{rate}");
+    }
+
+    #[test]
+    fn fast_breathing() {
+        let rate = respiration_rate(&breathing_spectrum(22.0, 60.0)).unwrap();
+        assert!((rate - 22.0).abs() < 0.5, "{rate}");
+    }
+
+    #[test]
+    fn slow_breathing_is_outside_hf() {
+        // Resonance breathing (around 6 breaths per minute) shows up as LF power instead
+        assert_eq!(respiration_rate(&breathing_spectrum(6.0, 90.0)), None);
+    }
+
+    #[test]
+    fn no_modulation() {
+        assert_eq!(respiration_rate(&power_spectrum(&[900.0; 80])), None);
+    }
+}
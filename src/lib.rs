@@ -2,7 +2,8 @@
 #[macro_use]
 extern crate lazy_static;
 
-use args::TopLevelCmd;
+use activities::{read_activity_names, ACTIVITIES_TOML_PATH};
+use args::{AnalyzeCmd, ExportCmd, TopLevelCmd};
 use errors::AppError;
 use ratatui::{backend::CrosstermBackend, Terminal};
 use self_update::cargo_crate_version;
@@ -10,7 +11,7 @@ use std::{io, path::PathBuf};
 use tokio::fs::create_dir;
 use tokio_util::sync::CancellationToken;
 
-use crate::app::App;
+use crate::app::{App, ErrorPopup};
 use event::{Event, EventHandler};
 use handler::handle_key_events;
 use std::error;
@@ -29,20 +30,29 @@ pub mod args;
 pub mod errors;
 
 mod activities;
+mod analysis;
 mod app;
 mod company_codes;
+mod export;
 mod heart_rate;
+mod hrv;
 mod logging;
 mod macros;
+mod mqtt;
 mod osc;
 mod panic_handler;
 mod scan;
+mod session_logs;
 mod settings;
+mod stats;
 mod structs;
+mod summary;
+mod training;
 mod updates;
 mod utils;
 mod vrcx;
 mod widgets;
+mod zones;
 
 mod event;
 mod handler;
@@ -103,6 +113,11 @@ pub async fn run_tui(mut arg_config: TopLevelCmd) -> AppResult<()> {
 
     let mut app = App::build(&arg_config, None);
 
+    if arg_config.csv_stdout {
+        reload_handle.modify(|layer| *layer.filter_mut() = app.settings.get_log_level())?;
+        return run_csv_stdout(app, &arg_config).await;
+    }
+
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
@@ -147,6 +162,22 @@ pub async fn run_tui(mut arg_config: TopLevelCmd) -> AppResult<()> {
     // After while loop closes
     app.join_threads().await;
 
+    if app.finish_session().await {
+        // Anything pressed while the threads were closing shouldn't skip past the summary
+        tui.events.discard_pending();
+        tui.draw(&mut app)?;
+        loop {
+            match tui.events.next().await? {
+                Event::Key(_) => break,
+                Event::Resize => {
+                    tui.autoresize()?;
+                    tui.draw(&mut app)?;
+                }
+                Event::Tick => {}
+            }
+        }
+    }
+
     info!("Shutting down gracefully...");
 
     // Reset the terminal.
@@ -180,12 +211,103 @@ pub async fn run_headless(
     info!("Joining...");
     // After while loop closes
     app.join_threads().await;
+    app.finish_session().await;
+
+    info!("Shutting down gracefully...");
+
+    Ok(())
+}
+
+/// Streams the session CSV to stdout instead of showing the TUI, until Ctrl+C or a fatal error
+async fn run_csv_stdout(mut app: App, arg_config: &TopLevelCmd) -> AppResult<()> {
+    if let Some(error) = app.error_message.take() {
+        eprintln!("{}", error_text(&error));
+        return Ok(());
+    }
+    app.settings.csv.to_stdout = true;
+
+    info!(
+        "Starting app with CSV on stdout... v{}",
+        cargo_crate_version!()
+    );
+
+    app.init(arg_config).await;
+
+    while !app.cancel_app.is_cancelled() && !app.cancel_actors.is_cancelled() {
+        tokio::select! {
+            data = app.app_receivers() => app.app_handlers(data).await,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        // Nobody's around to dismiss these, so just mention them
+        if let Some(error) = app.error_message.take() {
+            eprintln!("{}", error_text(&error));
+            if matches!(
+                error,
+                ErrorPopup::Fatal(_) | ErrorPopup::FatalDetailed(_, _)
+            ) {
+                break;
+            }
+        }
+    }
+    app.join_threads().await;
+    app.finish_session().await;
 
     info!("Shutting down gracefully...");
 
     Ok(())
 }
 
+fn error_text(error: &ErrorPopup) -> String {
+    match error {
+        ErrorPopup::Intermittent(message)
+        | ErrorPopup::UserMustDismiss(message)
+        | ErrorPopup::Fatal(message) => message.clone(),
+        ErrorPopup::FatalDetailed(message, details) => format!("{message} {details}"),
+    }
+}
+
+/// Analyzes session CSV logs and prints the report to stdout, for `iron-heart analyze`
+pub fn run_analyze(cmd: &AnalyzeCmd) -> Result<(), AppError> {
+    if cmd.files.is_empty() {
+        return Err(AppError::NothingToAnalyze);
+    }
+    // Same activities.toml the TUI uses, no matter where this is run from
+    let activities_path = match &cmd.activities {
+        Some(path) => path.clone(),
+        None => determine_working_directory()
+            .ok_or(AppError::WorkDir)?
+            .join(ACTIVITIES_TOML_PATH),
+    };
+    let activity_names = if cmd.activities.is_some() || activities_path.exists() {
+        read_activity_names(&activities_path)?
+    } else {
+        Default::default()
+    };
+    let report = analysis::analyze_files(&cmd.files, &activity_names)?;
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.to_table());
+    }
+    Ok(())
+}
+
+/// Converts session CSV logs to workout files, for `iron-heart export`
+pub fn run_export(cmd: &ExportCmd) -> Result<(), AppError> {
+    if cmd.files.is_empty() {
+        return Err(AppError::NothingToExport);
+    }
+    if cmd.format.is_empty() {
+        return Err(AppError::NoExportFormat);
+    }
+    for file in &cmd.files {
+        for path in export::export_session_log(file, &cmd.format, cmd.out_dir.as_deref())? {
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
+
 /// Returns the directory that logs, config, and other files should be placed in by default.
 // The rules for how it determines the directory is as follows:
 // If the app is built with the portable feature, it will just return it's parent directory.
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Local, SecondsFormat};
+
+use crate::errors::AppError;
+use crate::settings::{CsvColumn, CsvSettings, CsvTimestampFormat};
+
+use super::CSV_DELIMITERS;
+
+/// Everything that can go in a session CSV row
+#[derive(Debug, Clone, Default)]
+pub(super) struct CsvData {
+    pub timestamp: DateTime<Local>,
+    pub bpm: u16,
+    pub rr_ms: u16,
+    /// RR intervals in this update that were corrected or flagged
+    pub artifacts: u8,
+    pub battery: u8,
+    pub twitch_up: bool,
+    pub twitch_down: bool,
+    pub activity: u8,
+    pub activity_name: String,
+    pub zone: u8,
+    pub rmssd: f32,
+    pub sdnn: f32,
+    pub pnn50: f32,
+    pub stress_index: f32,
+    pub lf: f32,
+    pub hf: f32,
+    pub lf_hf: f32,
+    /// Blank until there's an estimate
+    pub breaths_per_min: Option<f32>,
+}
+
+/// Turns [`CsvData`] into rows, with the columns and timestamps set up in the config
+#[derive(Debug)]
+pub(super) struct CsvSchema {
+    columns: Vec<CsvColumn>,
+    timestamp_format: CsvTimestampFormat,
+    delimiter: u8,
+    first_timestamp: Option<DateTime<Local>>,
+}
+
+impl CsvSchema {
+    pub fn build(settings: &CsvSettings) -> Result<Self, AppError> {
+        if settings.columns.is_empty() {
+            return Err(AppError::CsvSchema("columns is empty".into()));
+        }
+        let delimiter = match settings.delimiter.as_bytes() {
+            [delimiter] if CSV_DELIMITERS.contains(delimiter) => *delimiter,
+            _ => {
+                return Err(AppError::CsvSchema(format!(
+                    "delimiter must be one of \",\" \";\" \"\\t\" or \"|\", not {:?}",
+                    settings.delimiter
+                )))
+            }
+        };
+        Ok(Self {
+            columns: settings.columns.clone(),
+            timestamp_format: settings.timestamp_format,
+            delimiter,
+            first_timestamp: None,
+        })
+    }
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+    pub fn header(&self) -> Vec<&'static str> {
+        self.columns.iter().map(CsvColumn::header).collect()
+    }
+    pub fn record(&mut self, data: &CsvData) -> Vec<String> {
+        let first_timestamp = *self.first_timestamp.get_or_insert(data.timestamp);
+        self.columns
+            .iter()
+            .map(|column| match column {
+                CsvColumn::Timestamp => self.timestamp(data.timestamp, first_timestamp),
+                CsvColumn::Bpm => data.bpm.to_string(),
+                CsvColumn::Rr => data.rr_ms.to_string(),
+                CsvColumn::Artifacts => data.artifacts.to_string(),
+                CsvColumn::Battery => data.battery.to_string(),
+                CsvColumn::TwitchUp => (data.twitch_up as u8).to_string(),
+                CsvColumn::TwitchDown => (data.twitch_down as u8).to_string(),
+                CsvColumn::Activity => data.activity.to_string(),
+                CsvColumn::ActivityName => data.activity_name.clone(),
+                CsvColumn::Zone => data.zone.to_string(),
+                CsvColumn::Rmssd => float(round_to_tenth(data.rmssd)),
+                CsvColumn::Sdnn => float(round_to_tenth(data.sdnn)),
+                CsvColumn::Pnn50 => float(round_to_tenth(data.pnn50)),
+                CsvColumn::StressIndex => float(round_to_tenth(data.stress_index)),
+                CsvColumn::Lf => float(round_to_tenth(data.lf)),
+                CsvColumn::Hf => float(round_to_tenth(data.hf)),
+                CsvColumn::LfHf => float((data.lf_hf * 100.0).round() / 100.0),
+                CsvColumn::BreathsPerMin => data
+                    .breaths_per_min
+                    .map(|breaths| float(round_to_tenth(breaths)))
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+    fn timestamp(&self, timestamp: DateTime<Local>, first: DateTime<Local>) -> String {
+        match self.timestamp_format {
+            CsvTimestampFormat::Local => timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            CsvTimestampFormat::Iso8601 => timestamp.to_rfc3339_opts(SecondsFormat::Millis, false),
+            CsvTimestampFormat::UnixMs => timestamp.timestamp_millis().to_string(),
+            CsvTimestampFormat::ElapsedSecs => {
+                let millis = (timestamp - first).num_milliseconds();
+                format!("{:.3}", millis as f64 / 1000.0)
+            }
+        }
+    }
+}
+
+// Same as how they were written before columns could be picked, so `0.0` instead of `0`
+fn float(value: f32) -> String {
+    format!("{value:?}")
+}
+
+fn round_to_tenth(value: f32) -> f32 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    fn settings(columns: &[CsvColumn], timestamp_format: CsvTimestampFormat) -> CsvSettings {
+        CsvSettings {
+            columns: columns.to_vec(),
+            timestamp_format,
+            delimiter: ",".into(),
+            ..Default::default()
+        }
+    }
+
+    fn data(offset_ms: u64) -> CsvData {
+        CsvData {
+            timestamp: Local.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+                + Duration::from_millis(offset_ms),
+            bpm: 80,
+            rr_ms: 750,
+            activity: 2,
+            activity_name: "Dancing".into(),
+            rmssd: 42.26,
+            lf_hf: 1.234,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn all_columns_header_and_record() {
+        let mut schema =
+            CsvSchema::build(&settings(&CsvColumn::ALL, CsvTimestampFormat::Local)).unwrap();
+        assert_eq!(
+            schema.header().join(","),
+            "Timestamp,BPM,RR,Artifacts,Battery,TwitchUp,TwitchDown,Activity,ActivityName,Zone,RMSSD,SDNN,pNN50,StressIndex,LF,HF,LFHF,BreathsPerMin"
+        );
+        assert_eq!(
+            schema.record(&data(0)).join(","),
+            "2024-05-01 10:00:00,80,750,0,0,0,0,2,Dancing,0,42.3,0.0,0.0,0.0,0.0,0.0,1.23,"
+        );
+    }
+
+    #[test]
+    fn picked_columns_in_order() {
+        let columns = [CsvColumn::ActivityName, CsvColumn::Bpm, CsvColumn::Activity];
+        let mut schema = CsvSchema::build(&settings(&columns, CsvTimestampFormat::Local)).unwrap();
+        assert_eq!(schema.header(), vec!["ActivityName", "BPM", "Activity"]);
+        assert_eq!(schema.record(&data(0)), vec!["Dancing", "80", "2"]);
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let timestamps = |format| {
+            let mut schema = CsvSchema::build(&settings(&[CsvColumn::Timestamp], format)).unwrap();
+            [schema.record(&data(0)), schema.record(&data(1250))].map(|row| row[0].clone())
+        };
+        let [_, iso] = timestamps(CsvTimestampFormat::Iso8601);
+        let parsed = DateTime::parse_from_rfc3339(&iso).unwrap();
+        assert_eq!(parsed, data(1250).timestamp);
+        assert!(iso.contains(".250"), "{iso}");
+
+        let [first, second] = timestamps(CsvTimestampFormat::UnixMs);
+        assert_eq!(
+            second.parse::<i64>().unwrap() - first.parse::<i64>().unwrap(),
+            1250
+        );
+
+        assert_eq!(
+            timestamps(CsvTimestampFormat::ElapsedSecs),
+            ["0.000", "1.250"]
+        );
+    }
+
+    #[test]
+    fn bad_settings() {
+        assert!(CsvSchema::build(&settings(&[], CsvTimestampFormat::Local)).is_err());
+        // Anything the session log reader couldn't pick out again
+        for delimiter in ["", ",,", "a", "é", ":", "#", " "] {
+            let mut settings = settings(&CsvColumn::ALL, CsvTimestampFormat::Local);
+            settings.delimiter = delimiter.into();
+            assert!(CsvSchema::build(&settings).is_err(), "{delimiter}");
+        }
+        let mut tabs = settings(&CsvColumn::ALL, CsvTimestampFormat::Local);
+        tabs.delimiter = "\t".into();
+        assert_eq!(CsvSchema::build(&tabs).unwrap().delimiter(), b'\t');
+    }
+}
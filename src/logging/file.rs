@@ -1,56 +1,83 @@
 use crate::app::AppUpdate;
+use crate::broadcast;
 use crate::errors::AppError;
-use crate::heart_rate::HeartRateStatus;
-use crate::settings::MiscSettings;
+use crate::export::export_session_log;
+use crate::heart_rate::{HeartRateStatus, RrFlag};
+use crate::hrv::HrvMetrics;
+use crate::settings::{CsvSettings, MiscSettings};
+use crate::zones::HrZones;
 
-use csv_async::AsyncSerializer;
-use serde::Serialize;
-use std::path::PathBuf;
+use super::csv_schema::{CsvData, CsvSchema};
+use super::rotation::{RotationRules, Segment};
+use super::rr_series::RrSeriesLogger;
+
+use chrono::{DateTime, Local};
+use csv_async::{AsyncSerializer, AsyncWriterBuilder};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::fs::{create_dir, File};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::sync::broadcast::error::RecvError;
-use tokio::sync::broadcast::Receiver as BReceiver;
+use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
-const CSV_FILE_PREFIX: &str = "nih-";
-
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize)]
-struct CsvData {
-    Timestamp: String,
-    BPM: u16,
-    RR: u16,
-    Battery: u8,
-    TwitchUp: u8,
-    TwitchDown: u8,
-    Activity: u8,
-}
-
 pub(super) struct FileLoggingActor {
+    broadcast_tx: BSender<AppUpdate>,
     misc_settings: MiscSettings,
-    csv_writer: Option<AsyncSerializer<File>>,
+    csv_settings: CsvSettings,
+    zones: HrZones,
+    csv_writer: Option<AsyncSerializer<Box<dyn AsyncWrite + Unpin + Send>>>,
+    csv_schema: Option<CsvSchema>,
+    // None when writing to stdout
     csv_path: Option<PathBuf>,
+    csv_folder: PathBuf,
+    // Stdout never gets rotated
+    csv_rotation: Option<RotationRules>,
+    csv_segment: Option<Segment>,
+    csv_part: u32,
     txt_writer: Option<BufWriter<File>>,
     txt_path: Option<PathBuf>,
+    rr_series: Option<RrSeriesLogger>,
     files_initialized: bool,
     // Loop-specific vars
     last_rr: Duration,
+    last_data: Option<DateTime<Local>>,
     activity: u8,
+    activity_name: String,
+    hrv: HrvMetrics,
 }
 
 impl FileLoggingActor {
-    pub(super) fn new(initial_activity: u8, misc_settings: MiscSettings) -> Self {
+    pub(super) fn new(
+        broadcast_tx: BSender<AppUpdate>,
+        initial_activity: u8,
+        initial_activity_name: String,
+        misc_settings: MiscSettings,
+        csv_settings: CsvSettings,
+        zones: HrZones,
+    ) -> Self {
         Self {
+            broadcast_tx,
             misc_settings,
+            csv_settings,
+            zones,
             csv_writer: None,
+            csv_schema: None,
             csv_path: None,
+            csv_folder: PathBuf::new(),
+            csv_rotation: None,
+            csv_segment: None,
+            csv_part: 0,
             txt_writer: None,
             txt_path: None,
+            rr_series: None,
             last_rr: Duration::from_secs(0),
+            last_data: None,
             files_initialized: false,
             activity: initial_activity,
+            activity_name: initial_activity_name,
+            hrv: HrvMetrics::default(),
         }
     }
     pub(super) async fn rx_loop(
@@ -65,9 +92,13 @@ impl FileLoggingActor {
                         Ok(AppUpdate::HeartRateStatus(data)) => {
                             self.handle_data(data).await?;
                         },
-                        Ok(AppUpdate::ActivitySelected(index)) => {
-                            // Dunno if I want to trigger a CSV save here
+                        Ok(AppUpdate::ActivitySelected(index, name)) => {
+                            // If that starts a new CSV, it's done with the next update
                             self.activity = index;
+                            self.activity_name = name;
+                        },
+                        Ok(AppUpdate::Hrv(hrv)) => {
+                            self.hrv = hrv;
                         },
                         Ok(_) => {},
                         Err(RecvError::Closed) => {
@@ -81,81 +112,216 @@ impl FileLoggingActor {
                 }
                 _ = cancel_token.cancelled() => {
                     info!("Logging thread shutting down");
+                    if let Some(rr_series) = self.rr_series.take() {
+                        rr_series.finish().await?;
+                    }
+                    self.close_csv().await?;
                     return Ok(());
                 }
             }
         }
     }
-    async fn initialize_files(&mut self) -> Result<(), AppError> {
+    // Named after the first update
+    async fn initialize_files(&mut self, start: DateTime<Local>) -> Result<(), AppError> {
         let txt_path = self.misc_settings.bpm_file_path.clone();
 
         let csv_folder = PathBuf::from(self.misc_settings.log_sessions_csv_path.clone());
-        let mut csv_file_path = csv_folder.clone();
-        let csv_file_name = format!(
-            "{}{}.csv",
-            CSV_FILE_PREFIX,
-            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
-        );
-        csv_file_path.push(csv_file_name);
 
-        if self.misc_settings.log_sessions_to_csv {
-            if !csv_folder.exists() {
-                create_dir(&csv_folder)
-                    .await
-                    .map_err(|e| AppError::CreateDir {
-                        path: csv_folder,
-                        source: e,
-                    })?;
+        if ((self.misc_settings.log_sessions_to_csv && !self.csv_settings.to_stdout)
+            || self.misc_settings.log_rr_series)
+            && !csv_folder.exists()
+        {
+            create_dir(&csv_folder)
+                .await
+                .map_err(|e| AppError::CreateDir {
+                    path: csv_folder.clone(),
+                    source: e,
+                })?;
+        }
+        self.csv_folder = csv_folder;
+        if self.misc_settings.log_sessions_to_csv || self.csv_settings.to_stdout {
+            if !self.csv_settings.to_stdout {
+                self.csv_rotation = Some(RotationRules::build(&self.csv_settings)?);
             }
-            self.csv_writer = Some(AsyncSerializer::from_writer(
-                File::create(&csv_file_path)
-                    .await
-                    .map_err(|e| AppError::CreateFile {
-                        path: csv_file_path,
-                        source: e,
-                    })?,
-            ));
+            self.open_csv(start).await?;
+        }
+        if self.misc_settings.log_rr_series {
+            // Named like the session's first CSV, even when there isn't one
+            let stem = match self.csv_path.as_deref().and_then(Path::file_stem) {
+                Some(csv_stem) => csv_stem.to_string_lossy().into_owned(),
+                None => RotationRules::build(&self.csv_settings)?.file_stem(
+                    start,
+                    self.activity,
+                    &self.activity_name,
+                    1,
+                ),
+            };
+            self.rr_series = Some(RrSeriesLogger::create(&self.csv_folder, &stem).await?);
         }
         if self.misc_settings.write_bpm_to_file {
             let file = File::create(&txt_path)
                 .await
                 .map_err(|e| AppError::CreateFile {
-                    path: PathBuf::from(txt_path),
+                    path: PathBuf::from(&txt_path),
                     source: e,
                 })?;
             self.txt_writer = Some(BufWriter::new(file));
+            self.txt_path = Some(PathBuf::from(txt_path));
         }
         self.files_initialized = true;
         Ok(())
     }
+    async fn open_csv(&mut self, start: DateTime<Local>) -> Result<(), AppError> {
+        // Fresh schema, so elapsed timestamps restart with each file
+        let schema = CsvSchema::build(&self.csv_settings)?;
+        self.csv_part += 1;
+        let output: Box<dyn AsyncWrite + Unpin + Send> = match &self.csv_rotation {
+            Some(rotation) => {
+                let stem =
+                    rotation.file_stem(start, self.activity, &self.activity_name, self.csv_part);
+                let mut csv_file_path = self.csv_folder.join(format!("{stem}.csv"));
+                // Two files started in the same second with the same name
+                let mut duplicate = 1;
+                while csv_file_path.exists() {
+                    duplicate += 1;
+                    csv_file_path = self.csv_folder.join(format!("{stem}-{duplicate}.csv"));
+                }
+                let file =
+                    File::create(&csv_file_path)
+                        .await
+                        .map_err(|e| AppError::CreateFile {
+                            path: csv_file_path.clone(),
+                            source: e,
+                        })?;
+                info!("Logging session to {}", csv_file_path.display());
+                broadcast!(
+                    self.broadcast_tx,
+                    AppUpdate::SessionLogOpened(csv_file_path.clone())
+                );
+                self.csv_path = Some(csv_file_path);
+                Box::new(file)
+            }
+            None => Box::new(tokio::io::stdout()),
+        };
+        let mut csv_writer = AsyncWriterBuilder::new()
+            .delimiter(schema.delimiter())
+            .create_serializer(output);
+        csv_writer.write_record(schema.header()).await?;
+        self.csv_writer = Some(csv_writer);
+        self.csv_schema = Some(schema);
+        self.csv_segment = Some(Segment {
+            start,
+            activity: self.activity,
+            bytes: 0,
+        });
+        Ok(())
+    }
+    /// Flushes the current CSV and converts it to `misc.export_formats`
+    async fn close_csv(&mut self) -> Result<(), AppError> {
+        self.csv_schema = None;
+        self.csv_segment = None;
+        let Some(mut csv_writer) = self.csv_writer.take() else {
+            return Ok(());
+        };
+        let csv_path = self.csv_path.take();
+        csv_writer.flush().await.map_err(|e| AppError::WriteFile {
+            path: csv_path.clone().unwrap_or_else(|| PathBuf::from("stdout")),
+            source: e,
+        })?;
+        if let Some(csv_path) = csv_path {
+            if !self.misc_settings.export_formats.is_empty() {
+                // Reads the whole CSV back in, so keep it off the runtime
+                let formats = self.misc_settings.export_formats.clone();
+                let export = tokio::task::spawn_blocking(move || {
+                    export_session_log(&csv_path, &formats, None)
+                })
+                .await?;
+                match export {
+                    Ok(paths) => info!("Session exported to {paths:?}"),
+                    Err(e) => error!("Failed to export session: {e}"),
+                }
+            }
+        }
+        Ok(())
+    }
+    async fn rotate_csv_if_needed(&mut self, now: DateTime<Local>) -> Result<(), AppError> {
+        let (Some(rotation), Some(segment), Some(last_data)) =
+            (&self.csv_rotation, &self.csv_segment, self.last_data)
+        else {
+            return Ok(());
+        };
+        if let Some(reason) = rotation.check(segment, now, last_data, self.activity) {
+            info!("Starting a new session CSV: {reason:?}");
+            self.close_csv().await?;
+            self.open_csv(now).await?;
+        }
+        Ok(())
+    }
     async fn handle_data(&mut self, heart_rate_status: HeartRateStatus) -> Result<(), AppError> {
         if heart_rate_status.heart_rate_bpm == 0 {
             return Ok(());
         }
         if !self.files_initialized {
-            self.initialize_files().await?;
+            self.initialize_files(heart_rate_status.timestamp).await?;
         }
-        let reported_rr = heart_rate_status
-            .rr_intervals
-            .last()
-            .unwrap_or(&self.last_rr);
+        let reported_rr = heart_rate_status.latest_clean_rr().unwrap_or(self.last_rr);
 
-        if let Some(csv_writer) = &mut self.csv_writer {
-            let timestamp = heart_rate_status.timestamp.format("%Y-%m-%d %H:%M:%S");
+        if let Some(rr_series) = &mut self.rr_series {
+            rr_series.record(&heart_rate_status).await?;
+        }
+
+        self.rotate_csv_if_needed(heart_rate_status.timestamp)
+            .await?;
+        if let (Some(csv_writer), Some(csv_schema)) = (&mut self.csv_writer, &mut self.csv_schema) {
+            let (lf_power, hf_power, lf_hf_ratio) = self
+                .hrv
+                .frequency
+                .as_ref()
+                .map_or((0.0, 0.0, 0.0), |f| (f.lf_power, f.hf_power, f.lf_hf_ratio));
             let csv_data = CsvData {
-                Timestamp: timestamp.to_string(),
-                BPM: heart_rate_status.heart_rate_bpm,
-                RR: reported_rr.as_millis() as u16,
-                Battery: heart_rate_status.battery_level.into(),
-                TwitchUp: heart_rate_status.twitch_up as u8,
-                TwitchDown: heart_rate_status.twitch_down as u8,
-                Activity: self.activity,
+                timestamp: heart_rate_status.timestamp,
+                bpm: heart_rate_status.heart_rate_bpm,
+                rr_ms: reported_rr.as_millis() as u16,
+                artifacts: heart_rate_status
+                    .rr_flags
+                    .iter()
+                    .filter(|flag| **flag != RrFlag::Normal)
+                    .count() as u8,
+                battery: heart_rate_status.battery_level.into(),
+                twitch_up: heart_rate_status.twitch_up,
+                twitch_down: heart_rate_status.twitch_down,
+                activity: self.activity,
+                activity_name: self.activity_name.clone(),
+                zone: self.zones.zone_of(heart_rate_status.heart_rate_bpm),
+                rmssd: self.hrv.rmssd_ms,
+                sdnn: self.hrv.sdnn_ms,
+                pnn50: self.hrv.pnn50,
+                stress_index: self.hrv.stress_index,
+                lf: lf_power,
+                hf: hf_power,
+                lf_hf: lf_hf_ratio,
+                breaths_per_min: self.hrv.respiration_rate,
             };
-            csv_writer.serialize(csv_data).await?;
+            csv_writer
+                .write_record(csv_schema.record(&csv_data))
+                .await?;
             csv_writer.flush().await.map_err(|e| AppError::WriteFile {
-                path: self.csv_path.as_ref().unwrap().to_owned(),
+                path: self
+                    .csv_path
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("stdout")),
                 source: e,
             })?;
+            if let (Some(rotation), Some(segment), Some(csv_path)) =
+                (&self.csv_rotation, &mut self.csv_segment, &self.csv_path)
+            {
+                if rotation.has_size_limit() {
+                    segment.bytes = tokio::fs::metadata(csv_path)
+                        .await
+                        .map(|metadata| metadata.len())
+                        .unwrap_or_default();
+                }
+            }
         }
         if let Some(txt_writer) = &mut self.txt_writer {
             let txt_output = if self.misc_settings.write_rr_to_file {
@@ -186,7 +352,8 @@ impl FileLoggingActor {
                 source: e,
             })?;
         }
-        self.last_rr = *reported_rr;
+        self.last_rr = reported_rr;
+        self.last_data = Some(heart_rate_status.timestamp);
 
         Ok(())
     }
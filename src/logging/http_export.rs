@@ -0,0 +1,230 @@
+use crate::app::AppUpdate;
+use crate::errors::AppError;
+use crate::heart_rate::{BatteryLevel, HeartRateStatus};
+use crate::hrv::HrvMetrics;
+use crate::stats::SessionStats;
+use crate::zones::HrZones;
+
+use chrono::{DateTime, Local};
+use http::{header, HeaderName, HeaderValue};
+use reqwest::Client;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver as BReceiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything an exporter might want to send for one heart rate update
+#[derive(Debug, Clone, Default)]
+pub(super) struct ExportSample {
+    pub timestamp: DateTime<Local>,
+    pub device: String,
+    pub activity: u8,
+    pub activity_name: String,
+    pub bpm: u16,
+    /// Latest clean RR interval, falling back to the last one seen
+    pub rr_ms: f64,
+    pub battery: Option<u8>,
+    pub twitch_up: bool,
+    pub twitch_down: bool,
+    pub zone: u8,
+    pub hrv: HrvMetrics,
+    pub calories: Option<f32>,
+    pub trimp: f32,
+}
+
+/// Turns samples into request bodies, deciding for itself when there's enough to send
+pub(super) trait HttpExporter: Send + Sync {
+    /// Shown in logs and error popups
+    fn name(&self) -> &'static str;
+    fn content_type(&self) -> &'static str;
+    fn push(&mut self, sample: &ExportSample) -> Result<Option<Vec<u8>>, AppError>;
+    /// Whatever's left over when shutting down
+    fn flush(&mut self) -> Result<Option<Vec<u8>>, AppError>;
+}
+
+/// Shared by every HTTP exporter, keeps track of the app's state and POSTs whatever the exporter hands back
+pub(super) struct HttpExportActor {
+    exporter: Box<dyn HttpExporter>,
+    client: Client,
+    url: String,
+    zones: HrZones,
+    device: String,
+    activity: u8,
+    activity_name: String,
+    last_rr: Duration,
+    hrv: HrvMetrics,
+    session_stats: SessionStats,
+}
+
+impl HttpExportActor {
+    pub(super) fn build(
+        exporter: Box<dyn HttpExporter>,
+        url: &str,
+        headers: &[String],
+        initial_activity: u8,
+        initial_activity_name: String,
+        zones: HrZones,
+    ) -> Result<Self, AppError> {
+        let url = if url.contains("://") {
+            url.to_owned()
+        } else {
+            format!("http://{url}")
+        };
+        let mut header_map = parse_headers(headers)?;
+        if !header_map.contains_key(header::CONTENT_TYPE) {
+            header_map.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(exporter.content_type()),
+            );
+        }
+        let client = Client::builder()
+            .default_headers(header_map)
+            .timeout(SEND_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            exporter,
+            client,
+            url,
+            zones,
+            device: String::new(),
+            activity: initial_activity,
+            activity_name: initial_activity_name,
+            last_rr: Duration::from_secs(0),
+            hrv: HrvMetrics::default(),
+            session_stats: SessionStats::default(),
+        })
+    }
+
+    pub(super) fn name(&self) -> &'static str {
+        self.exporter.name()
+    }
+
+    pub(super) async fn rx_loop(
+        &mut self,
+        broadcast_rx: &mut BReceiver<AppUpdate>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), AppError> {
+        loop {
+            tokio::select! {
+                update = broadcast_rx.recv() => {
+                    match update {
+                        Ok(AppUpdate::HeartRateStatus(data)) => {
+                            self.handle_data(data).await?;
+                        },
+                        Ok(AppUpdate::ActivitySelected(index, name)) => {
+                            self.activity = index;
+                            self.activity_name = name;
+                        },
+                        Ok(AppUpdate::DeviceConnected(source)) => {
+                            self.device = source.label().to_owned();
+                        },
+                        Ok(AppUpdate::Hrv(hrv)) => {
+                            self.hrv = hrv;
+                        },
+                        Ok(AppUpdate::SessionStats(stats)) => {
+                            self.session_stats = stats;
+                        },
+                        Ok(_) => {},
+                        Err(RecvError::Closed) => {
+                            error!("{}: Channel closed", self.name());
+                            return Ok(());
+                        },
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("{}: Lagged! Missed {count} messages", self.name());
+                        }
+                    }
+                }
+                _ = cancel_token.cancelled() => {
+                    info!("{} thread shutting down", self.name());
+                    if let Some(body) = self.exporter.flush()? {
+                        self.send(body).await;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn handle_data(&mut self, heart_rate_status: HeartRateStatus) -> Result<(), AppError> {
+        if heart_rate_status.heart_rate_bpm == 0 {
+            return Ok(());
+        }
+        let reported_rr = heart_rate_status.latest_clean_rr().unwrap_or(self.last_rr);
+        self.last_rr = reported_rr;
+
+        let sample = ExportSample {
+            timestamp: heart_rate_status.timestamp,
+            device: self.device.clone(),
+            activity: self.activity,
+            activity_name: self.activity_name.clone(),
+            bpm: heart_rate_status.heart_rate_bpm,
+            rr_ms: reported_rr.as_secs_f64() * 1000.0,
+            battery: match heart_rate_status.battery_level {
+                BatteryLevel::Level(level) => Some(level),
+                _ => None,
+            },
+            twitch_up: heart_rate_status.twitch_up,
+            twitch_down: heart_rate_status.twitch_down,
+            zone: self.zones.zone_of(heart_rate_status.heart_rate_bpm),
+            hrv: self.hrv.clone(),
+            calories: self.session_stats.calories,
+            trimp: self.session_stats.trimp,
+        };
+
+        if let Some(body) = self.exporter.push(&sample)? {
+            self.send(body).await;
+        }
+
+        Ok(())
+    }
+
+    // Same as Prometheus, an unreachable server just gets logged
+    async fn send(&self, body: Vec<u8>) {
+        match self.client.post(&self.url).body(body).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                error!("{}: Server returned {}", self.name(), response.status());
+            }
+            Err(e) => {
+                error!("Error POSTing {} data! {e}", self.name());
+            }
+        }
+    }
+}
+
+/// Parses `"Key: Value"` pairs
+pub(super) fn parse_headers(headers: &[String]) -> Result<header::HeaderMap, AppError> {
+    let mut map = header::HeaderMap::new();
+    for line in headers.iter().filter(|line| !line.trim().is_empty()) {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or(AppError::MissingDelimiter)
+            .map(|(k, v)| (k.trim(), v.trim()))?;
+        map.append(HeaderName::from_str(key)?, HeaderValue::from_str(value)?);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let map = parse_headers(&[
+            "Authorization: Token abc:def".into(),
+            "".into(),
+            "X-Thing:1".into(),
+        ])
+        .unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["authorization"], "Token abc:def");
+        assert_eq!(map["x-thing"], "1");
+        assert!(parse_headers(&["No delimiter".into()]).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::errors::AppError;
+use crate::settings::InfluxSettings;
+
+use super::http_export::{ExportSample, HttpExporter};
+
+/// InfluxDB line protocol, which VictoriaMetrics (and others) accept too
+/// https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+pub(super) struct InfluxExporter {
+    measurement: String,
+    batch_size: usize,
+    lines: Vec<String>,
+}
+
+impl InfluxExporter {
+    pub fn build(settings: &InfluxSettings) -> Result<Self, AppError> {
+        if settings.measurement.trim().is_empty() {
+            return Err(AppError::EmptyInfluxMeasurement);
+        }
+        Ok(Self {
+            measurement: escape(&settings.measurement, &[',', ' ']),
+            batch_size: settings.batch_size.max(1),
+            lines: Vec::new(),
+        })
+    }
+
+    fn line(&self, sample: &ExportSample) -> String {
+        let mut line = self.measurement.clone();
+        // Empty tag values aren't allowed
+        for (key, value) in [
+            ("device", &sample.device),
+            ("activity", &sample.activity_name),
+        ] {
+            if !value.is_empty() {
+                line.push_str(&format!(",{key}={}", escape(value, &[',', '=', ' '])));
+            }
+        }
+
+        let hrv = &sample.hrv;
+        let mut fields = vec![
+            format!("bpm={}i", sample.bpm),
+            format!("activity_index={}i", sample.activity),
+            format!("zone={}i", sample.zone),
+            format!("twitch_up={}", sample.twitch_up),
+            format!("twitch_down={}", sample.twitch_down),
+        ];
+        if let Some(battery) = sample.battery {
+            fields.push(format!("battery={battery}i"));
+        }
+        let frequency = hrv.frequency.as_ref();
+        let floats = [
+            ("rr_ms", Some(sample.rr_ms)),
+            ("hrv_rmssd", Some(hrv.rmssd_ms as f64)),
+            ("hrv_sdnn", Some(hrv.sdnn_ms as f64)),
+            ("hrv_pnn50", Some(hrv.pnn50 as f64)),
+            ("hrv_stress_index", Some(hrv.stress_index as f64)),
+            ("hrv_lf_power", frequency.map(|f| f.lf_power as f64)),
+            ("hrv_hf_power", frequency.map(|f| f.hf_power as f64)),
+            ("hrv_lf_hf_ratio", frequency.map(|f| f.lf_hf_ratio as f64)),
+            ("breaths_per_min", hrv.respiration_rate.map(f64::from)),
+            ("calories", sample.calories.map(f64::from)),
+            ("trimp", Some(sample.trimp as f64)),
+        ];
+        for (key, value) in floats {
+            // NaN and infinity can't be written
+            if let Some(value) = value.filter(|v| v.is_finite()) {
+                fields.push(format!("{key}={value}"));
+            }
+        }
+
+        let timestamp = sample
+            .timestamp
+            .timestamp_nanos_opt()
+            .unwrap_or_else(|| sample.timestamp.timestamp_millis() * 1_000_000);
+        format!("{line} {} {timestamp}", fields.join(","))
+    }
+}
+
+impl HttpExporter for InfluxExporter {
+    fn name(&self) -> &'static str {
+        "InfluxDB"
+    }
+    fn content_type(&self) -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+    fn push(&mut self, sample: &ExportSample) -> Result<Option<Vec<u8>>, AppError> {
+        self.lines.push(self.line(sample));
+        if self.lines.len() >= self.batch_size {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+    fn flush(&mut self) -> Result<Option<Vec<u8>>, AppError> {
+        if self.lines.is_empty() {
+            return Ok(None);
+        }
+        let body = self.lines.join("\n");
+        self.lines.clear();
+        Ok(Some(body.into_bytes()))
+    }
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        // Line breaks would end the line early and can't be escaped,
+        // so they become spaces (which are, when they're special)
+        let c = if c == '\n' || c == '\r' { ' ' } else { c };
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hrv::HrvMetrics;
+    use chrono::{Local, TimeZone};
+
+    fn exporter(batch_size: usize) -> InfluxExporter {
+        InfluxExporter::build(&InfluxSettings {
+            measurement: "heart rate".into(),
+            batch_size,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn sample(bpm: u16) -> ExportSample {
+        ExportSample {
+            timestamp: Local.timestamp_millis_opt(1_700_000_000_123).unwrap(),
+            device: "Polar H10".into(),
+            activity: 2,
+            activity_name: "Beat Saber, Expert=1".into(),
+            bpm,
+            rr_ms: 750.0,
+            battery: Some(90),
+            twitch_up: true,
+            zone: 3,
+            hrv: HrvMetrics {
+                rmssd_ms: 42.5,
+                stress_index: f32::NAN,
+                ..Default::default()
+            },
+            trimp: 1.5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn line_protocol() {
+        let mut exporter = exporter(1);
+        let body = exporter.push(&sample(80)).unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "heart\\ rate,device=Polar\\ H10,activity=Beat\\ Saber\\,\\ Expert\\=1 \
+             bpm=80i,activity_index=2i,zone=3i,twitch_up=true,twitch_down=false,battery=90i,\
+             rr_ms=750,hrv_rmssd=42.5,hrv_sdnn=0,hrv_pnn50=0,trimp=1.5 \
+             1700000000123000000"
+        );
+    }
+
+    #[test]
+    fn line_breaks_in_tags() {
+        let mut exporter = exporter(1);
+        let mut multiline = sample(80);
+        multiline.activity_name = "Beat\nSaber\r\n".into();
+        let body = String::from_utf8(exporter.push(&multiline).unwrap().unwrap()).unwrap();
+        assert_eq!(body.lines().count(), 1);
+        assert!(
+            body.contains(",activity=Beat\\ Saber\\ \\  bpm=80i,"),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn batches() {
+        let mut exporter = exporter(2);
+        let mut without_tags = sample(80);
+        without_tags.device.clear();
+        without_tags.activity_name.clear();
+        assert_eq!(exporter.push(&without_tags).unwrap(), None);
+        let body = String::from_utf8(exporter.push(&sample(81)).unwrap().unwrap()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("heart\\ rate bpm=80i,"));
+        assert!(lines[1].contains("bpm=81i"));
+        assert_eq!(exporter.flush().unwrap(), None);
+
+        exporter.push(&sample(82)).unwrap();
+        assert!(exporter.flush().unwrap().is_some());
+    }
+
+    #[test]
+    fn needs_a_measurement() {
+        assert!(InfluxExporter::build(&InfluxSettings::default()).is_err());
+    }
+}
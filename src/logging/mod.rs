@@ -1,30 +1,72 @@
 use crate::app::{AppUpdate, ErrorPopup};
 use crate::broadcast;
+use crate::errors::AppError;
 
-use crate::settings::{MiscSettings, PrometheusSettings};
+#[cfg(feature = "parquet")]
+use crate::settings::ParquetSettings;
+use crate::settings::{
+    CsvSettings, InfluxSettings, MiscSettings, PrometheusSettings, SqliteSettings, WebhookSettings,
+};
+use crate::zones::HrZones;
 
 use file::FileLoggingActor;
+use http_export::HttpExportActor;
+use influx::InfluxExporter;
 use prometheus::PrometheusLoggingActor;
+use sqlite::SqliteLoggingActor;
+use std::path::Path;
+use tokio::net::TcpListener;
 use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
+use webhook::WebhookExporter;
 
+mod csv_schema;
 mod file;
+mod http_export;
+mod influx;
+#[cfg(feature = "parquet")]
+mod parquet;
 mod prometheus;
+mod remote_write;
+mod rotation;
+mod rr_series;
+mod sqlite;
+mod webhook;
 
+pub const SESSION_FILE_PREFIX: &str = "nih-";
+/// Session CSVs can be written with any of these, and the session log reader picks between them
+pub const CSV_DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+#[allow(clippy::too_many_arguments)]
 pub async fn file_logging_thread(
     mut broadcast_rx: BReceiver<AppUpdate>,
     broadcast_tx: BSender<AppUpdate>,
     initial_activity: u8,
+    initial_activity_name: String,
     misc_settings: MiscSettings,
+    csv_settings: CsvSettings,
+    zones: HrZones,
     cancel_token: CancellationToken,
 ) {
-    if !misc_settings.log_sessions_to_csv && !misc_settings.write_bpm_to_file {
+    if !misc_settings.log_sessions_to_csv
+        && !misc_settings.write_bpm_to_file
+        && !misc_settings.log_rr_series
+        && !csv_settings.to_stdout
+    {
         info!("No file logging was enabled! Shutting down thread.");
         return;
     }
 
-    let mut logging = FileLoggingActor::new(initial_activity, misc_settings);
+    let mut logging = FileLoggingActor::new(
+        broadcast_tx.clone(),
+        initial_activity,
+        initial_activity_name,
+        misc_settings,
+        csv_settings,
+        zones,
+    );
 
     info!("Logging thread started!");
 
@@ -39,15 +81,26 @@ pub async fn prometheus_logging_thread(
     mut broadcast_rx: BReceiver<AppUpdate>,
     broadcast_tx: BSender<AppUpdate>,
     initial_activity: u8,
+    initial_activity_name: String,
     prometheus_settings: PrometheusSettings,
+    zones: HrZones,
     cancel_token: CancellationToken,
 ) {
-    if !prometheus_settings.enabled {
+    if !prometheus_settings.enabled && !prometheus_settings.scrape_enabled {
         info!("Prometheus wasn't enabled! Shutting down thread");
         return;
     }
 
-    let mut logging = match PrometheusLoggingActor::build(initial_activity, prometheus_settings) {
+    let scrape_addr = prometheus_settings
+        .scrape_enabled
+        .then(|| prometheus_settings.scrape_addr.clone());
+
+    let mut logging = match PrometheusLoggingActor::build(
+        initial_activity,
+        initial_activity_name,
+        prometheus_settings,
+        zones,
+    ) {
         Ok(Some(prom)) => prom,
         Ok(None) => {
             info!("Prometheus: No metrics specified, shutting down thread");
@@ -60,6 +113,28 @@ pub async fn prometheus_logging_thread(
         }
     };
 
+    // Stopped alongside the actor, even if it bails early
+    let server_token = cancel_token.child_token();
+    let server_handle = match scrape_addr {
+        Some(addr) => match TcpListener::bind(&addr).await {
+            Ok(listener) => {
+                info!("Prometheus: Serving scrapes on http://{addr}/metrics");
+                Some(tokio::spawn(prometheus::serve_scrapes(
+                    listener,
+                    logging.registry(),
+                    server_token.clone(),
+                )))
+            }
+            Err(source) => {
+                let message = "Failed to start Prometheus scrape server";
+                let e = AppError::MetricsListen { addr, source };
+                broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+                return;
+            }
+        },
+        None => None,
+    };
+
     info!("Prometheus thread started!");
 
     if let Err(e) = logging.rx_loop(&mut broadcast_rx, cancel_token).await {
@@ -67,4 +142,182 @@ pub async fn prometheus_logging_thread(
         let message = "Prometheus error:";
         broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
     }
+
+    server_token.cancel();
+    if let Some(handle) = server_handle {
+        if let Err(e) = handle.await {
+            error!("Failed to join Prometheus scrape server: {e}");
+        }
+    }
+}
+
+pub async fn sqlite_logging_thread(
+    mut broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    initial_activity: u8,
+    initial_activity_name: String,
+    sqlite_settings: SqliteSettings,
+    zones: HrZones,
+    cancel_token: CancellationToken,
+) {
+    if !sqlite_settings.enabled {
+        info!("SQLite wasn't enabled! Shutting down thread");
+        return;
+    }
+
+    let conn = match sqlite::open_database(Path::new(&sqlite_settings.path)) {
+        Ok(conn) => conn,
+        Err(e) => {
+            let message = "Failed to open SQLite database";
+            broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+            return;
+        }
+    };
+    let mut logging = SqliteLoggingActor::new(
+        conn,
+        sqlite_settings.log_rr_beats,
+        initial_activity,
+        initial_activity_name,
+        zones,
+    );
+
+    info!("SQLite thread started!");
+
+    let (update_tx, update_rx) = mpsc::unbounded_channel();
+    // Keeps the queries from stalling the runtime's workers
+    let writer = tokio::task::spawn_blocking(move || logging.write_loop(update_rx));
+    sqlite::forward_updates(&mut broadcast_rx, update_tx, cancel_token).await;
+
+    let result = match writer.await {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        error!("SQLite error: {e}");
+        let message = "SQLite error:";
+        broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+    }
+}
+
+pub async fn influx_logging_thread(
+    broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    initial_activity: u8,
+    initial_activity_name: String,
+    influx_settings: InfluxSettings,
+    zones: HrZones,
+    cancel_token: CancellationToken,
+) {
+    if !influx_settings.enabled {
+        info!("InfluxDB wasn't enabled! Shutting down thread");
+        return;
+    }
+    let logging = InfluxExporter::build(&influx_settings).and_then(|exporter| {
+        HttpExportActor::build(
+            Box::new(exporter),
+            &influx_settings.url,
+            &influx_settings.headers,
+            initial_activity,
+            initial_activity_name,
+            zones,
+        )
+    });
+    http_export_thread(broadcast_rx, broadcast_tx, logging, cancel_token).await;
+}
+
+pub async fn webhook_logging_thread(
+    broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    initial_activity: u8,
+    initial_activity_name: String,
+    webhook_settings: WebhookSettings,
+    zones: HrZones,
+    cancel_token: CancellationToken,
+) {
+    if !webhook_settings.enabled {
+        info!("Webhook wasn't enabled! Shutting down thread");
+        return;
+    }
+    let logging = WebhookExporter::build(&webhook_settings).and_then(|exporter| {
+        HttpExportActor::build(
+            Box::new(exporter),
+            &webhook_settings.url,
+            &webhook_settings.headers,
+            initial_activity,
+            initial_activity_name,
+            zones,
+        )
+    });
+    http_export_thread(broadcast_rx, broadcast_tx, logging, cancel_token).await;
+}
+
+// Same for every HttpExporter, only the building differs
+async fn http_export_thread(
+    mut broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    logging: Result<HttpExportActor, AppError>,
+    cancel_token: CancellationToken,
+) {
+    let mut logging = match logging {
+        Ok(logging) => logging,
+        Err(e) => {
+            let message = "Failed to build HTTP exporter";
+            broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+            return;
+        }
+    };
+    let name = logging.name();
+
+    info!("{name} thread started!");
+
+    if let Err(e) = logging.rx_loop(&mut broadcast_rx, cancel_token).await {
+        error!("{name} error: {e}");
+        let message = format!("{name} error:");
+        broadcast!(broadcast_tx, ErrorPopup::detailed(&message, e));
+    }
+}
+
+#[cfg(feature = "parquet")]
+#[allow(clippy::too_many_arguments)]
+pub async fn parquet_logging_thread(
+    mut broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    initial_activity: u8,
+    initial_activity_name: String,
+    parquet_settings: ParquetSettings,
+    // Files go next to the CSVs, and rotate the same way
+    misc_settings: MiscSettings,
+    csv_settings: CsvSettings,
+    zones: HrZones,
+    cancel_token: CancellationToken,
+) {
+    if !parquet_settings.enabled {
+        info!("Parquet wasn't enabled! Shutting down thread");
+        return;
+    }
+
+    let rotation = match rotation::RotationRules::build(&csv_settings) {
+        Ok(rotation) => rotation,
+        Err(e) => {
+            let message = "Invalid Parquet file settings";
+            broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+            return;
+        }
+    };
+    let mut logging = parquet::ParquetLoggingActor::build(
+        misc_settings.log_sessions_csv_path.into(),
+        rotation,
+        &parquet_settings,
+        initial_activity,
+        initial_activity_name,
+        zones,
+    );
+
+    info!("Parquet thread started!");
+
+    if let Err(e) = logging.rx_loop(&mut broadcast_rx, cancel_token).await {
+        error!("Parquet error: {e}");
+        let message = "Parquet error:";
+        broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+    }
 }
@@ -0,0 +1,417 @@
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, ListBuilder, StringBuilder, TimestampNanosecondBuilder,
+    UInt16Builder, UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Local};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver as BReceiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::app::AppUpdate;
+use crate::errors::AppError;
+use crate::heart_rate::HeartRateStatus;
+use crate::settings::ParquetSettings;
+use crate::zones::HrZones;
+
+use super::rotation::{RotationRules, Segment};
+
+// Rows are handed to the writer in batches this big, it takes care of row groups from there
+const BATCH_ROWS: usize = 60;
+
+/// One heart rate update
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    timestamp: DateTime<Local>,
+    bpm: u16,
+    /// Every interval in the update, not just the latest
+    rr_ms: Vec<f64>,
+    battery: Option<u8>,
+    twitch_up: bool,
+    twitch_down: bool,
+    activity: u8,
+    activity_name: String,
+    zone: u8,
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("bpm", DataType::UInt16, false),
+        Field::new(
+            "rr_ms",
+            DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
+            false,
+        ),
+        Field::new("battery", DataType::UInt8, true),
+        Field::new("twitch_up", DataType::Boolean, false),
+        Field::new("twitch_down", DataType::Boolean, false),
+        Field::new("activity", DataType::UInt8, false),
+        Field::new("activity_name", DataType::Utf8, false),
+        Field::new("zone", DataType::UInt8, false),
+    ]))
+}
+
+fn record_batch(schema: &SchemaRef, rows: &[Row]) -> Result<RecordBatch, AppError> {
+    let mut timestamp = TimestampNanosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+    let mut bpm = UInt16Builder::with_capacity(rows.len());
+    let mut rr_ms = ListBuilder::new(Float64Builder::new());
+    let mut battery = UInt8Builder::with_capacity(rows.len());
+    let mut twitch_up = BooleanBuilder::with_capacity(rows.len());
+    let mut twitch_down = BooleanBuilder::with_capacity(rows.len());
+    let mut activity = UInt8Builder::with_capacity(rows.len());
+    let mut activity_name = StringBuilder::new();
+    let mut zone = UInt8Builder::with_capacity(rows.len());
+    for row in rows {
+        timestamp.append_option(row.timestamp.timestamp_nanos_opt());
+        bpm.append_value(row.bpm);
+        rr_ms.values().append_slice(&row.rr_ms);
+        rr_ms.append(true);
+        battery.append_option(row.battery);
+        twitch_up.append_value(row.twitch_up);
+        twitch_down.append_value(row.twitch_down);
+        activity.append_value(row.activity);
+        activity_name.append_value(&row.activity_name);
+        zone.append_value(row.zone);
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(timestamp.finish()),
+        Arc::new(bpm.finish()),
+        Arc::new(rr_ms.finish()),
+        Arc::new(battery.finish()),
+        Arc::new(twitch_up.finish()),
+        Arc::new(twitch_down.finish()),
+        Arc::new(activity.finish()),
+        Arc::new(activity_name.finish()),
+        Arc::new(zone.finish()),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Logs sessions as Parquet, started and rotated just like the session CSV.
+///
+/// Parquet files are only readable once their footer is written,
+/// so each file is closed on rotation and when the app exits.
+pub(super) struct ParquetLoggingActor {
+    folder: PathBuf,
+    rotation: RotationRules,
+    schema: SchemaRef,
+    properties: WriterProperties,
+    zones: HrZones,
+    writer: Option<ArrowWriter<File>>,
+    path: Option<PathBuf>,
+    segment: Option<Segment>,
+    part: u32,
+    rows: Vec<Row>,
+    last_data: Option<DateTime<Local>>,
+    activity: u8,
+    activity_name: String,
+}
+
+impl ParquetLoggingActor {
+    pub(super) fn build(
+        folder: PathBuf,
+        rotation: RotationRules,
+        parquet_settings: &ParquetSettings,
+        initial_activity: u8,
+        initial_activity_name: String,
+        zones: HrZones,
+    ) -> Self {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(parquet_settings.row_group_rows.max(1))
+            .build();
+        Self {
+            folder,
+            rotation,
+            schema: schema(),
+            properties,
+            zones,
+            writer: None,
+            path: None,
+            segment: None,
+            part: 0,
+            rows: Vec::with_capacity(BATCH_ROWS),
+            last_data: None,
+            activity: initial_activity,
+            activity_name: initial_activity_name,
+        }
+    }
+    pub(super) async fn rx_loop(
+        &mut self,
+        broadcast_rx: &mut BReceiver<AppUpdate>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), AppError> {
+        loop {
+            tokio::select! {
+                update = broadcast_rx.recv() => {
+                    match update {
+                        Ok(AppUpdate::HeartRateStatus(data)) => self.handle_data(&data)?,
+                        Ok(AppUpdate::ActivitySelected(index, name)) => {
+                            self.activity = index;
+                            self.activity_name = name;
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Closed) => {
+                            error!("Parquet: Channel closed");
+                            return self.close_file();
+                        }
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Parquet: Lagged! Missed {count} messages");
+                        }
+                    }
+                }
+                _ = cancel_token.cancelled() => {
+                    info!("Parquet thread shutting down");
+                    return self.close_file();
+                }
+            }
+        }
+    }
+    fn handle_data(&mut self, heart_rate_status: &HeartRateStatus) -> Result<(), AppError> {
+        if heart_rate_status.heart_rate_bpm == 0 {
+            return Ok(());
+        }
+        let now = heart_rate_status.timestamp;
+        if let (Some(segment), Some(last_data)) = (&self.segment, self.last_data) {
+            if let Some(reason) = self.rotation.check(segment, now, last_data, self.activity) {
+                info!("Starting a new Parquet file: {reason:?}");
+                self.close_file()?;
+            }
+        }
+        if self.writer.is_none() {
+            self.open_file(now)?;
+        }
+        let battery: u8 = heart_rate_status.battery_level.into();
+        self.rows.push(Row {
+            timestamp: now,
+            bpm: heart_rate_status.heart_rate_bpm,
+            rr_ms: heart_rate_status
+                .rr_intervals
+                .iter()
+                .map(|rr| rr.as_secs_f64() * 1000.0)
+                .collect(),
+            battery: (battery > 0).then_some(battery),
+            twitch_up: heart_rate_status.twitch_up,
+            twitch_down: heart_rate_status.twitch_down,
+            activity: self.activity,
+            activity_name: self.activity_name.clone(),
+            zone: self.zones.zone_of(heart_rate_status.heart_rate_bpm),
+        });
+        if self.rows.len() >= BATCH_ROWS {
+            self.write_rows()?;
+        }
+        self.last_data = Some(now);
+        Ok(())
+    }
+    fn open_file(&mut self, start: DateTime<Local>) -> Result<(), AppError> {
+        if !self.folder.exists() {
+            std::fs::create_dir_all(&self.folder).map_err(|e| AppError::CreateDir {
+                path: self.folder.clone(),
+                source: e,
+            })?;
+        }
+        self.part += 1;
+        let stem = self
+            .rotation
+            .file_stem(start, self.activity, &self.activity_name, self.part);
+        let mut path = self.folder.join(format!("{stem}.parquet"));
+        let mut duplicate = 1;
+        while path.exists() {
+            duplicate += 1;
+            path = self.folder.join(format!("{stem}-{duplicate}.parquet"));
+        }
+        let file = File::create(&path).map_err(|e| AppError::CreateFile {
+            path: path.clone(),
+            source: e,
+        })?;
+        info!("Logging session to {}", path.display());
+        self.writer = Some(ArrowWriter::try_new(
+            file,
+            self.schema.clone(),
+            Some(self.properties.clone()),
+        )?);
+        self.path = Some(path);
+        self.segment = Some(Segment {
+            start,
+            activity: self.activity,
+            bytes: 0,
+        });
+        Ok(())
+    }
+    fn write_rows(&mut self) -> Result<(), AppError> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let batch = record_batch(&self.schema, &self.rows)?;
+        writer.write(&batch)?;
+        self.rows.clear();
+        if let Some(segment) = &mut self.segment {
+            segment.bytes = (writer.bytes_written() + writer.in_progress_size()) as u64;
+        }
+        Ok(())
+    }
+    fn close_file(&mut self) -> Result<(), AppError> {
+        self.write_rows()?;
+        self.segment = None;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+            if let Some(path) = self.path.take() {
+                info!("Closed {}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{CsvSettings, ZoneMethod, ZoneSettings};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, TimestampNanosecondType, UInt16Type};
+    use chrono::TimeZone;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::time::Duration;
+
+    fn actor(folder: PathBuf, csv_settings: CsvSettings) -> ParquetLoggingActor {
+        let zone_settings = ZoneSettings {
+            method: ZoneMethod::PercentMax,
+            max_bpm: 200,
+            resting_bpm: 60,
+            percentages: vec![50, 60, 70, 80, 90],
+            custom_thresholds: vec![],
+        };
+        ParquetLoggingActor::build(
+            folder,
+            RotationRules::build(&csv_settings).unwrap(),
+            &ParquetSettings {
+                enabled: true,
+                row_group_rows: 100,
+            },
+            1,
+            "Sitting".into(),
+            HrZones::build(&zone_settings, 0).unwrap(),
+        )
+    }
+
+    fn csv_settings() -> CsvSettings {
+        CsvSettings {
+            file_name_template: "{start}".into(),
+            ..Default::default()
+        }
+    }
+
+    fn status(secs: u64, bpm: u16, rr_ms: &[u64]) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            rr_intervals: rr_ms.iter().map(|ms| Duration::from_millis(*ms)).collect(),
+            timestamp: Local.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+                + Duration::from_secs(secs),
+            ..Default::default()
+        }
+    }
+
+    fn read(path: &std::path::Path) -> RecordBatch {
+        let file = File::open(path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        // Tests only write a handful of rows, which come back as one batch
+        assert_eq!(batches.len(), 1);
+        batches.remove(0)
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut actor = actor(dir.path().to_owned(), csv_settings());
+        // More than a batch, so some rows go through the writer before closing
+        for i in 0..(BATCH_ROWS as u64 + 5) {
+            actor
+                .handle_data(&status(i, 80 + i as u16 % 10, &[750, 760]))
+                .unwrap();
+        }
+        actor.handle_data(&status(100, 0, &[])).unwrap();
+        actor.close_file().unwrap();
+
+        let path = dir.path().join("nih-2024-05-01_10-00-00.parquet");
+        let batch = read(&path);
+        assert_eq!(batch.schema(), schema());
+        assert_eq!(batch.num_rows(), BATCH_ROWS + 5);
+        let timestamps = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        assert_eq!(
+            timestamps.value(1) - timestamps.value(0),
+            Duration::from_secs(1).as_nanos() as i64
+        );
+        let bpm = batch.column(1).as_primitive::<UInt16Type>();
+        assert_eq!(bpm.value(3), 83);
+        let rr = batch.column(2).as_list::<i32>().value(0);
+        assert_eq!(rr.as_primitive::<Float64Type>().values(), &[750.0, 760.0]);
+        assert!(batch.column(3).is_null(0));
+        assert_eq!(batch.column(7).as_string::<i32>().value(0), "Sitting");
+    }
+
+    #[test]
+    fn each_beat_written_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut actor = actor(dir.path().to_owned(), csv_settings());
+        // Sources only put the beats that are new in each packet in rr_intervals
+        for (i, rr_ms) in [&[800][..], &[], &[810, 820]].into_iter().enumerate() {
+            actor.handle_data(&status(i as u64, 75, rr_ms)).unwrap();
+        }
+        actor.close_file().unwrap();
+
+        let batch = read(&dir.path().join("nih-2024-05-01_10-00-00.parquet"));
+        let rr = batch.column(2).as_list::<i32>();
+        let rows: Vec<Vec<f64>> = (0..batch.num_rows())
+            .map(|row| {
+                rr.value(row)
+                    .as_primitive::<Float64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(rows, vec![vec![800.0], vec![], vec![810.0, 820.0]]);
+    }
+
+    #[test]
+    fn rotates_like_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut actor = actor(
+            dir.path().to_owned(),
+            CsvSettings {
+                file_name_template: "{start}_{activity}".into(),
+                rotate_on_activity_change: true,
+                ..csv_settings()
+            },
+        );
+        actor.handle_data(&status(0, 80, &[])).unwrap();
+        actor.activity = 2;
+        actor.activity_name = "Dancing".into();
+        actor.handle_data(&status(1, 120, &[])).unwrap();
+        actor.close_file().unwrap();
+
+        let sitting = read(&dir.path().join("nih-2024-05-01_10-00-00_Sitting.parquet"));
+        let dancing = read(&dir.path().join("nih-2024-05-01_10-00-01_Dancing.parquet"));
+        assert_eq!((sitting.num_rows(), dancing.num_rows()), (1, 1));
+        assert_eq!(dancing.column(1).as_primitive::<UInt16Type>().value(0), 120);
+    }
+}
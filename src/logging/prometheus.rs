@@ -1,36 +1,63 @@
 use crate::app::AppUpdate;
 use crate::errors::AppError;
 use crate::heart_rate::HeartRateStatus;
-use crate::settings::PrometheusSettings;
+use crate::hrv::HrvMetrics;
+use crate::settings::{PrometheusProtocol, PrometheusSettings};
+use crate::stats::SessionStats;
+use crate::zones::HrZones;
+
+use super::remote_write::encode_write_request;
 
 use chrono::{DateTime, Local};
 use http::{header, HeaderName, HeaderValue};
 use prometheus::proto::MetricFamily;
-use reqwest::Client;
-use std::collections::BTreeMap;
+use reqwest::{Client, StatusCode};
+use std::collections::{BTreeMap, VecDeque};
 use std::str::FromStr;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver as BReceiver;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
-use prometheus::{Encoder, IntGauge, Opts, Registry, TextEncoder};
+use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
+
+const LABELS: [&str; 2] = ["device", "activity"];
+// Scrapers send a few hundred bytes at most, anything past this is ignored
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub(super) struct PrometheusLoggingActor {
     settings: PrometheusSettings,
+    zones: HrZones,
     last_rr: Duration,
     activity: u8,
+    activity_name: String,
+    device: String,
+    // Label values the gauges were last set with, so stale series can be dropped
+    set_labels: Option<[String; 2]>,
+    hrv: HrvMetrics,
+    // Only the training totals are used from here
+    session_stats: SessionStats,
     built_url: String,
     registry: Registry,
-    gauges: BTreeMap<String, IntGauge>,
+    gauges: BTreeMap<String, GaugeVec>,
     client: Client,
+    // Timestamped snapshots waiting for the batch to fill up
+    pending: Vec<Vec<MetricFamily>>,
+    // Encoded batches the server hasn't taken yet, oldest first
+    retry_queue: VecDeque<Vec<u8>>,
 }
 
 impl PrometheusLoggingActor {
     pub(super) fn build(
         initial_activity: u8,
+        initial_activity_name: String,
         settings: PrometheusSettings,
+        zones: HrZones,
     ) -> Result<Option<Self>, AppError> {
         let built_url = {
             let mut url = if settings.url.contains("://") {
@@ -65,6 +92,28 @@ impl PrometheusLoggingActor {
                 "If this heart rate update triggered a TwitchDown",
             ),
             (&settings.metrics.activity, "Current index of Activity"),
+            (&settings.metrics.zone, "Current heart rate zone"),
+            (&settings.metrics.hrv_rmssd, "RMSSD in milliseconds"),
+            (&settings.metrics.hrv_sdnn, "SDNN in milliseconds"),
+            (
+                &settings.metrics.hrv_pnn50,
+                "Percentage of successive RR intervals differing by more than 50ms",
+            ),
+            (&settings.metrics.hrv_stress_index, "Baevsky's stress index"),
+            (
+                &settings.metrics.hrv_lf_power,
+                "Low frequency (0.04 - 0.15 Hz) HRV power in ms²",
+            ),
+            (
+                &settings.metrics.hrv_hf_power,
+                "High frequency (0.15 - 0.4 Hz) HRV power in ms²",
+            ),
+            (&settings.metrics.hrv_lf_hf_ratio, "Ratio of LF to HF power"),
+            (
+                &settings.metrics.calories,
+                "Calories burned this session (kcal)",
+            ),
+            (&settings.metrics.trimp, "Banister TRIMP for this session"),
         ];
 
         for (name, desc) in metrics.iter() {
@@ -92,19 +141,47 @@ impl PrometheusLoggingActor {
             );
         }
 
-        let client = Client::builder().default_headers(headers).build()?;
+        if settings.protocol == PrometheusProtocol::RemoteWrite {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/x-protobuf"),
+            );
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("snappy"));
+            headers.insert(
+                "X-Prometheus-Remote-Write-Version",
+                HeaderValue::from_static("0.1.0"),
+            );
+        }
+
+        // Anything slower gets retried with the next batch
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(PUSH_TIMEOUT)
+            .build()?;
 
         Ok(Some(Self {
             settings,
+            zones,
             last_rr: Duration::from_secs(0),
             activity: initial_activity,
+            activity_name: initial_activity_name,
+            device: String::new(),
+            set_labels: None,
+            hrv: HrvMetrics::default(),
+            session_stats: SessionStats::default(),
             built_url,
             registry,
             gauges,
             client,
+            pending: Vec::new(),
+            retry_queue: VecDeque::new(),
         }))
     }
 
+    /// Shared with the scrape server, so it always serves the latest values
+    pub(super) fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
     pub(super) async fn rx_loop(
         &mut self,
         broadcast_rx: &mut BReceiver<AppUpdate>,
@@ -117,8 +194,18 @@ impl PrometheusLoggingActor {
                         Ok(AppUpdate::HeartRateStatus(data)) => {
                             self.handle_data(data).await?;
                         },
-                        Ok(AppUpdate::ActivitySelected(index)) => {
+                        Ok(AppUpdate::ActivitySelected(index, name)) => {
                             self.activity = index;
+                            self.activity_name = name;
+                        },
+                        Ok(AppUpdate::DeviceConnected(source)) => {
+                            self.device = source.label().to_owned();
+                        },
+                        Ok(AppUpdate::Hrv(hrv)) => {
+                            self.hrv = hrv;
+                        },
+                        Ok(AppUpdate::SessionStats(stats)) => {
+                            self.session_stats = stats;
                         },
                         Ok(_) => {},
                         Err(RecvError::Closed) => {
@@ -132,20 +219,22 @@ impl PrometheusLoggingActor {
                 }
                 _ = cancel_token.cancelled() => {
                     info!("Logging thread shutting down");
+                    // Last chance for anything still waiting
+                    if self.settings.enabled {
+                        self.queue_batch()?;
+                        self.send_queued().await;
+                    }
                     return Ok(());
                 }
             }
         }
     }
-    fn build_buffer(&self, timestamp: &DateTime<Local>) -> Result<Vec<u8>, AppError> {
-        // Gather the metrics.
-        let mut buffer = vec![];
-        let encoder = TextEncoder::new();
-        let metric_families = self.registry.gather();
+    fn snapshot(&self, timestamp: &DateTime<Local>) -> Vec<MetricFamily> {
         // Since there's not any timestamp support on the actual gauges in the Prometheus crate,
         // we're manually appending the timestamps before sending the data out
         // Taken from: https://github.com/tikv/rust-prometheus/issues/423#issuecomment-957742142
-        let metric_families: Vec<MetricFamily> = metric_families
+        self.registry
+            .gather()
             .into_iter()
             .map(|mut fam| {
                 for metric in fam.mut_metric() {
@@ -153,62 +242,156 @@ impl PrometheusLoggingActor {
                 }
                 fam
             })
-            .collect();
-        encoder.encode(&metric_families, &mut buffer)?;
-
-        Ok(buffer)
+            .collect()
+    }
+    /// Encodes whatever's pending into a batch at the back of the retry queue
+    fn queue_batch(&mut self) -> Result<(), AppError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let snapshots = std::mem::take(&mut self.pending);
+        let body = match self.settings.protocol {
+            PrometheusProtocol::Text => {
+                let mut buffer = vec![];
+                TextEncoder::new().encode(&merge_snapshots(snapshots), &mut buffer)?;
+                buffer
+            }
+            PrometheusProtocol::RemoteWrite => encode_write_request(&snapshots)?,
+        };
+        self.retry_queue.push_back(body);
+        while self.retry_queue.len() > self.settings.retry_queue_size.max(1) {
+            self.retry_queue.pop_front();
+            warn!("Prometheus: Retry queue full, dropped the oldest batch");
+        }
+        Ok(())
+    }
+    /// Sends queued batches in order, stopping at the first one the server couldn't take
+    async fn send_queued(&mut self) {
+        while let Some(body) = self.retry_queue.front() {
+            // Just putting errors in the .log, shutting down the whole app
+            // if a webserver wasn't reachable once seems overkill.
+            match self
+                .client
+                .post(&self.built_url)
+                .body(body.clone())
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => {}
+                // Sending it again won't help
+                Ok(response)
+                    if response.status().is_client_error()
+                        && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    error!(
+                        "Prometheus: Server rejected batch ({}), dropping it",
+                        response.status()
+                    );
+                }
+                Ok(response) => {
+                    warn!(
+                        "Prometheus: Server returned {}, will retry {} batch(es)",
+                        response.status(),
+                        self.retry_queue.len()
+                    );
+                    return;
+                }
+                Err(e) => {
+                    error!(
+                        "Error POSTing Prometheus data, will retry {} batch(es)! {e}",
+                        self.retry_queue.len()
+                    );
+                    return;
+                }
+            }
+            self.retry_queue.pop_front();
+        }
     }
     async fn handle_data(&mut self, heart_rate_status: HeartRateStatus) -> Result<(), AppError> {
         if heart_rate_status.heart_rate_bpm == 0 {
             return Ok(());
         }
-        let reported_rr = heart_rate_status
-            .rr_intervals
-            .last()
-            .unwrap_or(&self.last_rr);
+        let reported_rr = heart_rate_status.latest_clean_rr().unwrap_or(self.last_rr);
+
+        let (lf_power, hf_power, lf_hf_ratio) = self
+            .hrv
+            .frequency
+            .as_ref()
+            .map_or((0.0, 0.0, 0.0), |f| (f.lf_power, f.hf_power, f.lf_hf_ratio));
 
         let metrics = [
             (
                 &self.settings.metrics.bpm,
-                heart_rate_status.heart_rate_bpm as i64,
+                heart_rate_status.heart_rate_bpm as f64,
             ),
-            (&self.settings.metrics.rr, reported_rr.as_millis() as i64),
+            (&self.settings.metrics.rr, reported_rr.as_millis() as f64),
             (
                 &self.settings.metrics.battery,
-                u8::from(heart_rate_status.battery_level) as i64,
+                u8::from(heart_rate_status.battery_level) as f64,
             ),
             (
                 &self.settings.metrics.twitch_up,
-                heart_rate_status.twitch_up as i64,
+                heart_rate_status.twitch_up as u8 as f64,
             ),
             (
                 &self.settings.metrics.twitch_down,
-                heart_rate_status.twitch_down as i64,
+                heart_rate_status.twitch_down as u8 as f64,
+            ),
+            (&self.settings.metrics.activity, self.activity as f64),
+            (
+                &self.settings.metrics.zone,
+                self.zones.zone_of(heart_rate_status.heart_rate_bpm) as f64,
+            ),
+            (&self.settings.metrics.hrv_rmssd, self.hrv.rmssd_ms as f64),
+            (&self.settings.metrics.hrv_sdnn, self.hrv.sdnn_ms as f64),
+            (&self.settings.metrics.hrv_pnn50, self.hrv.pnn50 as f64),
+            (
+                &self.settings.metrics.hrv_stress_index,
+                self.hrv.stress_index as f64,
+            ),
+            (&self.settings.metrics.hrv_lf_power, lf_power as f64),
+            (&self.settings.metrics.hrv_hf_power, hf_power as f64),
+            (&self.settings.metrics.hrv_lf_hf_ratio, lf_hf_ratio as f64),
+            (
+                &self.settings.metrics.calories,
+                self.session_stats.calories.unwrap_or_default() as f64,
+            ),
+            (
+                &self.settings.metrics.trimp,
+                self.session_stats.trimp as f64,
             ),
-            (&self.settings.metrics.activity, self.activity as i64),
         ];
 
+        let labels = [self.device.clone(), self.activity_name.clone()];
+        // Otherwise the old device/activity would keep getting scraped with its last values
+        if self.set_labels.as_ref() != Some(&labels) {
+            for gauge in self.gauges.values() {
+                gauge.reset();
+            }
+            self.set_labels = Some(labels.clone());
+        }
+        let label_values = [labels[0].as_str(), labels[1].as_str()];
+
         for (metric_name, value) in metrics.iter() {
             if !metric_name.is_empty() {
                 self.gauges
                     .get(*metric_name)
                     .ok_or(AppError::MissingMetric)?
+                    .with_label_values(&label_values)
                     .set(*value);
             }
         }
 
-        let buf = self.build_buffer(&heart_rate_status.timestamp)?;
-
-        // Just putting errors in the .log, shutting down the whole app
-        // if a webserver wasn't reachable once seems overkill.
-        match self.client.post(&self.built_url).body(buf).send().await {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Error POSTing Prometheus data! {e}");
+        if self.settings.enabled {
+            self.pending
+                .push(self.snapshot(&heart_rate_status.timestamp));
+            if self.pending.len() >= self.settings.batch_size.max(1) {
+                self.queue_batch()?;
+                self.send_queued().await;
             }
         }
 
-        self.last_rr = *reported_rr;
+        self.last_rr = reported_rr;
 
         Ok(())
     }
@@ -218,12 +401,313 @@ fn try_add_gauge(
     metric_name: &str,
     metric_desc: &str,
     registry: &Registry,
-    map: &mut BTreeMap<String, IntGauge>,
+    map: &mut BTreeMap<String, GaugeVec>,
 ) -> Result<(), AppError> {
     let opts = Opts::new(metric_name, metric_desc);
-    let gauge = IntGauge::with_opts(opts)?;
+    let gauge = GaugeVec::new(opts, &LABELS)?;
     registry.register(Box::new(gauge.clone()))?;
     map.insert(metric_name.to_owned(), gauge);
 
     Ok(())
 }
+
+// The text format wants each family once, with every sample under it
+fn merge_snapshots(snapshots: Vec<Vec<MetricFamily>>) -> Vec<MetricFamily> {
+    let mut merged: BTreeMap<String, MetricFamily> = BTreeMap::new();
+    for mut family in snapshots.into_iter().flatten() {
+        match merged.get_mut(family.get_name()) {
+            Some(existing) => {
+                for metric in family.take_metric().into_vec() {
+                    existing.mut_metric().push(metric);
+                }
+            }
+            None => {
+                merged.insert(family.get_name().to_owned(), family);
+            }
+        }
+    }
+    merged.into_values().collect()
+}
+
+/// Answers Prometheus scrapes of `/metrics` until cancelled
+pub(super) async fn serve_scrapes(
+    listener: TcpListener,
+    registry: Registry,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, peer)) => {
+                        let registry = registry.clone();
+                        tokio::spawn(async move {
+                            match tokio::time::timeout(SCRAPE_TIMEOUT, answer_scrape(stream, &registry)).await {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => warn!("Prometheus: Failed to answer {peer}: {e}"),
+                                Err(_) => warn!("Prometheus: {peer} timed out"),
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("Prometheus: Failed to accept scrape: {e}");
+                    }
+                }
+            }
+            _ = cancel_token.cancelled() => {
+                info!("Prometheus scrape server shutting down");
+                return;
+            }
+        }
+    }
+}
+
+// Just enough HTTP/1.1 for scrapers and curl, one request per connection
+async fn answer_scrape<S>(mut stream: S, registry: &Registry) -> Result<(), AppError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_BYTES {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line
+        .next()
+        .and_then(|target| target.split('?').next())
+        .unwrap_or_default();
+
+    let encoder = TextEncoder::new();
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => {
+            let mut body = vec![];
+            encoder.encode(&registry.gather(), &mut body)?;
+            ("200 OK", encoder.format_type(), body)
+        }
+        ("GET", _) => ("404 Not Found", "text/plain", b"Not Found\n".to_vec()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method Not Allowed\n".to_vec(),
+        ),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{PrometheusMetrics, ZoneMethod, ZoneSettings};
+    use tokio::sync::mpsc;
+
+    fn actor() -> PrometheusLoggingActor {
+        actor_with(PrometheusSettings {
+            enabled: false,
+            scrape_enabled: true,
+            ..Default::default()
+        })
+    }
+
+    fn actor_with(settings: PrometheusSettings) -> PrometheusLoggingActor {
+        let zone_settings = ZoneSettings {
+            method: ZoneMethod::PercentMax,
+            max_bpm: 200,
+            resting_bpm: 60,
+            percentages: vec![50, 60, 70, 80, 90],
+            custom_thresholds: vec![],
+        };
+        let settings = PrometheusSettings {
+            metrics: PrometheusMetrics {
+                bpm: "heart_rate_bpm".into(),
+                ..Default::default()
+            },
+            ..settings
+        };
+        PrometheusLoggingActor::build(
+            1,
+            "Sitting".into(),
+            settings,
+            HrZones::build(&zone_settings, 0).unwrap(),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    async fn scrape(request: &str, registry: &Registry) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client.write_all(request.as_bytes()).await.unwrap();
+        answer_scrape(server, registry).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn pusher(url: String, protocol: PrometheusProtocol) -> PrometheusLoggingActor {
+        actor_with(PrometheusSettings {
+            enabled: true,
+            url,
+            protocol,
+            batch_size: 2,
+            retry_queue_size: 2,
+            ..Default::default()
+        })
+    }
+
+    /// Stands in for the push endpoint, answering each POST with the next status
+    /// and passing along the request's head and body
+    async fn stand_in(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let head_end = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap();
+                while request.len() < head_end + length {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let response = format!(
+                    "HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                tx.send((head, request.split_off(head_end))).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn status(bpm: u16) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            timestamp: Local::now(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn labels_follow_device_and_activity() {
+        let mut actor = actor();
+        actor.device = "Polar H10".into();
+        actor.handle_data(status(80)).await.unwrap();
+        actor.activity_name = "Dancing".into();
+        actor.handle_data(status(120)).await.unwrap();
+
+        let response = scrape(
+            "GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n",
+            &actor.registry(),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains(r#"heart_rate_bpm{activity="Dancing",device="Polar H10"} 120"#));
+        // The previous activity's series is dropped instead of going stale
+        assert!(!response.contains("Sitting"));
+    }
+
+    #[tokio::test]
+    async fn only_metrics_is_served() {
+        let registry = actor().registry();
+        let not_found = scrape("GET / HTTP/1.1\r\n\r\n", &registry).await;
+        assert!(not_found.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let not_allowed = scrape("POST /metrics HTTP/1.1\r\n\r\n", &registry).await;
+        assert!(not_allowed.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        let query = scrape("GET /metrics?x=1 HTTP/1.1\r\n\r\n", &registry).await;
+        assert!(query.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn batches_are_retried_in_order() {
+        let (url, mut requests) = stand_in(vec![503, 200, 200]).await;
+        let mut actor = pusher(url, PrometheusProtocol::Text);
+
+        actor.handle_data(status(80)).await.unwrap();
+        assert!(requests.try_recv().is_err(), "batch isn't full yet");
+        actor.handle_data(status(81)).await.unwrap();
+        let (_, failed) = requests.recv().await.unwrap();
+        assert_eq!(actor.retry_queue.len(), 1);
+
+        actor.handle_data(status(82)).await.unwrap();
+        actor.handle_data(status(83)).await.unwrap();
+        let (_, retried) = requests.recv().await.unwrap();
+        let (_, second) = requests.recv().await.unwrap();
+        assert!(actor.retry_queue.is_empty());
+
+        assert_eq!(failed, retried);
+        let first = String::from_utf8(retried).unwrap();
+        // One family, both samples under it
+        assert_eq!(first.matches("# TYPE heart_rate_bpm").count(), 1);
+        assert!(first.contains(" 80 ") && first.contains(" 81 "));
+        let second = String::from_utf8(second).unwrap();
+        assert!(second.contains(" 82 ") && second.contains(" 83 "));
+    }
+
+    #[tokio::test]
+    async fn retry_queue_is_bounded() {
+        // Nothing's listening here once the listener is dropped
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let mut actor = pusher(url, PrometheusProtocol::Text);
+        for bpm in 80..86 {
+            actor.handle_data(status(bpm)).await.unwrap();
+        }
+        assert_eq!(actor.retry_queue.len(), 2);
+        let oldest = String::from_utf8(actor.retry_queue[0].clone()).unwrap();
+        assert!(oldest.contains(" 82 "), "{oldest}");
+    }
+
+    #[tokio::test]
+    async fn remote_write() {
+        let (url, mut requests) = stand_in(vec![204]).await;
+        let mut actor = pusher(url, PrometheusProtocol::RemoteWrite);
+        actor.device = "Polar H10".into();
+        actor.handle_data(status(80)).await.unwrap();
+        actor.handle_data(status(81)).await.unwrap();
+
+        let (head, body) = requests.recv().await.unwrap();
+        assert!(actor.retry_queue.is_empty());
+        assert!(head.contains("content-type: application/x-protobuf"));
+        assert!(head.contains("content-encoding: snappy"));
+        assert!(head.contains("x-prometheus-remote-write-version: 0.1.0"));
+        let request = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+        let request = String::from_utf8_lossy(&request);
+        assert!(request.contains("heart_rate_bpm") && request.contains("Polar H10"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Just enough protobuf for Prometheus' remote-write `WriteRequest`,
+//! see https://prometheus.io/docs/concepts/remote_write_spec/
+
+use prometheus::proto::MetricFamily;
+use std::collections::BTreeMap;
+
+use crate::errors::AppError;
+
+const NAME_LABEL: &str = "__name__";
+
+// Protobuf wire types
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+
+/// Sorted label pairs, `__name__` included
+type Series = Vec<(String, String)>;
+
+/// Encodes gathered (and timestamped) snapshots as a snappy-compressed `WriteRequest`
+pub(super) fn encode_write_request(snapshots: &[Vec<MetricFamily>]) -> Result<Vec<u8>, AppError> {
+    let mut series: BTreeMap<Series, Vec<(f64, i64)>> = BTreeMap::new();
+    for family in snapshots.iter().flatten() {
+        for metric in family.get_metric() {
+            let mut labels: Series = metric
+                .get_label()
+                .iter()
+                .map(|pair| (pair.get_name().to_owned(), pair.get_value().to_owned()))
+                .collect();
+            labels.push((NAME_LABEL.to_owned(), family.get_name().to_owned()));
+            labels.sort();
+            // Snapshots are in order, so the samples are too
+            series
+                .entry(labels)
+                .or_default()
+                .push((metric.get_gauge().get_value(), metric.get_timestamp_ms()));
+        }
+    }
+
+    let mut request = Vec::new();
+    for (labels, samples) in series {
+        let mut time_series = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            write_bytes(&mut label, 1, name.as_bytes());
+            write_bytes(&mut label, 2, value.as_bytes());
+            write_bytes(&mut time_series, 1, &label);
+        }
+        for (value, timestamp_ms) in samples {
+            let mut sample = Vec::new();
+            write_key(&mut sample, 1, FIXED64);
+            sample.extend_from_slice(&value.to_le_bytes());
+            write_key(&mut sample, 2, VARINT);
+            write_varint(&mut sample, timestamp_ms as u64);
+            write_bytes(&mut time_series, 2, &sample);
+        }
+        write_bytes(&mut request, 1, &time_series);
+    }
+
+    Ok(snap::raw::Encoder::new().compress_vec(&request)?)
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u8, wire_type: u8) {
+    buf.push(field << 3 | wire_type);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    write_key(buf, field, LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Opts, Registry};
+
+    fn snapshot(registry: &Registry, timestamp_ms: i64) -> Vec<MetricFamily> {
+        let mut families = registry.gather();
+        for family in families.iter_mut() {
+            for metric in family.mut_metric() {
+                metric.set_timestamp_ms(timestamp_ms);
+            }
+        }
+        families
+    }
+
+    #[test]
+    fn varints() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (300, vec![0xAC, 0x02]),
+            (1_700_000_000_000, vec![0x80, 0xD0, 0x95, 0xFF, 0xBC, 0x31]),
+        ] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(buf, expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn write_request() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("hr", "Heart rate"), &["device"]).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.with_label_values(&["a"]).set(1.0);
+        let first = snapshot(&registry, 2);
+        gauge.with_label_values(&["a"]).set(2.0);
+        let second = snapshot(&registry, 3);
+
+        let compressed = encode_write_request(&[first, second]).unwrap();
+        let request = snap::raw::Decoder::new()
+            .decompress_vec(&compressed)
+            .unwrap();
+
+        let mut expected = vec![0x0A, 55];
+        // __name__ sorts before device
+        expected.extend([0x0A, 14, 0x0A, 8]);
+        expected.extend(b"__name__");
+        expected.extend([0x12, 2]);
+        expected.extend(b"hr");
+        expected.extend([0x0A, 11, 0x0A, 6]);
+        expected.extend(b"device");
+        expected.extend([0x12, 1]);
+        expected.extend(b"a");
+        // One series, both samples
+        for (value, timestamp) in [(1.0f64, 2), (2.0, 3)] {
+            expected.extend([0x12, 11, 0x09]);
+            expected.extend(value.to_le_bytes());
+            expected.extend([0x10, timestamp]);
+        }
+        assert_eq!(request, expected);
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::{DateTime, Local};
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::settings::CsvSettings;
+
+use super::SESSION_FILE_PREFIX;
+
+const START_PLACEHOLDER: &str = "{start}";
+const ACTIVITY_PLACEHOLDER: &str = "{activity}";
+
+/// Why the current CSV gets closed and a new one started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RotateReason {
+    ActivityChanged,
+    Idle,
+    Midnight,
+    SizeLimit,
+}
+
+/// The CSV currently being written, as far as the rules care
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Segment {
+    pub start: DateTime<Local>,
+    pub activity: u8,
+    pub bytes: u64,
+}
+
+/// When to start a new session CSV, and what to call it
+#[derive(Debug, Clone)]
+pub(super) struct RotationRules {
+    template: String,
+    on_activity_change: bool,
+    idle: Option<Duration>,
+    at_midnight: bool,
+    max_bytes: Option<u64>,
+}
+
+impl RotationRules {
+    pub fn build(settings: &CsvSettings) -> Result<Self, AppError> {
+        let template = &settings.file_name_template;
+        // Keeps the files sorted by time, and readable by the log browser
+        if !template.starts_with(START_PLACEHOLDER) {
+            return Err(AppError::CsvSchema(format!(
+                "file_name_template must start with {START_PLACEHOLDER}, not \"{template}\""
+            )));
+        }
+        if template.contains(['.', '/', '\\']) {
+            return Err(AppError::CsvSchema(format!(
+                "file_name_template can't contain '.', '/' or '\\', \"{template}\""
+            )));
+        }
+        Ok(Self {
+            template: template.clone(),
+            on_activity_change: settings.rotate_on_activity_change,
+            idle: (settings.rotate_after_idle_mins > 0)
+                .then(|| Duration::from_secs(settings.rotate_after_idle_mins as u64 * 60)),
+            at_midnight: settings.rotate_at_midnight,
+            max_bytes: (settings.rotate_at_size_kb > 0)
+                .then_some(settings.rotate_at_size_kb * 1024),
+        })
+    }
+    /// Only worth checking the file's size if there's a limit
+    pub fn has_size_limit(&self) -> bool {
+        self.max_bytes.is_some()
+    }
+    /// Checked before writing each row, `last_data` being when the previous row was written
+    pub fn check(
+        &self,
+        segment: &Segment,
+        now: DateTime<Local>,
+        last_data: DateTime<Local>,
+        activity: u8,
+    ) -> Option<RotateReason> {
+        if self.on_activity_change && activity != segment.activity {
+            Some(RotateReason::ActivityChanged)
+        } else if self
+            .idle
+            .is_some_and(|idle| (now - last_data).to_std().unwrap_or_default() >= idle)
+        {
+            Some(RotateReason::Idle)
+        } else if self.at_midnight && now.date_naive() != segment.start.date_naive() {
+            Some(RotateReason::Midnight)
+        } else if self.max_bytes.is_some_and(|max| segment.bytes >= max) {
+            Some(RotateReason::SizeLimit)
+        } else {
+            None
+        }
+    }
+    /// File name (without extension) for a CSV started at `start`,
+    /// `{activity}` is left out along with its separator when there's no activity name
+    pub fn file_stem(
+        &self,
+        start: DateTime<Local>,
+        activity: u8,
+        activity_name: &str,
+        part: u32,
+    ) -> String {
+        let mut template = self.template.clone();
+        if activity_name.is_empty() {
+            for separator in ['_', '-', ' '] {
+                template = template.replace(&format!("{separator}{ACTIVITY_PLACEHOLDER}"), "");
+            }
+        }
+        let name = template
+            .replace(
+                START_PLACEHOLDER,
+                &start.format("%Y-%m-%d_%H-%M-%S").to_string(),
+            )
+            .replace(ACTIVITY_PLACEHOLDER, &file_safe(activity_name))
+            .replace("{activity_index}", &activity.to_string())
+            .replace("{part}", &part.to_string());
+        format!("{SESSION_FILE_PREFIX}{name}")
+    }
+}
+
+// Activity names can be anything, but the log browser skips stems with dots in them
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::CsvColumn;
+    use chrono::TimeZone;
+
+    fn settings() -> CsvSettings {
+        CsvSettings {
+            columns: CsvColumn::ALL.to_vec(),
+            delimiter: ",".into(),
+            file_name_template: "{start}".into(),
+            ..Default::default()
+        }
+    }
+
+    fn at(hour: u32, min: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, hour, min, 0).unwrap()
+    }
+
+    fn segment() -> Segment {
+        Segment {
+            start: at(22, 0),
+            activity: 1,
+            bytes: 0,
+        }
+    }
+
+    #[test]
+    fn default_never_rotates() {
+        let rules = RotationRules::build(&settings()).unwrap();
+        let late = at(23, 59) + Duration::from_secs(3600);
+        let huge = Segment {
+            bytes: u64::MAX,
+            ..segment()
+        };
+        assert_eq!(rules.check(&huge, late, at(22, 0), 5), None);
+        assert!(!rules.has_size_limit());
+        assert_eq!(
+            rules.file_stem(at(22, 0), 1, "Dancing", 1),
+            "nih-2024-05-01_22-00-00"
+        );
+    }
+
+    #[test]
+    fn rotation_reasons() {
+        let rules = RotationRules::build(&CsvSettings {
+            rotate_on_activity_change: true,
+            rotate_after_idle_mins: 5,
+            rotate_at_midnight: true,
+            rotate_at_size_kb: 1,
+            ..settings()
+        })
+        .unwrap();
+        let check = |segment: &Segment, now, last_data, activity| {
+            rules.check(segment, now, last_data, activity)
+        };
+        assert_eq!(check(&segment(), at(22, 10), at(22, 9), 1), None);
+        assert_eq!(
+            check(&segment(), at(22, 10), at(22, 9), 2),
+            Some(RotateReason::ActivityChanged)
+        );
+        assert_eq!(
+            check(&segment(), at(22, 10), at(22, 5), 1),
+            Some(RotateReason::Idle)
+        );
+        let tomorrow = at(23, 59) + Duration::from_secs(120);
+        assert_eq!(
+            check(&segment(), tomorrow, tomorrow, 1),
+            Some(RotateReason::Midnight)
+        );
+        let full = Segment {
+            bytes: 1024,
+            ..segment()
+        };
+        assert_eq!(
+            check(&full, at(22, 10), at(22, 9), 1),
+            Some(RotateReason::SizeLimit)
+        );
+    }
+
+    #[test]
+    fn file_names() {
+        let rules = RotationRules::build(&CsvSettings {
+            file_name_template: "{start}_{activity}_{activity_index}-{part}".into(),
+            ..settings()
+        })
+        .unwrap();
+        assert_eq!(
+            rules.file_stem(at(22, 0), 3, "Beat Saber (Expert+)", 2),
+            "nih-2024-05-01_22-00-00_Beat_Saber__Expert___3-2"
+        );
+        assert_eq!(
+            rules.file_stem(at(22, 0), 0, "", 1),
+            "nih-2024-05-01_22-00-00_0-1"
+        );
+    }
+
+    #[test]
+    fn default_template() {
+        let rules = RotationRules::build(&CsvSettings {
+            file_name_template: "{start}_{activity}".into(),
+            ..settings()
+        })
+        .unwrap();
+        assert_eq!(
+            rules.file_stem(at(22, 0), 2, "Dancing", 1),
+            "nih-2024-05-01_22-00-00_Dancing"
+        );
+        // Activities are off, so it's named the same as it always was
+        assert_eq!(
+            rules.file_stem(at(22, 0), 0, "", 1),
+            "nih-2024-05-01_22-00-00"
+        );
+    }
+
+    #[test]
+    fn bad_templates() {
+        for template in ["{activity}_{start}", "{start}.{part}", "{start}/{activity}"] {
+            let settings = CsvSettings {
+                file_name_template: template.into(),
+                ..settings()
+            };
+            assert!(RotationRules::build(&settings).is_err(), "{template}");
+        }
+    }
+}
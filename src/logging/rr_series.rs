@@ -0,0 +1,303 @@
+use chrono::{DateTime, Local, TimeDelta};
+use csv_async::AsyncSerializer;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+
+use crate::errors::AppError;
+use crate::heart_rate::HeartRateStatus;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+// How far the beat clock can drift from the packets' arrival times before it's resynced,
+// (after dropped packets or a disconnection, the intervals no longer add up)
+const MAX_CLOCK_DRIFT: Duration = Duration::from_secs(2);
+// Polar's HRM "Interval" value for R-R recordings
+const HRM_RR_INTERVAL: u16 = 238;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+struct RrRow {
+    /// When the beat that ended this interval happened (reconstructed)
+    Timestamp: String,
+    /// Seconds since the session's first beat
+    Elapsed: f64,
+    /// Milliseconds
+    RR: f64,
+    Flag: &'static str,
+    BPM: u16,
+}
+
+/// Reconstructs when each beat happened.
+///
+/// Monitors send a packet every second or so with the intervals that ended since the last one,
+/// so beats are placed by adding up intervals, anchored to the packets' arrival times.
+#[derive(Debug, Default)]
+pub(super) struct BeatClock {
+    last_beat: Option<DateTime<Local>>,
+}
+
+impl BeatClock {
+    /// Returns when each interval's closing beat happened
+    pub fn place(
+        &mut self,
+        rr_intervals: &[Duration],
+        received: DateTime<Local>,
+    ) -> Vec<DateTime<Local>> {
+        let total: Duration = rr_intervals.iter().sum();
+        // Assume the packet was sent right after its last beat
+        let anchored_first =
+            received - delta(total) + delta(*rr_intervals.first().unwrap_or(&Duration::ZERO));
+        let mut beat = match self.last_beat {
+            Some(last_beat) => {
+                let counted_first =
+                    last_beat + delta(*rr_intervals.first().unwrap_or(&Duration::ZERO));
+                let drift = (counted_first - anchored_first).abs();
+                if drift.to_std().unwrap_or_default() > MAX_CLOCK_DRIFT {
+                    anchored_first
+                } else {
+                    counted_first
+                }
+            }
+            None => anchored_first,
+        };
+        let mut beats = Vec::with_capacity(rr_intervals.len());
+        for (i, rr) in rr_intervals.iter().enumerate() {
+            if i > 0 {
+                beat += delta(*rr);
+            }
+            beats.push(beat);
+        }
+        if let Some(last) = beats.last() {
+            self.last_beat = Some(*last);
+        }
+        beats
+    }
+}
+
+/// Logs every RR interval to `<session>.rr.csv` as it comes in,
+/// then writes Kubios and Polar HRM sidecars once the session's over
+pub(super) struct RrSeriesLogger {
+    csv_writer: AsyncSerializer<File>,
+    csv_path: PathBuf,
+    sidecar_stem: PathBuf,
+    clock: BeatClock,
+    first_beat: Option<DateTime<Local>>,
+    intervals: Vec<Duration>,
+}
+
+impl RrSeriesLogger {
+    pub async fn create(folder: &Path, stem: &str) -> Result<Self, AppError> {
+        let csv_path = folder.join(format!("{stem}.rr.csv"));
+        let file = File::create(&csv_path)
+            .await
+            .map_err(|e| AppError::CreateFile {
+                path: csv_path.clone(),
+                source: e,
+            })?;
+        Ok(Self {
+            csv_writer: AsyncSerializer::from_writer(file),
+            csv_path,
+            sidecar_stem: folder.join(stem),
+            clock: BeatClock::default(),
+            first_beat: None,
+            intervals: Vec::new(),
+        })
+    }
+    /// Every interval in `rr_intervals` is written as a beat,
+    /// sources only put beats there that are new in this packet
+    pub async fn record(&mut self, heart_rate_status: &HeartRateStatus) -> Result<(), AppError> {
+        if heart_rate_status.rr_intervals.is_empty() {
+            return Ok(());
+        }
+        let beats = self
+            .clock
+            .place(&heart_rate_status.rr_intervals, heart_rate_status.timestamp);
+        for (i, (rr, beat)) in heart_rate_status.rr_intervals.iter().zip(beats).enumerate() {
+            let first_beat = *self.first_beat.get_or_insert(beat);
+            let flag = heart_rate_status
+                .rr_flags
+                .get(i)
+                .copied()
+                .unwrap_or_default();
+            self.csv_writer
+                .serialize(RrRow {
+                    Timestamp: beat.format(TIMESTAMP_FORMAT).to_string(),
+                    Elapsed: round_to(
+                        (beat - first_beat).num_microseconds().unwrap_or_default() as f64 / 1e6,
+                        6,
+                    ),
+                    RR: round_to(rr.as_secs_f64() * 1000.0, 3),
+                    Flag: flag.as_str(),
+                    BPM: heart_rate_status.heart_rate_bpm,
+                })
+                .await?;
+            self.intervals.push(*rr);
+        }
+        self.csv_writer
+            .flush()
+            .await
+            .map_err(|e| AppError::WriteFile {
+                path: self.csv_path.clone(),
+                source: e,
+            })?;
+        Ok(())
+    }
+    /// Writes the sidecars, if there were any intervals
+    pub async fn finish(mut self) -> Result<(), AppError> {
+        self.csv_writer
+            .flush()
+            .await
+            .map_err(|e| AppError::WriteFile {
+                path: self.csv_path.clone(),
+                source: e,
+            })?;
+        let Some(first_beat) = self.first_beat else {
+            return Ok(());
+        };
+        // The recording started at the beat before the first interval's
+        let start = first_beat - delta(self.intervals[0]);
+        let sidecars = [
+            ("kubios.txt", kubios_txt(&self.intervals)),
+            ("hrm", polar_hrm(start, &self.intervals)),
+        ];
+        for (extension, contents) in sidecars {
+            let path = self.sidecar_stem.with_extension(extension);
+            tokio::fs::write(&path, contents)
+                .await
+                .map_err(|e| AppError::WriteFile { path, source: e })?;
+        }
+        Ok(())
+    }
+}
+
+/// One interval per line in milliseconds, which Kubios HRV imports as a custom ASCII file
+pub fn kubios_txt(intervals: &[Duration]) -> String {
+    intervals.iter().fold(String::new(), |mut out, rr| {
+        // Writing to a String can't fail
+        let _ = writeln!(out, "{:.3}", rr.as_secs_f64() * 1000.0);
+        out
+    })
+}
+
+/// Polar's HRM format, as an R-R recording
+pub fn polar_hrm(start: DateTime<Local>, intervals: &[Duration]) -> String {
+    let length: Duration = intervals.iter().sum();
+    let secs = length.as_secs();
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = write!(
+        out,
+        "[Params]\r\nVersion=106\r\nMonitor=0\r\nSMode=000000000\r\nDate={}\r\nStartTime={}.0\r\nLength={:02}:{:02}:{:02}.{}\r\nInterval={HRM_RR_INTERVAL}\r\n\r\n[HRData]\r\n",
+        start.format("%Y%m%d"),
+        start.format("%H:%M:%S"),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        length.subsec_millis() / 100,
+    );
+    for rr in intervals {
+        let _ = write!(out, "{}\r\n", rr.as_millis());
+    }
+    out
+}
+
+fn delta(duration: Duration) -> TimeDelta {
+    TimeDelta::from_std(duration).unwrap_or_default()
+}
+
+fn round_to(value: f64, decimals: i32) -> f64 {
+    let scale = 10f64.powi(decimals);
+    (value * scale).round() / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn start() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn places_every_beat_in_a_packet() {
+        let mut clock = BeatClock::default();
+        let received = start() + ms(2000);
+        let beats = clock.place(&[ms(800), ms(700), ms(500)], received);
+        assert_eq!(
+            beats,
+            vec![received - ms(1200), received - ms(500), received]
+        );
+    }
+
+    #[test]
+    fn keeps_counting_through_late_packets() {
+        let mut clock = BeatClock::default();
+        clock.place(&[Duration::from_micros(976_562)], start());
+        // Arrives a bit late, but still adds up
+        let beats = clock.place(&[Duration::from_micros(976_562)], start() + ms(1300));
+        assert_eq!(beats, vec![start() + Duration::from_micros(976_562)]);
+    }
+
+    #[test]
+    fn resyncs_after_gap() {
+        let mut clock = BeatClock::default();
+        clock.place(&[ms(1000)], start());
+        // A minute of dropped packets
+        let received = start() + Duration::from_secs(60);
+        let beats = clock.place(&[ms(900), ms(1000)], received);
+        assert_eq!(beats, vec![received - ms(1000), received]);
+    }
+
+    #[tokio::test]
+    async fn records_each_beat_once() {
+        let folder = tempfile::tempdir().unwrap();
+        let mut logger = RrSeriesLogger::create(folder.path(), "session")
+            .await
+            .unwrap();
+        let status = |rr_ms: Option<u64>, secs: u64| HeartRateStatus {
+            heart_rate_bpm: 75,
+            rr_intervals: rr_ms.map(ms).into_iter().collect(),
+            last_rr: Some(ms(800)),
+            timestamp: start() + Duration::from_secs(secs),
+            ..Default::default()
+        };
+        logger.record(&status(Some(800), 1)).await.unwrap();
+        // Websocket messages without a new RR in them
+        for secs in 2..5 {
+            logger.record(&status(None, secs)).await.unwrap();
+        }
+        logger.record(&status(Some(810), 5)).await.unwrap();
+        logger.finish().await.unwrap();
+        let kubios = std::fs::read_to_string(folder.path().join("session.kubios.txt")).unwrap();
+        assert_eq!(kubios, "800.000\n810.000\n");
+    }
+
+    #[test]
+    fn kubios() {
+        let txt = kubios_txt(&[ms(800), Duration::from_micros(976_562)]);
+        assert_eq!(txt, "800.000\n976.562\n");
+    }
+
+    #[test]
+    fn hrm() {
+        let hrm = polar_hrm(start(), &[ms(800), ms(1200), ms(1000)]);
+        assert!(hrm.starts_with("[Params]\r\nVersion=106\r\n"));
+        assert!(hrm.contains(
+            "Date=20240501\r\nStartTime=10:00:00.0\r\nLength=00:00:03.0\r\nInterval=238\r\n"
+        ));
+        let data: Vec<&str> = hrm
+            .split("[HRData]\r\n")
+            .nth(1)
+            .unwrap()
+            .split_terminator("\r\n")
+            .collect();
+        assert_eq!(data, vec!["800", "1200", "1000"]);
+    }
+}
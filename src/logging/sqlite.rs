@@ -0,0 +1,494 @@
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver as BReceiver;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::app::AppUpdate;
+use crate::errors::AppError;
+use crate::heart_rate::{HeartRateStatus, RrFlag};
+use crate::hrv::HrvMetrics;
+use crate::structs::HrSource;
+use crate::zones::HrZones;
+
+use super::rr_series::BeatClock;
+
+/// Each entry upgrades the schema by one version, tracked in `PRAGMA user_version`.
+/// Never edit one that's been released, add another instead.
+const MIGRATIONS: &[&str] = &[
+    // v1
+    r#"
+    CREATE TABLE devices (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        name TEXT NOT NULL,
+        address TEXT NOT NULL,
+        UNIQUE (kind, name, address)
+    );
+    CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        -- Unix milliseconds
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER,
+        device_id INTEGER REFERENCES devices (id),
+        app_version TEXT NOT NULL
+    );
+    CREATE TABLE samples (
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp INTEGER NOT NULL,
+        bpm INTEGER NOT NULL,
+        rr_ms INTEGER,
+        artifacts INTEGER NOT NULL,
+        battery INTEGER,
+        activity INTEGER NOT NULL,
+        zone INTEGER NOT NULL,
+        rmssd REAL,
+        sdnn REAL,
+        pnn50 REAL,
+        stress_index REAL,
+        lf REAL,
+        hf REAL,
+        lf_hf REAL,
+        breaths_per_min REAL
+    );
+    CREATE INDEX samples_by_session ON samples (session_id, timestamp);
+    CREATE TABLE rr_beats (
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        -- When the beat ending the interval happened (reconstructed), Unix microseconds
+        beat_at INTEGER NOT NULL,
+        rr_ms REAL NOT NULL,
+        flag TEXT NOT NULL
+    );
+    CREATE INDEX rr_beats_by_session ON rr_beats (session_id, beat_at);
+    CREATE TABLE activity_changes (
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp INTEGER NOT NULL,
+        activity INTEGER NOT NULL,
+        name TEXT NOT NULL
+    );
+    "#,
+];
+
+/// Opens (or creates) the session database, bringing its schema up to date
+pub fn open_database(path: &Path) -> Result<Connection, AppError> {
+    if let Some(folder) = path
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(folder).map_err(|e| AppError::CreateDir {
+            path: folder.to_owned(),
+            source: e,
+        })?;
+    }
+    let mut conn = Connection::open(path)?;
+    // Readers (like sqlite3 or Grafana) can query it while a session's being logged
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    migrate(&mut conn, path)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection, path: &Path) -> Result<(), AppError> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version as usize > MIGRATIONS.len() {
+        return Err(AppError::SqliteNewerSchema {
+            path: path.to_owned(),
+            version,
+        });
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+        info!("SQLite: Migrated schema to v{}", i + 1);
+    }
+    Ok(())
+}
+
+pub(super) struct SqliteLoggingActor {
+    conn: Connection,
+    log_rr_beats: bool,
+    zones: HrZones,
+    // Only made once there's data, so idle launches don't leave empty sessions around
+    session_id: Option<i64>,
+    last_timestamp: Option<DateTime<Local>>,
+    clock: BeatClock,
+    source: Option<HrSource>,
+    last_rr: Duration,
+    activity: u8,
+    activity_name: String,
+    hrv: HrvMetrics,
+}
+
+impl SqliteLoggingActor {
+    pub(super) fn new(
+        conn: Connection,
+        log_rr_beats: bool,
+        initial_activity: u8,
+        initial_activity_name: String,
+        zones: HrZones,
+    ) -> Self {
+        Self {
+            conn,
+            log_rr_beats,
+            zones,
+            session_id: None,
+            last_timestamp: None,
+            clock: BeatClock::default(),
+            source: None,
+            last_rr: Duration::ZERO,
+            activity: initial_activity,
+            activity_name: initial_activity_name,
+            hrv: HrvMetrics::default(),
+        }
+    }
+    /// Every query blocks, so this runs on its own thread,
+    /// fed by `forward_updates` until the sender's dropped
+    pub(super) fn write_loop(
+        &mut self,
+        mut update_rx: UnboundedReceiver<AppUpdate>,
+    ) -> Result<(), AppError> {
+        while let Some(update) = update_rx.blocking_recv() {
+            match update {
+                AppUpdate::HeartRateStatus(data) => self.handle_data(&data)?,
+                AppUpdate::ActivitySelected(index, name) => self.handle_activity(index, name)?,
+                AppUpdate::DeviceConnected(source) => self.handle_source(source)?,
+                AppUpdate::Hrv(hrv) => self.hrv = hrv,
+                _ => {}
+            }
+        }
+        self.finish()
+    }
+    fn handle_data(&mut self, heart_rate_status: &HeartRateStatus) -> Result<(), AppError> {
+        if heart_rate_status.heart_rate_bpm == 0 {
+            return Ok(());
+        }
+        let session_id = match self.session_id {
+            Some(id) => id,
+            None => self.start_session(heart_rate_status.timestamp)?,
+        };
+        let reported_rr = heart_rate_status.latest_clean_rr().unwrap_or(self.last_rr);
+        let frequency = self.hrv.frequency.as_ref();
+        let battery: u8 = heart_rate_status.battery_level.into();
+        let transaction = self.conn.transaction()?;
+        transaction.execute(
+            "INSERT INTO samples (session_id, timestamp, bpm, rr_ms, artifacts, battery, activity,
+                zone, rmssd, sdnn, pnn50, stress_index, lf, hf, lf_hf, breaths_per_min)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            params![
+                session_id,
+                heart_rate_status.timestamp.timestamp_millis(),
+                heart_rate_status.heart_rate_bpm,
+                (!reported_rr.is_zero()).then_some(reported_rr.as_millis() as u64),
+                heart_rate_status
+                    .rr_flags
+                    .iter()
+                    .filter(|flag| **flag != RrFlag::Normal)
+                    .count(),
+                (battery > 0).then_some(battery),
+                self.activity,
+                self.zones.zone_of(heart_rate_status.heart_rate_bpm),
+                self.hrv.rmssd_ms,
+                self.hrv.sdnn_ms,
+                self.hrv.pnn50,
+                self.hrv.stress_index,
+                frequency.map(|f| f.lf_power),
+                frequency.map(|f| f.hf_power),
+                frequency.map(|f| f.lf_hf_ratio),
+                self.hrv.respiration_rate,
+            ],
+        )?;
+        if self.log_rr_beats && !heart_rate_status.rr_intervals.is_empty() {
+            let beats = self
+                .clock
+                .place(&heart_rate_status.rr_intervals, heart_rate_status.timestamp);
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO rr_beats (session_id, beat_at, rr_ms, flag) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (i, (rr, beat)) in heart_rate_status.rr_intervals.iter().zip(beats).enumerate() {
+                let flag = heart_rate_status
+                    .rr_flags
+                    .get(i)
+                    .copied()
+                    .unwrap_or_default();
+                insert.execute(params![
+                    session_id,
+                    beat.timestamp_micros(),
+                    rr.as_secs_f64() * 1000.0,
+                    flag.as_str(),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        self.last_rr = reported_rr;
+        self.last_timestamp = Some(heart_rate_status.timestamp);
+        Ok(())
+    }
+    fn start_session(&mut self, start: DateTime<Local>) -> Result<i64, AppError> {
+        let device_id = self
+            .source
+            .clone()
+            .map(|s| self.device_id(&s))
+            .transpose()?;
+        self.conn.execute(
+            "INSERT INTO sessions (started_at, device_id, app_version) VALUES (?1, ?2, ?3)",
+            params![
+                start.timestamp_millis(),
+                device_id,
+                env!("CARGO_PKG_VERSION")
+            ],
+        )?;
+        let session_id = self.conn.last_insert_rowid();
+        self.session_id = Some(session_id);
+        info!("SQLite: Started session {session_id}");
+        self.insert_activity_change(start)?;
+        Ok(session_id)
+    }
+    fn handle_activity(&mut self, index: u8, name: String) -> Result<(), AppError> {
+        self.activity = index;
+        self.activity_name = name;
+        if self.session_id.is_some() {
+            self.insert_activity_change(Local::now())?;
+        }
+        Ok(())
+    }
+    fn insert_activity_change(&self, timestamp: DateTime<Local>) -> Result<(), AppError> {
+        self.conn.execute(
+            "INSERT INTO activity_changes (session_id, timestamp, activity, name)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                self.session_id,
+                timestamp.timestamp_millis(),
+                self.activity,
+                self.activity_name
+            ],
+        )?;
+        Ok(())
+    }
+    fn handle_source(&mut self, source: HrSource) -> Result<(), AppError> {
+        // Connecting to a different device mid-session, the latest one wins
+        if let Some(session_id) = self.session_id {
+            let device_id = self.device_id(&source)?;
+            self.conn.execute(
+                "UPDATE sessions SET device_id = ?1 WHERE id = ?2",
+                params![device_id, session_id],
+            )?;
+        }
+        self.source = Some(source);
+        Ok(())
+    }
+    fn device_id(&self, source: &HrSource) -> Result<i64, AppError> {
+        let existing = self
+            .conn
+            .query_row(
+                "SELECT id FROM devices WHERE kind = ?1 AND name = ?2 AND address = ?3",
+                params![source.kind, source.name, source.address],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+        self.conn.execute(
+            "INSERT INTO devices (kind, name, address) VALUES (?1, ?2, ?3)",
+            params![source.kind, source.name, source.address],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+    fn finish(&mut self) -> Result<(), AppError> {
+        if let (Some(session_id), Some(last)) = (self.session_id, self.last_timestamp) {
+            self.conn.execute(
+                "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+                params![last.timestamp_millis(), session_id],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Passes along the updates the database cares about to `write_loop`
+pub(super) async fn forward_updates(
+    broadcast_rx: &mut BReceiver<AppUpdate>,
+    update_tx: UnboundedSender<AppUpdate>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            update = broadcast_rx.recv() => {
+                match update {
+                    Ok(
+                        update @ (AppUpdate::HeartRateStatus(_)
+                        | AppUpdate::ActivitySelected(..)
+                        | AppUpdate::DeviceConnected(_)
+                        | AppUpdate::Hrv(_)),
+                    ) => {
+                        // Only hangs up after an error, which gets reported once it's joined
+                        if update_tx.send(update).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => {
+                        error!("SQLite: Channel closed");
+                        return;
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("SQLite: Lagged! Missed {count} messages");
+                    }
+                }
+            }
+            _ = cancel_token.cancelled() => {
+                info!("SQLite thread shutting down");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{ZoneMethod, ZoneSettings};
+    use chrono::TimeZone;
+
+    fn actor(conn: Connection) -> SqliteLoggingActor {
+        let zone_settings = ZoneSettings {
+            method: ZoneMethod::PercentMax,
+            max_bpm: 200,
+            resting_bpm: 60,
+            percentages: vec![50, 60, 70, 80, 90],
+            custom_thresholds: vec![],
+        };
+        let zones = HrZones::build(&zone_settings, 0).unwrap();
+        SqliteLoggingActor::new(conn, true, 1, "Sitting".into(), zones)
+    }
+
+    fn status(secs: u64, bpm: u16, rr_ms: &[u64]) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            rr_intervals: rr_ms.iter().map(|ms| Duration::from_millis(*ms)).collect(),
+            timestamp: Local.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+                + Duration::from_secs(secs),
+            ..Default::default()
+        }
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn migrates_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("test.sqlite3");
+        let conn = open_database(&path).unwrap();
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        drop(conn);
+        // Nothing to do the second time around
+        open_database(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        drop(conn);
+        assert!(matches!(
+            open_database(&path),
+            Err(AppError::SqliteNewerSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn logs_a_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sqlite3");
+        let mut actor = actor(open_database(&path).unwrap());
+        actor
+            .handle_source(HrSource {
+                kind: "ble",
+                name: "Polar H10".into(),
+                address: "AA:BB".into(),
+            })
+            .unwrap();
+        // Nothing's logged until there's a heart rate
+        actor.handle_data(&status(0, 0, &[])).unwrap();
+        assert_eq!(count(&actor.conn, "sessions"), 0);
+
+        actor.handle_data(&status(1, 80, &[750, 760])).unwrap();
+        actor.handle_activity(2, "Dancing".into()).unwrap();
+        actor.handle_data(&status(2, 90, &[700])).unwrap();
+        actor.finish().unwrap();
+
+        let conn = &actor.conn;
+        assert_eq!(count(conn, "samples"), 2);
+        assert_eq!(count(conn, "rr_beats"), 3);
+        let (device, ended_at): (String, i64) = conn
+            .query_row(
+                "SELECT devices.name, sessions.ended_at FROM sessions
+                JOIN devices ON devices.id = sessions.device_id",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(device, "Polar H10");
+        assert_eq!(ended_at, status(2, 0, &[]).timestamp.timestamp_millis());
+        let activities: Vec<(u8, String)> = conn
+            .prepare("SELECT activity, name FROM activity_changes ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            activities,
+            vec![(1, "Sitting".to_owned()), (2, "Dancing".to_owned())]
+        );
+        let sample_activities: Vec<(u16, u8, Option<u64>)> = conn
+            .prepare("SELECT bpm, activity, rr_ms FROM samples ORDER BY timestamp")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            sample_activities,
+            vec![(80, 1, Some(760)), (90, 2, Some(700))]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_on_its_own_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sqlite3");
+        let mut actor = actor(open_database(&path).unwrap());
+        let (update_tx, update_rx) = tokio::sync::mpsc::unbounded_channel();
+        let writer = tokio::task::spawn_blocking(move || {
+            actor.write_loop(update_rx).unwrap();
+            actor
+        });
+
+        update_tx.send(status(1, 80, &[750]).into()).unwrap();
+        // Websocket messages without a new RR, the last one's only kept for display
+        for secs in 2..5 {
+            let mut repeated = status(secs, 80, &[]);
+            repeated.last_rr = Some(Duration::from_millis(750));
+            update_tx.send(repeated.into()).unwrap();
+        }
+        update_tx.send(status(5, 80, &[760]).into()).unwrap();
+        drop(update_tx);
+
+        let actor = writer.await.unwrap();
+        assert_eq!(count(&actor.conn, "samples"), 5);
+        assert_eq!(count(&actor.conn, "rr_beats"), 2);
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::settings::WebhookSettings;
+
+use super::http_export::{ExportSample, HttpExporter};
+
+/// POSTs `body` with its `{placeholders}` filled in, at most once per interval
+pub(super) struct WebhookExporter {
+    template: String,
+    interval: Duration,
+    last_sent: Option<DateTime<Local>>,
+}
+
+impl WebhookExporter {
+    pub fn build(settings: &WebhookSettings) -> Result<Self, AppError> {
+        let exporter = Self {
+            template: settings.body.clone(),
+            interval: Duration::from_secs_f32(settings.interval_secs.max(0.0)),
+            last_sent: None,
+        };
+        // Better to find out about typos now than from the receiver
+        let rendered = exporter.render(&ExportSample::default())?;
+        serde_json::from_str::<serde_json::Value>(&rendered)
+            .map_err(|e| AppError::WebhookBody(format!("not valid JSON ({e}): {rendered}")))?;
+        Ok(exporter)
+    }
+
+    fn render(&self, sample: &ExportSample) -> Result<String, AppError> {
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let name_len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            // Anything else is just JSON's own braces
+            if name_len == 0 || !after[name_len..].starts_with('}') {
+                rendered.push('{');
+                rest = after;
+                continue;
+            }
+            let name = &after[..name_len];
+            rendered.push_str(
+                &placeholder(name, sample).ok_or_else(|| {
+                    AppError::WebhookBody(format!("unknown placeholder {{{name}}}"))
+                })?,
+            );
+            rest = &after[name_len + 1..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+// Values are already JSON, so strings come quoted and missing values are null
+fn placeholder(name: &str, sample: &ExportSample) -> Option<String> {
+    let hrv = &sample.hrv;
+    let frequency = hrv.frequency.as_ref();
+    let json = match name {
+        "timestamp" => json_string(
+            &sample
+                .timestamp
+                .to_rfc3339_opts(SecondsFormat::Millis, false),
+        ),
+        "timestamp_ms" => sample.timestamp.timestamp_millis().to_string(),
+        "device" => json_string(&sample.device),
+        "activity" => json_string(&sample.activity_name),
+        "activity_index" => sample.activity.to_string(),
+        "bpm" => sample.bpm.to_string(),
+        "rr_ms" => json_f64(Some(sample.rr_ms)),
+        "battery" => sample
+            .battery
+            .map_or_else(|| "null".to_owned(), |b| b.to_string()),
+        "twitch_up" => sample.twitch_up.to_string(),
+        "twitch_down" => sample.twitch_down.to_string(),
+        "zone" => sample.zone.to_string(),
+        "hrv_rmssd" => json_f32(Some(hrv.rmssd_ms)),
+        "hrv_sdnn" => json_f32(Some(hrv.sdnn_ms)),
+        "hrv_pnn50" => json_f32(Some(hrv.pnn50)),
+        "hrv_stress_index" => json_f32(Some(hrv.stress_index)),
+        "hrv_lf_power" => json_f32(frequency.map(|f| f.lf_power)),
+        "hrv_hf_power" => json_f32(frequency.map(|f| f.hf_power)),
+        "hrv_lf_hf_ratio" => json_f32(frequency.map(|f| f.lf_hf_ratio)),
+        "breaths_per_min" => json_f32(hrv.respiration_rate),
+        "calories" => json_f32(sample.calories),
+        "trimp" => json_f32(Some(sample.trimp)),
+        _ => return None,
+    };
+    Some(json)
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+// serde_json writes NaN and infinity as null
+fn json_f32(value: Option<f32>) -> String {
+    serde_json::to_string(&value).unwrap_or_else(|_| "null".to_owned())
+}
+
+fn json_f64(value: Option<f64>) -> String {
+    serde_json::to_string(&value).unwrap_or_else(|_| "null".to_owned())
+}
+
+impl HttpExporter for WebhookExporter {
+    fn name(&self) -> &'static str {
+        "Webhook"
+    }
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+    fn push(&mut self, sample: &ExportSample) -> Result<Option<Vec<u8>>, AppError> {
+        let due = self.last_sent.is_none_or(|last| {
+            (sample.timestamp - last).to_std().unwrap_or_default() >= self.interval
+        });
+        if !due {
+            return Ok(None);
+        }
+        self.last_sent = Some(sample.timestamp);
+        Ok(Some(self.render(sample)?.into_bytes()))
+    }
+    // Receivers only care about the latest values, which already went out
+    fn flush(&mut self) -> Result<Option<Vec<u8>>, AppError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hrv::HrvMetrics;
+    use chrono::TimeZone;
+
+    fn settings(body: &str, interval_secs: f32) -> WebhookSettings {
+        WebhookSettings {
+            body: body.into(),
+            interval_secs,
+            ..Default::default()
+        }
+    }
+
+    fn sample(secs: i64, bpm: u16) -> ExportSample {
+        ExportSample {
+            timestamp: Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap(),
+            device: "Polar \"H10\"".into(),
+            activity_name: "Dancing".into(),
+            bpm,
+            rr_ms: 750.0,
+            hrv: HrvMetrics {
+                rmssd_ms: 0.1,
+                stress_index: f32::NAN,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_json() {
+        let mut exporter = WebhookExporter::build(&settings(
+            r#"{"state": {bpm}, "attributes": {"device": {device}, "activity": {activity},
+                "rr": {rr_ms}, "rmssd": {hrv_rmssd}, "stress": {hrv_stress_index},
+                "battery": {battery}, "at": {timestamp_ms}}}"#,
+            0.0,
+        ))
+        .unwrap();
+        let body = exporter.push(&sample(0, 80)).unwrap().unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "state": 80,
+                "attributes": {
+                    "device": "Polar \"H10\"",
+                    "activity": "Dancing",
+                    "rr": 750.0,
+                    "rmssd": 0.1,
+                    "stress": null,
+                    "battery": null,
+                    "at": 1_700_000_000_000i64,
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn rate_limited() {
+        let mut exporter = WebhookExporter::build(&settings(r#"{"bpm": {bpm}}"#, 5.0)).unwrap();
+        let sent: Vec<u16> = (0..12)
+            .filter_map(|secs| {
+                exporter
+                    .push(&sample(secs, 60 + secs as u16))
+                    .unwrap()
+                    .map(|_| 60 + secs as u16)
+            })
+            .collect();
+        assert_eq!(sent, [60, 65, 70]);
+        assert_eq!(exporter.flush().unwrap(), None);
+    }
+
+    #[test]
+    fn bad_bodies() {
+        for body in [r#"{"bpm": {bmp}}"#, r#"{"bpm": {bpm}"#, "bpm={bpm}"] {
+            assert!(
+                WebhookExporter::build(&settings(body, 1.0)).is_err(),
+                "{body}"
+            );
+        }
+    }
+}
@@ -1,8 +1,23 @@
-use iron_heart::{args::TopLevelCmd, run_tui, AppResult};
+use iron_heart::{
+    args::{SubCommands, TopLevelCmd},
+    run_analyze, run_export, run_tui, AppResult,
+};
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
     let arg_config: TopLevelCmd = argh::from_env();
+    let offline = match &arg_config.subcommands {
+        Some(SubCommands::Analyze(analyze)) => Some(run_analyze(analyze)),
+        Some(SubCommands::Export(export)) => Some(run_export(export)),
+        _ => None,
+    };
+    if let Some(result) = offline {
+        if let Err(e) = result {
+            eprintln!("An error occurred: {e}");
+            return Err(e.into());
+        }
+        return Ok(());
+    }
     if let Err(e) = run_tui(arg_config).await {
         eprintln!("An error occurred: {e}");
         Err(e)
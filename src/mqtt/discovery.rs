@@ -0,0 +1,155 @@
+//! Home Assistant MQTT discovery configs,
+//! see https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+
+use serde_json::{json, Value};
+
+use crate::settings::MqttSettings;
+
+use super::{STATUS_OFFLINE, STATUS_ONLINE};
+
+struct Entity {
+    component: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    extra: Value,
+}
+
+fn entities() -> [Entity; 6] {
+    [
+        Entity {
+            component: "sensor",
+            object_id: "bpm",
+            name: "Heart Rate",
+            extra: json!({
+                "unit_of_measurement": "bpm",
+                "state_class": "measurement",
+                "icon": "mdi:heart-pulse",
+            }),
+        },
+        Entity {
+            component: "sensor",
+            object_id: "rr",
+            name: "RR Interval",
+            extra: json!({
+                "unit_of_measurement": "ms",
+                "state_class": "measurement",
+                "icon": "mdi:timer-outline",
+            }),
+        },
+        Entity {
+            component: "sensor",
+            object_id: "battery",
+            name: "Battery",
+            extra: json!({
+                "device_class": "battery",
+                "unit_of_measurement": "%",
+                "state_class": "measurement",
+            }),
+        },
+        Entity {
+            component: "sensor",
+            object_id: "activity",
+            name: "Activity",
+            extra: json!({ "icon": "mdi:run" }),
+        },
+        Entity {
+            component: "sensor",
+            object_id: "device",
+            name: "Heart Rate Monitor",
+            extra: json!({ "icon": "mdi:bluetooth" }),
+        },
+        Entity {
+            component: "binary_sensor",
+            object_id: "connected",
+            name: "Connected",
+            extra: json!({
+                "device_class": "connectivity",
+                "payload_on": "ON",
+                "payload_off": "OFF",
+            }),
+        },
+    ]
+}
+
+/// Topic and (retained) payload for every sensor
+pub(super) fn configs(settings: &MqttSettings) -> Vec<(String, String)> {
+    let node_id = node_id(&settings.client_id);
+    let prefix = &settings.topic_prefix;
+    let device = json!({
+        "identifiers": [node_id],
+        "name": settings.client_id,
+        "manufacturer": "iron-heart",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    entities()
+        .into_iter()
+        .map(|entity| {
+            let mut config = json!({
+                "name": entity.name,
+                "unique_id": format!("{node_id}_{}", entity.object_id),
+                "state_topic": format!("{prefix}/{}", entity.object_id),
+                "availability_topic": format!("{prefix}/status"),
+                "payload_available": STATUS_ONLINE,
+                "payload_not_available": STATUS_OFFLINE,
+                "device": device,
+            });
+            if let (Some(config), Value::Object(extra)) = (config.as_object_mut(), entity.extra) {
+                config.extend(extra);
+            }
+            let topic = format!(
+                "{}/{}/{node_id}/{}/config",
+                settings.discovery_prefix, entity.component, entity.object_id
+            );
+            (topic, config.to_string())
+        })
+        .collect()
+}
+
+// Home Assistant only allows these in discovery topics
+fn node_id(client_id: &str) -> String {
+    client_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensor_configs() {
+        let settings = MqttSettings {
+            client_id: "iron heart".into(),
+            topic_prefix: "hr".into(),
+            discovery_prefix: "homeassistant".into(),
+            ..Default::default()
+        };
+        let configs = configs(&settings);
+        assert_eq!(configs.len(), 6);
+
+        let (topic, payload) = &configs[0];
+        assert_eq!(topic, "homeassistant/sensor/iron_heart/bpm/config");
+        let payload: Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["unique_id"], "iron_heart_bpm");
+        assert_eq!(payload["state_topic"], "hr/bpm");
+        assert_eq!(payload["availability_topic"], "hr/status");
+        assert_eq!(payload["unit_of_measurement"], "bpm");
+        assert_eq!(payload["device"]["identifiers"][0], "iron_heart");
+
+        let (topic, payload) = &configs[5];
+        assert_eq!(
+            topic,
+            "homeassistant/binary_sensor/iron_heart/connected/config"
+        );
+        let payload: Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["device_class"], "connectivity");
+    }
+}
@@ -0,0 +1,496 @@
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::app::{AppUpdate, ErrorPopup};
+use crate::broadcast;
+use crate::errors::AppError;
+use crate::heart_rate::{BatteryLevel, HeartRateStatus};
+use crate::settings::MqttSettings;
+
+mod discovery;
+
+const STATUS_ONLINE: &str = "online";
+const STATUS_OFFLINE: &str = "offline";
+// Updates queued while the broker is unreachable, anything past this is dropped
+const REQUEST_CAPACITY: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Time given to get "offline" and the disconnect out when shutting down
+const SHUTDOWN_FLUSH: Duration = Duration::from_secs(1);
+
+struct MqttActor {
+    client: AsyncClient,
+    settings: MqttSettings,
+    qos: QoS,
+    // Latest values, resent (retained) whenever the broker connects
+    activity_name: String,
+    device: String,
+    connected: bool,
+    battery: Option<u8>,
+}
+
+impl MqttActor {
+    fn build(
+        settings: MqttSettings,
+        initial_activity_name: String,
+    ) -> Result<(Self, EventLoop), AppError> {
+        let qos = rumqttc::qos(settings.qos).map_err(|_| {
+            AppError::MqttSettings(format!("qos must be 0, 1 or 2, not {}", settings.qos))
+        })?;
+        if settings.host.trim().is_empty() {
+            return Err(AppError::MqttSettings("host can't be empty".into()));
+        }
+        if settings.client_id.trim().is_empty() {
+            return Err(AppError::MqttSettings("client_id can't be empty".into()));
+        }
+        let prefix = &settings.topic_prefix;
+        if prefix.is_empty() || prefix.ends_with('/') || prefix.contains(['#', '+']) {
+            return Err(AppError::MqttSettings(format!(
+                "topic_prefix can't be empty, end with '/' or contain wildcards, \"{prefix}\""
+            )));
+        }
+
+        let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+        options.set_keep_alive(Duration::from_secs(settings.keep_alive_secs as u64));
+        // Lets Home Assistant know we're gone if we vanish without saying goodbye
+        options.set_last_will(LastWill::new(
+            format!("{prefix}/status"),
+            STATUS_OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if !settings.username.is_empty() {
+            options.set_credentials(&settings.username, &settings.password);
+        }
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+        Ok((
+            Self {
+                client,
+                settings,
+                qos,
+                activity_name: initial_activity_name,
+                device: String::new(),
+                connected: false,
+                battery: None,
+            },
+            eventloop,
+        ))
+    }
+
+    async fn rx_loop(
+        &mut self,
+        poller: &mut JoinHandle<()>,
+        connected_rx: &mut UnboundedReceiver<()>,
+        broadcast_rx: &mut BReceiver<AppUpdate>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), AppError> {
+        loop {
+            tokio::select! {
+                update = broadcast_rx.recv() => {
+                    match update {
+                        Ok(AppUpdate::HeartRateStatus(data)) => self.handle_data(&data),
+                        Ok(AppUpdate::ActivitySelected(_, name)) => {
+                            self.activity_name = name;
+                            self.publish("activity", true, self.activity_name.clone());
+                        }
+                        Ok(AppUpdate::DeviceConnected(source)) => {
+                            self.device = source.label().to_owned();
+                            self.publish("device", true, self.device.clone());
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Closed) => {
+                            error!("MQTT: Channel closed");
+                            return Ok(());
+                        }
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("MQTT: Lagged! Missed {count} messages");
+                        }
+                    }
+                }
+                Some(()) = connected_rx.recv() => {
+                    info!("MQTT: Connected to {}:{}", self.settings.host, self.settings.port);
+                    self.announce();
+                }
+                _ = cancel_token.cancelled() => {
+                    info!("MQTT thread shutting down");
+                    self.say_goodbye(poller).await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn handle_data(&mut self, heart_rate_status: &HeartRateStatus) {
+        let connected = heart_rate_status.heart_rate_bpm > 0;
+        if connected != self.connected {
+            self.connected = connected;
+            self.publish("connected", true, on_off(connected));
+        }
+        if !connected {
+            return;
+        }
+        self.publish("bpm", false, heart_rate_status.heart_rate_bpm.to_string());
+        if let Some(rr) = heart_rate_status.latest_clean_rr() {
+            self.publish("rr", false, rr.as_millis().to_string());
+        }
+        if let BatteryLevel::Level(level) = heart_rate_status.battery_level {
+            if self.battery != Some(level) {
+                self.battery = Some(level);
+                self.publish("battery", true, level.to_string());
+            }
+        }
+    }
+
+    /// Discovery configs, availability and the slower changing values, on every (re)connect
+    fn announce(&self) {
+        if self.settings.discovery {
+            for (topic, config) in discovery::configs(&self.settings) {
+                if let Err(e) = self
+                    .client
+                    .try_publish(topic, QoS::AtLeastOnce, true, config)
+                {
+                    warn!("MQTT: Failed to queue discovery config: {e}");
+                }
+            }
+        }
+        self.publish("activity", true, self.activity_name.clone());
+        if !self.device.is_empty() {
+            self.publish("device", true, self.device.clone());
+        }
+        self.publish("connected", true, on_off(self.connected));
+        if let Some(level) = self.battery {
+            self.publish("battery", true, level.to_string());
+        }
+        // Last, so everything's filled in once the sensors show up as available
+        self.publish("status", true, STATUS_ONLINE);
+    }
+
+    // A clean disconnect skips the last will, so "offline" has to be sent by hand
+    async fn say_goodbye(&self, poller: &mut JoinHandle<()>) {
+        self.publish("status", true, STATUS_OFFLINE);
+        if let Err(e) = self.client.try_disconnect() {
+            warn!("MQTT: Failed to queue disconnect: {e}");
+            return;
+        }
+        // The poller stops by itself once the disconnect is sent
+        if time::timeout(SHUTDOWN_FLUSH, poller).await.is_err() {
+            debug!("MQTT: Timed out saying goodbye");
+        }
+    }
+
+    // Queued without waiting, the poller task sends it
+    fn publish(&self, name: &str, retain: bool, payload: impl Into<Vec<u8>>) {
+        let topic = format!("{}/{name}", self.settings.topic_prefix);
+        let qos = if retain { QoS::AtLeastOnce } else { self.qos };
+        if let Err(e) = self.client.try_publish(topic, qos, retain, payload) {
+            debug!("MQTT: Dropped {name} update: {e}");
+        }
+    }
+}
+
+/// Drives the connection (and reconnects) on its own task, so the actor never has to wait on
+/// the broker. Lets the actor know each time it connects, and stops after a clean disconnect
+async fn poll_loop(mut eventloop: EventLoop, connected_tx: UnboundedSender<()>) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                if connected_tx.send(()).is_err() {
+                    return;
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+            Ok(_) => {}
+            // Polling again reconnects, but not right away
+            Err(e) => {
+                warn!(
+                    "MQTT: Connection error, retrying in {}s: {e}",
+                    RECONNECT_DELAY.as_secs()
+                );
+                time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+pub async fn mqtt_thread(
+    mut broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    initial_activity_name: String,
+    mqtt_settings: MqttSettings,
+    cancel_token: CancellationToken,
+) {
+    if !mqtt_settings.enabled {
+        info!("MQTT wasn't enabled! Shutting down thread");
+        return;
+    }
+
+    let (mut mqtt, eventloop) = match MqttActor::build(mqtt_settings, initial_activity_name) {
+        Ok(built) => built,
+        Err(e) => {
+            let message = "Failed to set up MQTT";
+            broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+            return;
+        }
+    };
+
+    info!("MQTT thread started!");
+
+    let (connected_tx, mut connected_rx) = mpsc::unbounded_channel();
+    let mut poller = tokio::spawn(poll_loop(eventloop, connected_tx));
+
+    let result = mqtt
+        .rx_loop(
+            &mut poller,
+            &mut connected_rx,
+            &mut broadcast_rx,
+            cancel_token,
+        )
+        .await;
+    // Don't keep reconnecting if the actor's gone
+    poller.abort();
+    if let Err(e) = result {
+        error!("MQTT error: {e}");
+        let message = "MQTT error:";
+        broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::HrSource;
+    use chrono::Local;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{broadcast, mpsc};
+
+    #[derive(Debug)]
+    struct Published {
+        topic: String,
+        payload: String,
+        retain: bool,
+    }
+
+    async fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let first = stream.read_u8().await.ok()?;
+        let mut length = 0usize;
+        for shift in (0..28).step_by(7) {
+            let byte = stream.read_u8().await.ok()?;
+            length |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((first, body))
+    }
+
+    /// Just enough of an MQTT 3.1.1 broker to see what gets published.
+    /// The CONNECT packet comes through as a `<connect>` topic
+    async fn stand_in_broker() -> (u16, mpsc::UnboundedReceiver<Published>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Some((first, body)) = read_packet(&mut stream).await {
+                // The client might hang up before its last ack, that's fine
+                let reply = match first >> 4 {
+                    // CONNECT
+                    1 => {
+                        let payload = String::from_utf8_lossy(&body).into_owned();
+                        let topic = "<connect>".into();
+                        let retain = false;
+                        tx.send(Published {
+                            topic,
+                            payload,
+                            retain,
+                        })
+                        .unwrap();
+                        vec![0x20, 0x02, 0x00, 0x00]
+                    }
+                    // PUBLISH
+                    3 => {
+                        let qos = (first >> 1) & 0b11;
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+                        let (packet_id, payload) = if qos > 0 {
+                            body[2 + topic_len..].split_at(2)
+                        } else {
+                            body[2 + topic_len..].split_at(0)
+                        };
+                        let payload = String::from_utf8_lossy(payload).into_owned();
+                        let retain = first & 1 == 1;
+                        tx.send(Published {
+                            topic,
+                            payload,
+                            retain,
+                        })
+                        .unwrap();
+                        match packet_id {
+                            [high, low] => vec![0x40, 0x02, *high, *low],
+                            _ => continue,
+                        }
+                    }
+                    // PINGREQ
+                    12 => vec![0xD0, 0x00],
+                    // DISCONNECT
+                    14 => break,
+                    _ => continue,
+                };
+                if stream.write_all(&reply).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    async fn until(
+        broker: &mut mpsc::UnboundedReceiver<Published>,
+        topic: &str,
+        payload: &str,
+    ) -> Vec<Published> {
+        let mut seen = Vec::new();
+        loop {
+            let published = time::timeout(Duration::from_secs(5), broker.recv())
+                .await
+                .unwrap_or_else(|_| panic!("no {topic} = {payload} in {seen:#?}"))
+                .unwrap();
+            let done = published.topic == topic && published.payload == payload;
+            seen.push(published);
+            if done {
+                return seen;
+            }
+        }
+    }
+
+    fn status(bpm: u16) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            rr_intervals: vec![Duration::from_millis(750)],
+            battery_level: BatteryLevel::Level(90),
+            timestamp: Local::now(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_to_broker() {
+        let (port, mut broker) = stand_in_broker().await;
+        let settings = MqttSettings {
+            enabled: true,
+            host: "127.0.0.1".into(),
+            port,
+            client_id: "iron-heart-test".into(),
+            topic_prefix: "hr".into(),
+            keep_alive_secs: 30,
+            discovery: true,
+            discovery_prefix: "homeassistant".into(),
+            ..Default::default()
+        };
+        let (tx, rx) = broadcast::channel(16);
+        let cancel = CancellationToken::new();
+        let handle = tokio::spawn(mqtt_thread(
+            rx,
+            tx.clone(),
+            "Sitting".into(),
+            settings,
+            cancel.clone(),
+        ));
+
+        let connected = until(&mut broker, "hr/status", STATUS_ONLINE).await;
+        let connect = &connected[0];
+        assert_eq!(connect.topic, "<connect>");
+        assert!(connect.payload.contains("hr/status") && connect.payload.contains(STATUS_OFFLINE));
+        let bpm_config = connected
+            .iter()
+            .find(|p| p.topic == "homeassistant/sensor/iron-heart-test/bpm/config")
+            .unwrap();
+        assert!(bpm_config.retain);
+        assert!(bpm_config.payload.contains(r#""state_topic":"hr/bpm""#));
+        assert!(connected
+            .iter()
+            .any(|p| p.topic == "homeassistant/binary_sensor/iron-heart-test/connected/config"));
+
+        tx.send(AppUpdate::DeviceConnected(HrSource {
+            kind: "ble",
+            name: "Polar H10".into(),
+            address: "AA:BB".into(),
+        }))
+        .unwrap();
+        tx.send(status(80).into()).unwrap();
+        tx.send(AppUpdate::ActivitySelected(2, "Dancing".into()))
+            .unwrap();
+        tx.send(status(0).into()).unwrap();
+
+        let updates = until(&mut broker, "hr/connected", "OFF").await;
+        let value = |topic: &str| {
+            updates
+                .iter()
+                .find(|p| p.topic == topic)
+                .map(|p| (p.payload.as_str(), p.retain))
+        };
+        assert_eq!(value("hr/device"), Some(("Polar H10", true)));
+        assert_eq!(value("hr/connected"), Some(("ON", true)));
+        assert_eq!(value("hr/bpm"), Some(("80", false)));
+        assert_eq!(value("hr/rr"), Some(("750", false)));
+        assert_eq!(value("hr/battery"), Some(("90", true)));
+        assert_eq!(value("hr/activity"), Some(("Dancing", true)));
+
+        cancel.cancel();
+        let goodbye = until(&mut broker, "hr/status", STATUS_OFFLINE).await;
+        assert!(goodbye.last().unwrap().retain);
+        handle.await.unwrap();
+    }
+
+    #[test]
+    fn bad_settings() {
+        let good = MqttSettings {
+            host: "localhost".into(),
+            client_id: "iron-heart".into(),
+            topic_prefix: "iron-heart".into(),
+            ..Default::default()
+        };
+        assert!(MqttActor::build(good.clone(), String::new()).is_ok());
+        for settings in [
+            MqttSettings {
+                qos: 3,
+                ..good.clone()
+            },
+            MqttSettings {
+                host: " ".into(),
+                ..good.clone()
+            },
+            MqttSettings {
+                client_id: String::new(),
+                ..good.clone()
+            },
+            MqttSettings {
+                topic_prefix: "hr/#".into(),
+                ..good.clone()
+            },
+            MqttSettings {
+                topic_prefix: "hr/".into(),
+                ..good.clone()
+            },
+        ] {
+            assert!(MqttActor::build(settings, String::new()).is_err());
+        }
+    }
+}
@@ -5,13 +5,8 @@ use crate::{errors::AppError, settings::OscAddrConf};
 pub(super) struct OscAddresses {
     pub beat_toggle: String,
     pub beat_pulse: String,
-    pub bpm_int: String,
-    pub bpm_float: String,
     pub connected: String,
     pub hiding_disconnect: String,
-    pub latest_rr: String,
-    pub battery_int: String,
-    pub battery_float: String,
     pub rr_twitch_up: String,
     pub rr_twitch_down: String,
     pub activity: String,
@@ -30,7 +25,7 @@ fn remove_trailing_char(s: &mut String, ch: char) {
     }
 }
 
-fn format_prefix(prefix: &str) -> Result<String, AppError> {
+pub(super) fn format_prefix(prefix: &str) -> Result<String, AppError> {
     let mut address = String::from("/");
     address.push_str(prefix);
     remove_double_slashes(&mut address);
@@ -42,7 +37,11 @@ fn format_prefix(prefix: &str) -> Result<String, AppError> {
     }
 }
 
-fn format_address(prefix: &str, param: &str, param_name: &str) -> Result<String, AppError> {
+pub(super) fn format_address(
+    prefix: &str,
+    param: &str,
+    param_name: &str,
+) -> Result<String, AppError> {
     // Don't allow empty/blank addresses
     if param.is_empty() || param == "/" {
         return Err(AppError::OscAddress(
@@ -69,21 +68,12 @@ impl OscAddresses {
         Ok(OscAddresses {
             beat_toggle: format_address(&prefix, &osc_params.beat_toggle, "beat_toggle")?,
             beat_pulse: format_address(&prefix, &osc_params.beat_pulse, "beat_pulse")?,
-            bpm_int: format_address(&prefix, &osc_params.bpm_int, "bpm_int")?,
-            bpm_float: format_address(&prefix, &osc_params.bpm_float, "bpm_float")?,
             connected: format_address(&prefix, &osc_params.hrm_connected, "hrm_connected")?,
             hiding_disconnect: format_address(
                 &prefix,
                 &osc_params.hiding_disconnect,
                 "hiding_disconnect",
             )?,
-            latest_rr: format_address(&prefix, &osc_params.latest_rr_int, "latest_rr_int")?,
-            battery_int: format_address(&prefix, &osc_params.hrm_battery_int, "hrm_battery_int")?,
-            battery_float: format_address(
-                &prefix,
-                &osc_params.hrm_battery_float,
-                "hrm_battery_float",
-            )?,
             rr_twitch_up: format_address(&prefix, &osc_params.rr_twitch_up, "rr_twitch_up")?,
             rr_twitch_down: format_address(&prefix, &osc_params.rr_twitch_down, "rr_twitch_down")?,
             activity: format_address(&prefix, &osc_params.activity, "activity")?,
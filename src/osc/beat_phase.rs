@@ -0,0 +1,236 @@
+use tokio::time::{Duration, Instant};
+
+// How much of each new RR interval goes into the period estimate
+const PERIOD_SMOOTHING: f64 = 0.3;
+// How much of the measured phase error is corrected per update
+const PHASE_GAIN: f64 = 0.5;
+// Largest phase correction per update, as a fraction of the period,
+// so pulses never bunch up or stall noticeably while catching up
+const MAX_PHASE_CORRECTION: f64 = 0.25;
+
+/// Predicts when the next heart beat lands, so pulses sent to the avatar
+/// stay locked to the wearer's real beats instead of drifting.
+///
+/// RR intervals tend to arrive in batches, well after the beats happened,
+/// so instead of scheduling a pulse on arrival, this keeps a running
+/// period and phase estimate and nudges it towards each batch's timing.
+pub(super) struct BeatScheduler {
+    period: Duration,
+    next_beat: Option<Instant>,
+    last_beat: Option<Instant>,
+}
+
+impl BeatScheduler {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next_beat: None,
+            last_beat: None,
+        }
+    }
+    /// Updates the estimate from a batch of RR intervals,
+    /// where the last interval ended at `last_beat_at`
+    ///
+    /// Every interval pulls the period towards it, so the batch should
+    /// only have beats that are new since the last call
+    pub fn observe(&mut self, rr_intervals: &[Duration], last_beat_at: Instant, now: Instant) {
+        let Some(latest) = rr_intervals.last() else {
+            return;
+        };
+        for rr in rr_intervals {
+            self.smooth_period(*rr);
+        }
+        // A single beat is also the only hint at the period we have so far
+        if self.next_beat.is_none() && self.last_beat.is_none() {
+            self.period = *latest;
+        }
+
+        let observed_next = last_beat_at + self.period;
+        let next_beat = match self.next_beat {
+            None => observed_next,
+            Some(predicted) => {
+                let period = self.period.as_secs_f64();
+                let error = signed_secs(observed_next, predicted);
+                // Compare against the closest predicted beat,
+                // the batch could be one we've already pulsed for
+                let error = error - (error / period).round() * period;
+                let limit = period * MAX_PHASE_CORRECTION;
+                let correction = (error * PHASE_GAIN).clamp(-limit, limit);
+                offset(predicted, correction)
+            }
+        };
+        self.next_beat = Some(self.keep_spacing(next_beat, now));
+    }
+    /// Used when there are no RR intervals to align to,
+    /// the phase just runs freely at the given period
+    pub fn set_period(&mut self, period: Duration, now: Instant) {
+        self.period = period;
+        self.next_beat.get_or_insert(now);
+    }
+    pub fn next_beat_deadline(&self) -> Option<Instant> {
+        self.next_beat
+    }
+    /// Marks the predicted beat as pulsed and schedules the next one
+    pub fn beat(&mut self, now: Instant) {
+        self.last_beat = Some(now);
+        let next_beat = self.next_beat.map_or(now, |next| next + self.period);
+        self.next_beat = Some(self.keep_spacing(next_beat, now));
+    }
+    /// Beats from before a disconnection say nothing about the phase
+    pub fn reset(&mut self) {
+        self.next_beat = None;
+        self.last_beat = None;
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    fn smooth_period(&mut self, rr: Duration) {
+        let period = self.period.as_secs_f64();
+        let smoothed = period + (rr.as_secs_f64() - period) * PERIOD_SMOOTHING;
+        self.period = Duration::from_secs_f64(smoothed);
+    }
+    // Keeps beats from bunching up after a correction,
+    // and skips ahead instead of rushing through missed beats after a stall
+    fn keep_spacing(&self, mut next_beat: Instant, now: Instant) -> Instant {
+        if let Some(last_beat) = self.last_beat {
+            let min_gap = self.period.mul_f64(1.0 - MAX_PHASE_CORRECTION);
+            next_beat = next_beat.max(last_beat + min_gap);
+        }
+        while next_beat + self.period <= now {
+            next_beat += self.period;
+        }
+        next_beat
+    }
+}
+
+fn signed_secs(a: Instant, b: Instant) -> f64 {
+    if a >= b {
+        (a - b).as_secs_f64()
+    } else {
+        -(b - a).as_secs_f64()
+    }
+}
+
+fn offset(instant: Instant, secs: f64) -> Instant {
+    let amount = Duration::from_secs_f64(secs.abs());
+    if secs >= 0.0 {
+        instant + amount
+    } else {
+        instant.checked_sub(amount).unwrap_or(instant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heart_rate::HeartRateStatus;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn first_batch_sets_phase() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(1000));
+        scheduler.observe(&[ms(800), ms(800)], start, start);
+        assert_eq!(scheduler.next_beat_deadline(), Some(start + ms(800)));
+    }
+
+    #[test]
+    fn stays_locked_to_steady_beats() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(800));
+        scheduler.observe(&[ms(800)], start, start);
+        // Batches arriving late shouldn't move the beat
+        for i in 1..10 {
+            let beat = start + ms(800 * i);
+            scheduler.beat(scheduler.next_beat_deadline().unwrap());
+            scheduler.observe(&[ms(800)], beat, beat + ms(300));
+            assert_eq!(scheduler.next_beat_deadline(), Some(beat + ms(800)));
+        }
+    }
+
+    #[test]
+    fn corrects_phase_gradually() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(1000));
+        scheduler.observe(&[ms(1000)], start, start);
+        // Real beats turn out to land 300ms after the predicted ones
+        let first_real = start + ms(300);
+        let phase_error = |deadline: Instant| {
+            let offset = signed_secs(deadline, first_real);
+            (offset - offset.round()).abs()
+        };
+        let mut error = phase_error(scheduler.next_beat_deadline().unwrap());
+        assert!((error - 0.3).abs() < 0.001);
+
+        for _ in 0..8 {
+            let deadline = scheduler.next_beat_deadline().unwrap();
+            scheduler.beat(deadline);
+            // The batch for the most recent real beat shows up a bit later
+            let beats = signed_secs(deadline, first_real).ceil().max(0.0);
+            let real_beat = first_real + Duration::from_secs_f64(beats);
+            scheduler.observe(&[ms(1000)], real_beat, real_beat + ms(100));
+
+            let new_error = phase_error(scheduler.next_beat_deadline().unwrap());
+            assert!(new_error < error, "{new_error} !< {error}");
+            error = new_error;
+        }
+        assert!(error < 0.005, "{error}");
+    }
+
+    #[test]
+    fn each_beat_counts_once() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(1000));
+        scheduler.observe(&[ms(1000)], start, start);
+        scheduler.observe(&[ms(500)], start + ms(500), start + ms(500));
+        let period = scheduler.period();
+        assert!((period.as_secs_f64() - 0.85).abs() < 1e-6, "{period:?}");
+        // Packets without new beats don't get observed, the period holds
+        let websocket_status = HeartRateStatus {
+            heart_rate_bpm: 120,
+            last_rr: Some(ms(500)),
+            ..Default::default()
+        };
+        let new_beats: Vec<Duration> = websocket_status.clean_rr_intervals().collect();
+        scheduler.observe(&new_beats, start + ms(700), start + ms(700));
+        assert_eq!(scheduler.period(), period);
+    }
+
+    #[test]
+    fn batches_dont_bunch_beats() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(1000));
+        scheduler.observe(&[ms(1000)], start, start);
+        let first = scheduler.next_beat_deadline().unwrap();
+        scheduler.beat(first);
+        // A batch claiming the beat we just pulsed was really 400ms earlier
+        scheduler.observe(&[ms(1000)], first - ms(400), first);
+        let next = scheduler.next_beat_deadline().unwrap();
+        assert!(next - first >= ms(750), "{:?}", next - first);
+    }
+
+    #[test]
+    fn skips_ahead_after_stall() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(500));
+        scheduler.observe(&[ms(500)], start, start);
+        // The actor was busy for a few seconds
+        let late = start + ms(3200);
+        scheduler.beat(late);
+        let next = scheduler.next_beat_deadline().unwrap();
+        assert!(next > late && next <= late + ms(500));
+    }
+
+    #[test]
+    fn free_runs_without_rr() {
+        let start = Instant::now();
+        let mut scheduler = BeatScheduler::new(ms(1000));
+        scheduler.set_period(ms(750), start);
+        assert_eq!(scheduler.next_beat_deadline(), Some(start));
+        scheduler.beat(start);
+        assert_eq!(scheduler.next_beat_deadline(), Some(start + ms(750)));
+    }
+}
@@ -1,13 +1,9 @@
 use crate::heart_rate::HeartRateStatus;
-use rand::Rng;
-use rosc::encoder;
-use rosc::{OscBundle, OscMessage, OscPacket, OscType};
-use std::f32;
+use rosc::{OscMessage, OscType};
 
 use super::addresses::OscAddresses;
-use super::OSC_NOW;
-
-use std::net::{SocketAddrV4, UdpSocket};
+use super::mapping::{MappingInput, ParamMapper};
+use super::sender::OscSender;
 
 use crate::errors::AppError;
 
@@ -15,35 +11,26 @@ pub(super) fn send_raw_hr_status(
     hr_status: &HeartRateStatus,
     hiding_disconnect: bool,
     delay_sending_connected: bool,
-    positive_float_bpm: bool,
+    param_mapper: &mut ParamMapper,
     osc_addresses: &OscAddresses,
-    socket: &UdpSocket,
-    target_addr: SocketAddrV4,
+    sender: &mut OscSender,
 ) -> Result<(), AppError> {
-    let bundle = form_bpm_bundle(
+    let messages = form_bpm_messages(
         hr_status,
         hiding_disconnect,
         delay_sending_connected,
-        positive_float_bpm,
+        param_mapper,
         osc_addresses,
     );
-    let msg_buf = encoder::encode(&OscPacket::Bundle(bundle))?;
-    socket.send_to(&msg_buf, target_addr)?;
-    Ok(())
+    sender.send(messages)
 }
 
 pub(super) fn send_raw_beat_params(
     pulse_edge: bool,
     toggle_beat: bool,
     osc_addresses: &OscAddresses,
-    socket: &UdpSocket,
-    target_addr: SocketAddrV4,
+    sender: &mut OscSender,
 ) -> Result<(), AppError> {
-    let mut bundle = OscBundle {
-        timetag: OSC_NOW,
-        content: vec![],
-    };
-
     let pulse_msg = OscMessage {
         addr: osc_addresses.beat_pulse.clone(),
         args: vec![OscType::Bool(pulse_edge)],
@@ -54,75 +41,54 @@ pub(super) fn send_raw_beat_params(
         args: vec![OscType::Bool(toggle_beat)],
     };
 
-    bundle.content.push(OscPacket::Message(pulse_msg));
-    bundle.content.push(OscPacket::Message(toggle_msg));
+    // Beats are only useful if they're on time, so they skip the rate limit
+    sender.send_beat(vec![pulse_msg, toggle_msg])
+}
 
-    let msg_buf = encoder::encode(&OscPacket::Bundle(bundle))?;
-    socket.send_to(&msg_buf, target_addr)?;
-    Ok(())
+/// Only sends the twitches that have a new value
+pub(super) fn send_raw_twitch_params(
+    twitch_up: Option<bool>,
+    twitch_down: Option<bool>,
+    osc_addresses: &OscAddresses,
+    sender: &mut OscSender,
+) -> Result<(), AppError> {
+    let messages: Vec<OscMessage> = [
+        (&osc_addresses.rr_twitch_up, twitch_up),
+        (&osc_addresses.rr_twitch_down, twitch_down),
+    ]
+    .into_iter()
+    .filter_map(|(addr, value)| {
+        value.map(|value| OscMessage {
+            addr: addr.clone(),
+            args: vec![OscType::Bool(value)],
+        })
+    })
+    .collect();
+
+    // Twitches have their own timers, so they skip the rate limit like beats do
+    sender.send_now(messages)
 }
 
 pub(super) fn send_raw_activity_param(
     new_index: u8,
     osc_addresses: &OscAddresses,
-    socket: &UdpSocket,
-    target_addr: SocketAddrV4,
+    sender: &mut OscSender,
 ) -> Result<(), AppError> {
-    let mut bundle = OscBundle {
-        timetag: OSC_NOW,
-        content: vec![],
-    };
-
     let activity_msg = OscMessage {
         addr: osc_addresses.activity.clone(),
         args: vec![OscType::Int(new_index as i32)],
     };
 
-    bundle.content.push(OscPacket::Message(activity_msg));
-
-    let msg_buf = encoder::encode(&OscPacket::Bundle(bundle))?;
-    socket.send_to(&msg_buf, target_addr)?;
-    Ok(())
-}
-
-pub(super) fn make_mimic_data(hr_status: &HeartRateStatus) -> HeartRateStatus {
-    let mut mimic = HeartRateStatus::default();
-    let jitter = rand::thread_rng().gen_range(-3..3);
-    mimic.heart_rate_bpm = hr_status.heart_rate_bpm.saturating_add_signed(jitter);
-    mimic.battery_level = hr_status.battery_level;
-    // Add chance to fake a twitch
-    mimic.twitch_up = (rand::thread_rng().gen_range(0..5)) == 0;
-    mimic.twitch_down = (rand::thread_rng().gen_range(0..5)) == 0;
-    mimic
+    sender.send(vec![activity_msg])
 }
 
-pub(super) fn form_bpm_bundle(
+pub(super) fn form_bpm_messages(
     hr_status: &HeartRateStatus,
     hiding_disconnect: bool,
     delay_sending_connected: bool,
-    positive_float_bpm: bool,
+    param_mapper: &mut ParamMapper,
     osc_addresses: &OscAddresses,
-) -> OscBundle {
-    let mut bundle = OscBundle {
-        timetag: OSC_NOW,
-        content: vec![],
-    };
-
-    let bpm_int_msg = OscMessage {
-        addr: osc_addresses.bpm_int.clone(),
-        args: vec![OscType::Int(hr_status.heart_rate_bpm as i32)],
-    };
-
-    let hr_float = if positive_float_bpm {
-        hr_status.heart_rate_bpm as f32 / 255.0
-    } else {
-        (hr_status.heart_rate_bpm as f32 / 255.0) * 2.0 - 1.0
-    };
-    let bpm_float_msg = OscMessage {
-        addr: osc_addresses.bpm_float.clone(),
-        args: vec![OscType::Float(hr_float)],
-    };
-
+) -> Vec<OscMessage> {
     let connected = if delay_sending_connected {
         false
     } else {
@@ -139,52 +105,9 @@ pub(super) fn form_bpm_bundle(
         args: vec![OscType::Bool(hiding_disconnect)],
     };
 
-    let battery_level: u8 = hr_status.battery_level.into();
-
-    let battery_int_msg = OscMessage {
-        addr: osc_addresses.battery_int.clone(),
-        args: vec![OscType::Int(battery_level as i32)],
-    };
-
-    let battery_float_msg = OscMessage {
-        addr: osc_addresses.battery_float.clone(),
-        args: vec![OscType::Float(battery_level as f32 / 100.0)],
-    };
-
-    if hr_status.heart_rate_bpm == 0 {
-        let rr_msg = OscMessage {
-            addr: osc_addresses.latest_rr.clone(),
-            args: vec![OscType::Int(0)],
-        };
-        bundle.content.push(OscPacket::Message(rr_msg));
-    } else if let Some(&latest_rr) = hr_status.rr_intervals.last() {
-        let rr_msg = OscMessage {
-            addr: osc_addresses.latest_rr.clone(),
-            args: vec![OscType::Int((latest_rr.as_secs_f32() * 1000.0) as i32)],
-        };
-        bundle.content.push(OscPacket::Message(rr_msg));
-    }
-
-    let twitch_up_msg = OscMessage {
-        addr: osc_addresses.rr_twitch_up.clone(),
-        args: vec![OscType::Bool(hr_status.twitch_up)],
-    };
-
-    let twitch_down_msg = OscMessage {
-        addr: osc_addresses.rr_twitch_down.clone(),
-        args: vec![OscType::Bool(hr_status.twitch_down)],
-    };
-
-    bundle.content.push(OscPacket::Message(bpm_int_msg));
-    bundle.content.push(OscPacket::Message(bpm_float_msg));
-    bundle.content.push(OscPacket::Message(connected_msg));
-    bundle
-        .content
-        .push(OscPacket::Message(hiding_disconnect_msg));
-    bundle.content.push(OscPacket::Message(battery_int_msg));
-    bundle.content.push(OscPacket::Message(battery_float_msg));
-    bundle.content.push(OscPacket::Message(twitch_up_msg));
-    bundle.content.push(OscPacket::Message(twitch_down_msg));
+    let mut messages = param_mapper.messages(MappingInput::HeartRate(hr_status));
+    messages.push(connected_msg);
+    messages.push(hiding_disconnect_msg);
 
-    bundle
+    messages
 }
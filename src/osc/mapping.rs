@@ -0,0 +1,411 @@
+use rosc::{OscMessage, OscType};
+
+use super::addresses::{format_address, format_prefix};
+use super::sender::SendCounters;
+use crate::errors::AppError;
+use crate::heart_rate::HeartRateStatus;
+use crate::hrv::HrvMetrics;
+use crate::settings::{MappingSource, MappingTransform, MappingType, OscMapping};
+use crate::stats::SessionStats;
+use crate::zones::HrZones;
+
+/// Data for the mappings to read from, each kind arrives in its own update
+#[derive(Clone, Copy)]
+pub(super) enum MappingInput<'a> {
+    HeartRate(&'a HeartRateStatus),
+    SessionStats(&'a SessionStats),
+    Hrv(&'a HrvMetrics),
+    /// The sender's own counters, sent along with each resync
+    SendCounters(&'a SendCounters),
+}
+
+/// A user-configured OSC parameter, ready to turn heart rate data into messages.
+struct MappedParam {
+    address: String,
+    source: MappingSource,
+    osc_type: MappingType,
+    transform: MappingTransform,
+    smoothing: f32,
+    smoothed: Option<f32>,
+}
+
+pub(super) struct ParamMapper {
+    params: Vec<MappedParam>,
+    zones: HrZones,
+}
+
+impl ParamMapper {
+    pub fn build(prefix: &str, mappings: &[OscMapping], zones: HrZones) -> Result<Self, AppError> {
+        let prefix = format_prefix(prefix)?;
+        let mut params = Vec::with_capacity(mappings.len());
+        for (index, mapping) in mappings.iter().enumerate() {
+            let param_name = format!("mappings[{index}]");
+            if !(0.0..1.0).contains(&mapping.smoothing) {
+                return Err(AppError::OscMapping(
+                    param_name,
+                    "smoothing must be at least 0.0 and less than 1.0".into(),
+                ));
+            }
+            if let Err(reason) = validate_transform(&mapping.transform) {
+                return Err(AppError::OscMapping(param_name, reason.into()));
+            }
+            params.push(MappedParam {
+                address: format_address(&prefix, &mapping.address, &param_name)?,
+                source: mapping.source,
+                osc_type: mapping.osc_type,
+                transform: mapping.transform.clone(),
+                smoothing: mapping.smoothing,
+                smoothed: None,
+            });
+        }
+        Ok(Self { params, zones })
+    }
+    /// Forget any smoothing history, so the next values are sent as-is
+    pub fn reset(&mut self) {
+        for param in self.params.iter_mut() {
+            param.smoothed = None;
+        }
+    }
+    /// Messages for the params whose source is in `input`
+    pub fn messages(&mut self, input: MappingInput) -> Vec<OscMessage> {
+        // Values drop straight to 0 when disconnected instead of easing down,
+        // and start fresh once we're reconnected
+        let connected = match input {
+            MappingInput::HeartRate(hr_status) => hr_status.heart_rate_bpm > 0,
+            _ => true,
+        };
+        if !connected {
+            self.reset();
+        }
+        let zones = &self.zones;
+        self.params
+            .iter_mut()
+            .filter_map(|param| {
+                let value = source_value(param.source, input, zones)?;
+                let value = if connected {
+                    param.smooth(value)
+                } else {
+                    value
+                };
+                let value = apply_transform(&param.transform, value);
+                Some(OscMessage {
+                    addr: param.address.clone(),
+                    args: vec![to_osc_type(param.osc_type, value)],
+                })
+            })
+            .collect()
+    }
+}
+
+impl MappedParam {
+    fn smooth(&mut self, value: f32) -> f32 {
+        if self.smoothing == 0.0 {
+            return value;
+        }
+        let smoothed = match self.smoothed {
+            Some(last) => last + (value - last) * (1.0 - self.smoothing),
+            None => value,
+        };
+        self.smoothed = Some(smoothed);
+        smoothed
+    }
+}
+
+fn validate_transform(transform: &MappingTransform) -> Result<(), &'static str> {
+    match transform {
+        MappingTransform::None => Ok(()),
+        MappingTransform::Linear { in_min, in_max, .. }
+        | MappingTransform::Log { in_min, in_max, .. } => {
+            if in_min == in_max {
+                Err("in_min and in_max can't be equal")
+            } else {
+                Ok(())
+            }
+        }
+        MappingTransform::Thresholds { thresholds } => {
+            if thresholds.windows(2).all(|w| w[0] <= w[1]) {
+                Ok(())
+            } else {
+                Err("thresholds must be in ascending order")
+            }
+        }
+    }
+}
+
+/// Returns None if there's nothing worth sending for this source right now,
+/// or if it isn't part of this kind of update
+fn source_value(source: MappingSource, input: MappingInput, zones: &HrZones) -> Option<f32> {
+    use MappingSource::*;
+    match (input, source) {
+        (MappingInput::HeartRate(hr_status), Bpm) => Some(hr_status.heart_rate_bpm as f32),
+        (MappingInput::HeartRate(hr_status), Rr) => {
+            if hr_status.heart_rate_bpm == 0 {
+                Some(0.0)
+            } else {
+                // Not every update carries an RR interval,
+                // so we just keep the last one on the avatar
+                hr_status
+                    .latest_clean_rr()
+                    .map(|rr| rr.as_secs_f32() * 1000.0)
+            }
+        }
+        (MappingInput::HeartRate(hr_status), Battery) => {
+            Some(u8::from(hr_status.battery_level) as f32)
+        }
+        (MappingInput::HeartRate(hr_status), Zone) => {
+            Some(zones.zone_of(hr_status.heart_rate_bpm) as f32)
+        }
+        (MappingInput::SessionStats(stats), SessionMinBpm) => Some(stats.min_bpm.0 as f32),
+        (MappingInput::SessionStats(stats), SessionMaxBpm) => Some(stats.max_bpm.0 as f32),
+        (MappingInput::SessionStats(stats), RollingAvgBpm) => Some(stats.rolling_average_bpm),
+        (MappingInput::SessionStats(stats), PercentOfMax) => Some(stats.percent_of_max),
+        (MappingInput::SessionStats(stats), MinutesInZone) => {
+            Some((stats.time_in_current_zone().as_secs() / 60) as f32)
+        }
+        (MappingInput::SessionStats(stats), Calories) => stats.calories,
+        (MappingInput::SessionStats(stats), Trimp) => Some(stats.trimp),
+        (MappingInput::Hrv(hrv), Rmssd) => Some(hrv.rmssd_ms),
+        (MappingInput::Hrv(hrv), Sdnn) => Some(hrv.sdnn_ms),
+        (MappingInput::Hrv(hrv), Pnn50) => Some(hrv.pnn50),
+        (MappingInput::Hrv(hrv), StressIndex) => Some(hrv.stress_index),
+        (MappingInput::Hrv(hrv), LfHfRatio) => hrv.frequency.as_ref().map(|f| f.lf_hf_ratio),
+        (MappingInput::Hrv(hrv), RespirationRate) => hrv.respiration_rate,
+        (MappingInput::SendCounters(counters), OscSent) => Some(counters.sent as f32),
+        (MappingInput::SendCounters(counters), OscUnchanged) => Some(counters.unchanged as f32),
+        (MappingInput::SendCounters(counters), OscRateLimited) => {
+            Some(counters.rate_limited as f32)
+        }
+        _ => None,
+    }
+}
+
+fn apply_transform(transform: &MappingTransform, value: f32) -> f32 {
+    match transform {
+        MappingTransform::None => value,
+        MappingTransform::Linear {
+            in_min,
+            in_max,
+            out_min,
+            out_max,
+        } => {
+            let t = normalize(value, *in_min, *in_max);
+            out_min + (out_max - out_min) * t
+        }
+        MappingTransform::Log {
+            in_min,
+            in_max,
+            out_min,
+            out_max,
+        } => {
+            let t = normalize(value, *in_min, *in_max);
+            // ln(1) = 0 and ln(10) / ln(10) = 1, so the ends still line up
+            let t = (1.0 + 9.0 * t).ln() / 10f32.ln();
+            out_min + (out_max - out_min) * t
+        }
+        MappingTransform::Thresholds { thresholds } => {
+            thresholds.iter().take_while(|&&t| value >= t).count() as f32
+        }
+    }
+}
+
+/// Where `value` sits between `min` and `max`, from 0.0 to 1.0
+fn normalize(value: f32, min: f32, max: f32) -> f32 {
+    ((value - min) / (max - min)).clamp(0.0, 1.0)
+}
+
+fn to_osc_type(osc_type: MappingType, value: f32) -> OscType {
+    match osc_type {
+        MappingType::Int => OscType::Int(value.round() as i32),
+        MappingType::Float => OscType::Float(value),
+        MappingType::Bool => OscType::Bool(value >= 0.5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heart_rate::BatteryLevel;
+    use crate::settings::{ZoneMethod, ZoneSettings};
+    use std::time::Duration;
+
+    fn linear(in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> MappingTransform {
+        MappingTransform::Linear {
+            in_min,
+            in_max,
+            out_min,
+            out_max,
+        }
+    }
+
+    fn mapping(source: MappingSource, osc_type: MappingType) -> OscMapping {
+        OscMapping {
+            address: "test".into(),
+            source,
+            osc_type,
+            transform: MappingTransform::None,
+            smoothing: 0.0,
+        }
+    }
+
+    fn zones() -> HrZones {
+        let zone_settings = ZoneSettings {
+            method: ZoneMethod::Custom,
+            max_bpm: 190,
+            custom_thresholds: vec![100, 120, 140],
+            ..Default::default()
+        };
+        HrZones::build(&zone_settings, 0).unwrap()
+    }
+
+    fn status(bpm: u16, rr_ms: Option<u64>) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            rr_intervals: rr_ms.map(Duration::from_millis).into_iter().collect(),
+            battery_level: BatteryLevel::Level(80),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn linear_matches_old_float_bpm() {
+        let transform = linear(0.0, 255.0, -1.0, 1.0);
+        for bpm in [0u16, 60, 128, 255] {
+            let old = (bpm as f32 / 255.0) * 2.0 - 1.0;
+            let new = apply_transform(&transform, bpm as f32);
+            assert!((old - new).abs() < 0.0001, "{bpm}: {old} != {new}");
+        }
+    }
+
+    #[test]
+    fn linear_clamps_and_inverts() {
+        let transform = linear(60.0, 180.0, 1.0, 0.0);
+        assert_eq!(apply_transform(&transform, 30.0), 1.0);
+        assert_eq!(apply_transform(&transform, 120.0), 0.5);
+        assert_eq!(apply_transform(&transform, 250.0), 0.0);
+    }
+
+    #[test]
+    fn log_keeps_endpoints() {
+        let transform = MappingTransform::Log {
+            in_min: 40.0,
+            in_max: 200.0,
+            out_min: 0.0,
+            out_max: 1.0,
+        };
+        assert_eq!(apply_transform(&transform, 40.0), 0.0);
+        assert!((apply_transform(&transform, 200.0) - 1.0).abs() < 0.0001);
+        // Halfway in should be well past halfway out
+        assert!(apply_transform(&transform, 120.0) > 0.7);
+    }
+
+    #[test]
+    fn thresholds_count_reached() {
+        let transform = MappingTransform::Thresholds {
+            thresholds: vec![100.0, 120.0, 140.0],
+        };
+        assert_eq!(apply_transform(&transform, 80.0), 0.0);
+        assert_eq!(apply_transform(&transform, 100.0), 1.0);
+        assert_eq!(apply_transform(&transform, 139.0), 2.0);
+        assert_eq!(apply_transform(&transform, 200.0), 3.0);
+    }
+
+    #[test]
+    fn rr_skipped_without_interval() {
+        let mut mapper = ParamMapper::build(
+            "/",
+            &[mapping(MappingSource::Rr, MappingType::Int)],
+            zones(),
+        )
+        .unwrap();
+        assert!(mapper
+            .messages(MappingInput::HeartRate(&status(70, None)))
+            .is_empty());
+        let messages = mapper.messages(MappingInput::HeartRate(&status(70, Some(857))));
+        assert_eq!(messages[0].args, vec![OscType::Int(857)]);
+        // Disconnected, always send a 0
+        let messages = mapper.messages(MappingInput::HeartRate(&status(0, None)));
+        assert_eq!(messages[0].args, vec![OscType::Int(0)]);
+    }
+
+    #[test]
+    fn smoothing_eases_and_resets() {
+        let mut smoothed = mapping(MappingSource::Bpm, MappingType::Float);
+        smoothed.smoothing = 0.5;
+        let mut mapper = ParamMapper::build("/", &[smoothed], zones()).unwrap();
+        let first = mapper.messages(MappingInput::HeartRate(&status(60, None)));
+        assert_eq!(first[0].args, vec![OscType::Float(60.0)]);
+        let second = mapper.messages(MappingInput::HeartRate(&status(80, None)));
+        assert_eq!(second[0].args, vec![OscType::Float(70.0)]);
+        let disconnected = mapper.messages(MappingInput::HeartRate(&status(0, None)));
+        assert_eq!(disconnected[0].args, vec![OscType::Float(0.0)]);
+        let reconnected = mapper.messages(MappingInput::HeartRate(&status(90, None)));
+        assert_eq!(reconnected[0].args, vec![OscType::Float(90.0)]);
+    }
+
+    #[test]
+    fn bool_and_battery() {
+        let mut low_battery = mapping(MappingSource::Battery, MappingType::Bool);
+        low_battery.transform = linear(20.0, 21.0, 1.0, 0.0);
+        let mut mapper = ParamMapper::build("/avatar/parameters", &[low_battery], zones()).unwrap();
+        let messages = mapper.messages(MappingInput::HeartRate(&status(70, None)));
+        assert_eq!(messages[0].addr, "/avatar/parameters/test");
+        assert_eq!(messages[0].args, vec![OscType::Bool(false)]);
+    }
+
+    #[test]
+    fn sources_follow_their_updates() {
+        let mut mapper = ParamMapper::build(
+            "/",
+            &[
+                mapping(MappingSource::Zone, MappingType::Int),
+                mapping(MappingSource::Rmssd, MappingType::Int),
+                mapping(MappingSource::MinutesInZone, MappingType::Int),
+                mapping(MappingSource::RespirationRate, MappingType::Float),
+                mapping(MappingSource::OscUnchanged, MappingType::Int),
+            ],
+            zones(),
+        )
+        .unwrap();
+        let messages = mapper.messages(MappingInput::HeartRate(&status(125, None)));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].args, vec![OscType::Int(2)]);
+
+        let hrv = HrvMetrics {
+            rmssd_ms: 42.4,
+            ..Default::default()
+        };
+        let messages = mapper.messages(MappingInput::Hrv(&hrv));
+        // No breathing rate yet, so it's left as-is
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].args, vec![OscType::Int(42)]);
+
+        let stats = SessionStats {
+            zone: 1,
+            time_in_zones: vec![Duration::ZERO, Duration::from_secs(150)],
+            ..Default::default()
+        };
+        let messages = mapper.messages(MappingInput::SessionStats(&stats));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].args, vec![OscType::Int(2)]);
+
+        let counters = SendCounters {
+            sent: 120,
+            unchanged: 45,
+            rate_limited: 3,
+        };
+        let messages = mapper.messages(MappingInput::SendCounters(&counters));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].args, vec![OscType::Int(45)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "mappings[1]")]
+    fn bad_thresholds_rejected() {
+        let mut unordered = mapping(MappingSource::Bpm, MappingType::Int);
+        unordered.transform = MappingTransform::Thresholds {
+            thresholds: vec![120.0, 100.0],
+        };
+        let valid = mapping(MappingSource::Bpm, MappingType::Int);
+        ParamMapper::build("/", &[valid, unordered], zones()).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+use crate::heart_rate::{rr_from_bpm, HeartRateStatus};
+use crate::settings::MimicStrategy;
+
+// How much real data is kept around to base the mimic data on
+const HISTORY_LENGTH: Duration = Duration::from_secs(60);
+// The trend is only taken from the most recent part of the history
+const TREND_WINDOW: Duration = Duration::from_secs(20);
+// Steepest trend (BPM per second) we're willing to carry on with
+const MAX_TREND: f32 = 1.0;
+// How quickly the trend flattens out, and how quickly we then settle
+// towards the resting baseline (time constants, in seconds)
+const TREND_FADE_SECS: f32 = 15.0;
+const BASELINE_DECAY_SECS: f32 = 120.0;
+const MIN_BPM: f32 = 30.0;
+
+#[derive(Debug, Clone)]
+struct Sample {
+    at: Instant,
+    hr_status: HeartRateStatus,
+}
+
+/// What we knew about the wearer when the connection dropped
+struct Snapshot {
+    last: HeartRateStatus,
+    // BPM per second
+    trend: f32,
+    // Standard deviation of the RR intervals around their local mean
+    rr_spread: Duration,
+    baseline: f32,
+    replay: Vec<(Duration, HeartRateStatus)>,
+    replay_length: Duration,
+    last_rr: Option<Duration>,
+}
+
+/// Makes up heart rate data while `hide_disconnections` is masking a lost connection
+pub(super) struct Mimic {
+    strategy: MimicStrategy,
+    resting_bpm: u16,
+    twitch_threshold: Duration,
+    history: VecDeque<Sample>,
+    snapshot: Option<Snapshot>,
+}
+
+impl Mimic {
+    pub fn new(strategy: MimicStrategy, resting_bpm: u16, twitch_threshold: Duration) -> Self {
+        Self {
+            strategy,
+            resting_bpm,
+            twitch_threshold,
+            history: VecDeque::new(),
+            snapshot: None,
+        }
+    }
+    /// How often mimic data should be sent
+    pub fn update_interval(&self) -> Duration {
+        match self.strategy {
+            // Nothing to gain from going faster than the random noise
+            MimicStrategy::Jitter => Duration::from_secs(6),
+            _ => Duration::from_secs(1),
+        }
+    }
+    /// Keeps track of real data, and stops any masking in progress
+    pub fn record(&mut self, hr_status: &HeartRateStatus, now: Instant) {
+        self.snapshot = None;
+        while self
+            .history
+            .front()
+            .is_some_and(|sample| now.saturating_duration_since(sample.at) > HISTORY_LENGTH)
+        {
+            self.history.pop_front();
+        }
+        self.history.push_back(Sample {
+            at: now,
+            hr_status: hr_status.clone(),
+        });
+    }
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.snapshot = None;
+    }
+    /// Mimic data for `now`, when the connection was lost at `since`
+    pub fn next<R: Rng>(&mut self, since: Instant, now: Instant, rng: &mut R) -> HeartRateStatus {
+        if self.snapshot.is_none() {
+            self.snapshot = Some(self.snapshot(since));
+        }
+        let strategy = self.strategy;
+        let twitch_threshold = self.twitch_threshold;
+        let snapshot = self.snapshot.as_mut().expect("Snapshot was just made");
+        let elapsed = now.saturating_duration_since(since);
+
+        let mut mimic = HeartRateStatus {
+            battery_level: snapshot.last.battery_level,
+            ..Default::default()
+        };
+        match strategy {
+            MimicStrategy::Jitter => {
+                let jitter = rng.gen_range(-3..3);
+                mimic.heart_rate_bpm = snapshot.last.heart_rate_bpm.saturating_add_signed(jitter);
+                mimic.rr_intervals = vec![rr_from_bpm(mimic.heart_rate_bpm)];
+                // Add chance to fake a twitch
+                mimic.twitch_up = rng.gen_range(0..5) == 0;
+                mimic.twitch_down = rng.gen_range(0..5) == 0;
+            }
+            MimicStrategy::Hold => {
+                mimic.heart_rate_bpm = snapshot.last.heart_rate_bpm;
+                mimic.rr_intervals = vec![snapshot
+                    .last
+                    .latest_clean_rr()
+                    .or(snapshot.last.last_rr)
+                    .unwrap_or_else(|| rr_from_bpm(mimic.heart_rate_bpm))];
+            }
+            MimicStrategy::Trend => {
+                let bpm = trend_bpm(snapshot, elapsed.as_secs_f32());
+                mimic.heart_rate_bpm = bpm.round() as u16;
+                // Uniform noise with the same spread as the real beats had
+                let spread = snapshot.rr_spread.as_secs_f32() * 3f32.sqrt();
+                let noise = if spread > 0.0 {
+                    rng.gen_range(-spread..spread)
+                } else {
+                    0.0
+                };
+                let rr = Duration::from_secs_f32((60.0 / bpm + noise).max(0.3));
+                if let Some(last_rr) = snapshot.last_rr {
+                    mimic.twitch_up = rr > last_rr + twitch_threshold;
+                    mimic.twitch_down = rr + twitch_threshold < last_rr;
+                }
+                snapshot.last_rr = Some(rr);
+                mimic.rr_intervals = vec![rr];
+            }
+            MimicStrategy::Replay
+                if !snapshot.replay.is_empty() && !snapshot.replay_length.is_zero() =>
+            {
+                let position = Duration::from_secs_f64(
+                    elapsed.as_secs_f64() % snapshot.replay_length.as_secs_f64(),
+                );
+                let (_, replayed) = snapshot
+                    .replay
+                    .iter()
+                    .rev()
+                    .find(|(offset, _)| *offset <= position)
+                    .expect("First sample is at offset 0");
+                mimic.heart_rate_bpm = replayed.heart_rate_bpm;
+                mimic.rr_intervals = replayed.clean_rr_intervals().collect();
+                mimic.twitch_up = replayed.twitch_up;
+                mimic.twitch_down = replayed.twitch_down;
+            }
+            // Not enough to replay, just hold the last value
+            MimicStrategy::Replay => {
+                mimic.heart_rate_bpm = snapshot.last.heart_rate_bpm;
+                mimic.rr_intervals = vec![rr_from_bpm(mimic.heart_rate_bpm)];
+            }
+        }
+        mimic
+    }
+    fn snapshot(&self, since: Instant) -> Snapshot {
+        let last = self
+            .history
+            .back()
+            .map(|sample| sample.hr_status.clone())
+            .unwrap_or_default();
+        let first_at = self.history.front().map_or(since, |sample| sample.at);
+        let replay = self
+            .history
+            .iter()
+            .map(|sample| (sample.at - first_at, sample.hr_status.clone()))
+            .collect();
+        // Loops back around at the point where the connection was lost
+        let replay_length = since.saturating_duration_since(first_at);
+        // Without a configured resting BPM, the lowest recent one will do
+        let baseline = match self.resting_bpm {
+            0 => self
+                .history
+                .iter()
+                .map(|sample| sample.hr_status.heart_rate_bpm)
+                .min()
+                .unwrap_or(last.heart_rate_bpm),
+            resting => resting,
+        };
+        Snapshot {
+            trend: self.trend(),
+            rr_spread: self.rr_spread(),
+            baseline: baseline as f32,
+            last_rr: last.latest_clean_rr().or(last.last_rr),
+            last,
+            replay,
+            replay_length,
+        }
+    }
+    /// Least-squares slope of the recent BPMs, in BPM per second
+    fn trend(&self) -> f32 {
+        let Some(latest) = self.history.back() else {
+            return 0.0;
+        };
+        let points: Vec<(f32, f32)> = self
+            .history
+            .iter()
+            .filter(|sample| latest.at - sample.at <= TREND_WINDOW)
+            .map(|sample| {
+                let secs = -(latest.at - sample.at).as_secs_f32();
+                (secs, sample.hr_status.heart_rate_bpm as f32)
+            })
+            .collect();
+        let count = points.len() as f32;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f32>() / count;
+        let mean_bpm = points.iter().map(|(_, bpm)| bpm).sum::<f32>() / count;
+        let covariance: f32 = points
+            .iter()
+            .map(|(t, bpm)| (t - mean_t) * (bpm - mean_bpm))
+            .sum();
+        let variance: f32 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        (covariance / variance).clamp(-MAX_TREND, MAX_TREND)
+    }
+    /// RMSSD / √2, the spread independent beats would need to show the same beat-to-beat variation
+    fn rr_spread(&self) -> Duration {
+        let rr: Vec<f32> = self
+            .history
+            .iter()
+            .flat_map(|sample| sample.hr_status.clean_rr_intervals())
+            .map(|rr| rr.as_secs_f32())
+            .collect();
+        if rr.len() < 2 {
+            return Duration::ZERO;
+        }
+        let mean_square =
+            rr.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f32>() / (rr.len() - 1) as f32;
+        Duration::from_secs_f32((mean_square / 2.0).sqrt())
+    }
+}
+
+/// Carries on with the trend as it flattens out, then settles towards the baseline
+fn trend_bpm(snapshot: &Snapshot, elapsed_secs: f32) -> f32 {
+    let start = snapshot.last.heart_rate_bpm as f32;
+    let extrapolated =
+        start + snapshot.trend * TREND_FADE_SECS * (1.0 - (-elapsed_secs / TREND_FADE_SECS).exp());
+    let settling_secs = (elapsed_secs - TREND_FADE_SECS).max(0.0);
+    let settled = snapshot.baseline
+        + (extrapolated - snapshot.baseline) * (-settling_secs / BASELINE_DECAY_SECS).exp();
+    settled.max(MIN_BPM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn status(bpm: u16, rr_ms: &[u64]) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            rr_intervals: rr_ms.iter().map(|ms| Duration::from_millis(*ms)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Records one update per second, then returns when the connection was lost
+    fn recorded(mimic: &mut Mimic, start: Instant, updates: &[HeartRateStatus]) -> Instant {
+        for (i, hr_status) in updates.iter().enumerate() {
+            mimic.record(hr_status, start + Duration::from_secs(i as u64));
+        }
+        start + Duration::from_secs(updates.len() as u64)
+    }
+
+    #[test]
+    fn hold() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut mimic = Mimic::new(MimicStrategy::Hold, 60, Duration::from_millis(50));
+        let since = recorded(&mut mimic, start, &[status(90, &[650]), status(92, &[655])]);
+        for secs in [1, 30, 59] {
+            let data = mimic.next(since, since + Duration::from_secs(secs), &mut rng);
+            assert_eq!(data.heart_rate_bpm, 92);
+            assert_eq!(data.rr_intervals, vec![Duration::from_millis(655)]);
+            assert!(!data.twitch_up && !data.twitch_down);
+        }
+    }
+
+    #[test]
+    fn jitter_stays_close() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut mimic = Mimic::new(MimicStrategy::Jitter, 60, Duration::from_millis(50));
+        let since = recorded(&mut mimic, start, &[status(100, &[600])]);
+        for secs in 0..20 {
+            let data = mimic.next(since, since + Duration::from_secs(secs), &mut rng);
+            assert!((97..=102).contains(&data.heart_rate_bpm));
+            assert_eq!(data.rr_intervals.len(), 1);
+        }
+    }
+
+    #[test]
+    fn trend_continues_then_settles() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut mimic = Mimic::new(MimicStrategy::Trend, 60, Duration::from_millis(50));
+        // Climbing half a BPM per second
+        let climbing: Vec<HeartRateStatus> = (0..20)
+            .map(|i| {
+                let bpm = 120 + i / 2;
+                status(bpm, &[60_000 / bpm as u64])
+            })
+            .collect();
+        let since = recorded(&mut mimic, start, &climbing);
+        let last_bpm = climbing.last().unwrap().heart_rate_bpm;
+
+        let soon = mimic.next(since, since + Duration::from_secs(5), &mut rng);
+        assert!(soon.heart_rate_bpm > last_bpm, "{}", soon.heart_rate_bpm);
+        let later = mimic.next(since, since + Duration::from_secs(600), &mut rng);
+        assert!(
+            later.heart_rate_bpm.abs_diff(60) <= 2,
+            "{}",
+            later.heart_rate_bpm
+        );
+    }
+
+    #[test]
+    fn trend_keeps_rr_variability() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut mimic = Mimic::new(MimicStrategy::Trend, 60, Duration::from_millis(500));
+        let alternating: Vec<HeartRateStatus> = (0..30)
+            .map(|i| status(75, &[if i % 2 == 0 { 770 } else { 830 }]))
+            .collect();
+        let since = recorded(&mut mimic, start, &alternating);
+        let rr: Vec<Duration> = (1..=20)
+            .map(|secs| mimic.next(since, since + Duration::from_secs(secs), &mut rng))
+            .map(|data| data.rr_intervals[0])
+            .collect();
+        assert!(rr.windows(2).any(|w| w[0] != w[1]), "RR should vary");
+        let max_spread = rr.iter().max().unwrap().abs_diff(*rr.iter().min().unwrap());
+        assert!(max_spread < Duration::from_millis(250), "{max_spread:?}");
+    }
+
+    #[test]
+    fn replay_loops() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut mimic = Mimic::new(MimicStrategy::Replay, 60, Duration::from_millis(50));
+        let updates: Vec<HeartRateStatus> =
+            (0..4).map(|i| status(80 + i, &[700 + i as u64])).collect();
+        let since = recorded(&mut mimic, start, &updates);
+        // Recorded samples span 4 seconds, up until the disconnection
+        let bpms: Vec<u16> = (0..9)
+            .map(|secs| mimic.next(since, since + Duration::from_secs(secs), &mut rng))
+            .map(|data| data.heart_rate_bpm)
+            .collect();
+        assert_eq!(bpms, vec![80, 81, 82, 83, 80, 81, 82, 83, 80]);
+    }
+
+    #[test]
+    fn real_data_ends_masking() {
+        let start = Instant::now();
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut mimic = Mimic::new(MimicStrategy::Hold, 60, Duration::from_millis(50));
+        let since = recorded(&mut mimic, start, &[status(90, &[])]);
+        assert_eq!(mimic.next(since, since, &mut rng).heart_rate_bpm, 90);
+        mimic.record(&status(110, &[]), since + Duration::from_secs(2));
+        let since = since + Duration::from_secs(3);
+        assert_eq!(mimic.next(since, since, &mut rng).heart_rate_bpm, 110);
+    }
+}
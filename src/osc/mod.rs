@@ -1,22 +1,36 @@
 use addresses::OscAddresses;
-use hr::{make_mimic_data, send_raw_activity_param, send_raw_beat_params, send_raw_hr_status};
+use beat_phase::BeatScheduler;
+use chrono::Local;
+use hr::{
+    send_raw_activity_param, send_raw_beat_params, send_raw_hr_status, send_raw_twitch_params,
+};
+use mapping::{MappingInput, ParamMapper};
+use mimic::Mimic;
 use rosc::OscTime;
+use sender::OscSender;
 use std::net::{SocketAddrV4, UdpSocket};
 use std::str::FromStr;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
-use tokio::time::{self, interval, Duration, Instant, Interval};
+use tokio::time::{self, Duration, Instant, Interval};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
+use twitch::TwitchDriver;
 
 use crate::app::{AppUpdate, ErrorPopup};
 use crate::broadcast;
 use crate::errors::AppError;
 use crate::heart_rate::{rr_from_bpm, HeartRateStatus};
 use crate::settings::OscSettings;
+use crate::zones::HrZones;
 
 mod addresses;
+mod beat_phase;
 mod hr;
+mod mapping;
+mod mimic;
+mod sender;
+mod twitch;
 
 const OSC_NOW: OscTime = OscTime {
     seconds: 0,
@@ -25,38 +39,53 @@ const OSC_NOW: OscTime = OscTime {
 
 struct OscActor {
     // I/O and current data
-    target_addr: SocketAddrV4,
+    sender: OscSender,
     hr_status: HeartRateStatus,
     //
     osc_settings: OscSettings,
-    socket: UdpSocket,
     osc_addresses: OscAddresses,
+    param_mapper: ParamMapper,
     // Used to delay the connected bool by one update "cycle",
     // as otherwise a value of "0" can sneak in on the display.
     delay_sending_connected: bool,
     //
-    positive_float_bpm: bool,
     use_real_rr: bool,
-    latest_rr: Duration,
-    // This interval flip/flops between the RR duration and the pulse duration
-    // to allow sending a pulse without a blocking sleep() call
-    heart_beat_ticker: Interval,
+    // Predicts when the wearer's next beat lands, so pulses line up with it
+    beat_scheduler: BeatScheduler,
     beat_pulse: Duration,
     pulse_edge: bool,
+    // When the current beat pulse should fall back to false
+    pulse_release: Option<Instant>,
     toggle_edge: bool,
+    twitch_up: TwitchDriver,
+    twitch_down: TwitchDriver,
     disconnected_at: Option<Instant>,
     disconnect_update_interval: Interval,
+    // Makes up the data sent while hiding a disconnection
+    mimic: Mimic,
     // Used when BLE connection is lost, but we don't want to
     // hide the BPM display in VRChat, we'll just bounce around
     // the last known actual value until we reconnect or time out.
     max_hide_disconnection: Duration,
+    // Resends everything we've sent so far, in case the
+    // avatar was reset or a packet went missing
+    resync_interval: Option<Interval>,
     // TODO send with bpm
     activity: Option<u8>,
 }
 
 impl OscActor {
-    fn build(initial_activity: Option<u8>, osc_settings: OscSettings) -> Result<Self, AppError> {
+    fn build(
+        initial_activity: Option<u8>,
+        osc_settings: OscSettings,
+        zones: HrZones,
+    ) -> Result<Self, AppError> {
         let osc_addresses = OscAddresses::build(&osc_settings.addresses)?;
+        let param_mapper = ParamMapper::build(
+            &osc_settings.addresses.prefix,
+            &osc_settings.mappings,
+            zones.clone(),
+        )?;
 
         let host_addr = SocketAddrV4::from_str(&format!("{}:{}", osc_settings.host_ip, 0))?;
 
@@ -66,30 +95,59 @@ impl OscActor {
         let socket = UdpSocket::bind(host_addr)?;
 
         let beat_pulse_duration = Duration::from_millis(osc_settings.pulse_length_ms as u64);
-        let positive_float_bpm = osc_settings.only_positive_float_bpm;
 
-        let disconnect_update_interval = time::interval(Duration::from_secs(6));
+        let twitch_length = Duration::from_millis(osc_settings.twitch_length_ms as u64);
+        let twitch_cooldown = Duration::from_millis(osc_settings.twitch_cooldown_ms as u64);
+        let twitch_up = TwitchDriver::new(osc_settings.twitch_mode, twitch_length, twitch_cooldown);
+        let twitch_down =
+            TwitchDriver::new(osc_settings.twitch_mode, twitch_length, twitch_cooldown);
+
+        let mimic = Mimic::new(
+            osc_settings.mimic_strategy,
+            zones.resting_bpm(),
+            Duration::from_millis(osc_settings.twitch_rr_threshold_ms as u64),
+        );
+        let disconnect_update_interval = time::interval(mimic.update_interval());
 
         let max_hide_disconnection =
             Duration::from_secs(osc_settings.max_hide_disconnection_sec as u64);
 
-        Ok(OscActor {
+        let sender = OscSender::new(
+            socket,
             target_addr,
+            osc_settings.only_send_changes,
+            Duration::from_millis(osc_settings.min_send_interval_ms as u64),
+        );
+
+        let resync_interval = match osc_settings.resync_interval_sec {
+            0 => None,
+            secs => {
+                let period = Duration::from_secs(secs as u64);
+                // Skip the immediate first tick, init_params just sent everything
+                Some(time::interval_at(Instant::now() + period, period))
+            }
+        };
+
+        Ok(OscActor {
+            sender,
             delay_sending_connected: true,
-            positive_float_bpm,
             use_real_rr: false,
-            latest_rr: Duration::from_secs(1),
-            socket,
+            beat_scheduler: BeatScheduler::new(Duration::from_secs(1)),
             osc_settings,
             osc_addresses,
+            param_mapper,
             hr_status: HeartRateStatus::default(),
-            heart_beat_ticker: interval(Duration::from_secs(1)),
             beat_pulse: beat_pulse_duration,
             pulse_edge: false,
+            pulse_release: None,
             toggle_edge: false,
+            twitch_up,
+            twitch_down,
             disconnected_at: None,
             disconnect_update_interval,
+            mimic,
             max_hide_disconnection,
+            resync_interval,
             activity: initial_activity,
         })
     }
@@ -98,37 +156,54 @@ impl OscActor {
     fn init_params(&mut self) -> Result<(), AppError> {
         self.delay_sending_connected = true;
         self.toggle_edge = false;
+        self.pulse_edge = false;
+        self.pulse_release = None;
+        self.beat_scheduler.reset();
         send_raw_hr_status(
             &HeartRateStatus::default(),
             false,
             false,
-            self.positive_float_bpm,
+            &mut self.param_mapper,
             &self.osc_addresses,
-            &self.socket,
-            self.target_addr,
+            &mut self.sender,
         )?;
-        send_raw_beat_params(
-            false,
-            false,
+        send_raw_beat_params(false, false, &self.osc_addresses, &mut self.sender)?;
+        self.twitch_up.reset();
+        self.twitch_down.reset();
+        send_raw_twitch_params(
+            Some(false),
+            Some(false),
             &self.osc_addresses,
-            &self.socket,
-            self.target_addr,
+            &mut self.sender,
         )?;
-        send_raw_activity_param(0, &self.osc_addresses, &self.socket, self.target_addr)?;
+        send_raw_activity_param(0, &self.osc_addresses, &mut self.sender)?;
+        // Don't leave these waiting on the rate limit, we might be shutting down
+        self.sender.flush()?;
         Ok(())
     }
     fn handle_data(&mut self, data: HeartRateStatus) -> Result<(), AppError> {
         // Fresh BPM data!
         if data.heart_rate_bpm > 0 {
+            self.trigger_twitches(data.twitch_up, data.twitch_down)?;
+            self.mimic.record(&data, Instant::now());
             self.hr_status = data;
             self.disconnected_at = None;
-            if let Some(new_rr) = self.hr_status.rr_intervals.last() {
-                self.latest_rr = *new_rr;
+            let now = Instant::now();
+            let rr_intervals: Vec<Duration> = self.hr_status.clean_rr_intervals().collect();
+            if !rr_intervals.is_empty() {
+                // The last beat in the batch landed when the source received it
+                let age = (Local::now() - self.hr_status.timestamp)
+                    .to_std()
+                    .unwrap_or_default();
+                let last_beat_at = now.checked_sub(age).unwrap_or(now);
+                self.beat_scheduler
+                    .observe(&rr_intervals, last_beat_at, now);
                 // Mark that we know we'll get real RR intervals
                 // (don't need to calculate from BPM from now on)
                 self.use_real_rr = true;
             } else if !self.use_real_rr {
-                self.latest_rr = rr_from_bpm(self.hr_status.heart_rate_bpm);
+                self.beat_scheduler
+                    .set_period(rr_from_bpm(self.hr_status.heart_rate_bpm), now);
             }
         // Got a 0 BPM packet
         // This can be due to either a disconnection,
@@ -155,10 +230,9 @@ impl OscActor {
             &self.hr_status,
             hiding_ble_disconnection,
             self.delay_sending_connected,
-            self.positive_float_bpm,
+            &mut self.param_mapper,
             &self.osc_addresses,
-            &self.socket,
-            self.target_addr,
+            &mut self.sender,
         )?;
         // Check after sending, otherwise it's pointless
         if self.delay_sending_connected && (self.hr_status.heart_rate_bpm > 0) {
@@ -166,33 +240,58 @@ impl OscActor {
         }
         Ok(())
     }
-    // Ran by the `heart_beat_ticker`'s tick()
-    // And modifies the interval on each tick
-    // to send short pulses without blocking
+    // Ran once the beat scheduler's predicted beat arrives (rising edge)
+    // and again once the pulse length has passed (falling edge)
     fn heart_beat(&mut self) -> Result<(), AppError> {
-        if self.hr_status.heart_rate_bpm > 0 && !self.delay_sending_connected {
-            if !self.pulse_edge {
-                // Rising edge
-                self.pulse_edge = true;
-                self.toggle_edge = !self.toggle_edge;
-                self.heart_beat_ticker = time::interval(self.beat_pulse);
-                self.heart_beat_ticker.reset();
-            } else {
-                // Falling edge
-                self.pulse_edge = false;
-                let new_interval = self.latest_rr.saturating_sub(self.beat_pulse);
-                self.heart_beat_ticker = time::interval(new_interval);
-                self.heart_beat_ticker.reset();
+        let now = Instant::now();
+        if self.pulse_release.is_some_and(|release| release <= now) {
+            self.pulse_release = None;
+            self.pulse_edge = false;
+        } else {
+            self.beat_scheduler.beat(now);
+            if self.hr_status.heart_rate_bpm == 0 || self.delay_sending_connected {
+                return Ok(());
             }
-            send_raw_beat_params(
-                self.pulse_edge,
-                self.toggle_edge,
-                &self.osc_addresses,
-                &self.socket,
-                self.target_addr,
-            )?;
+            self.pulse_edge = true;
+            self.toggle_edge = !self.toggle_edge;
+            // Can't be longer than the beat itself, or we'd miss the next rising edge
+            let max_pulse = self.beat_scheduler.period().mul_f32(0.5);
+            self.pulse_release = Some(now + self.beat_pulse.min(max_pulse));
+        }
+        send_raw_beat_params(
+            self.pulse_edge,
+            self.toggle_edge,
+            &self.osc_addresses,
+            &mut self.sender,
+        )
+    }
+    fn heart_beat_deadline(&self) -> Option<Instant> {
+        match (self.pulse_release, self.beat_scheduler.next_beat_deadline()) {
+            (Some(release), Some(beat)) => Some(release.min(beat)),
+            (release, beat) => release.or(beat),
+        }
+    }
+    fn trigger_twitches(&mut self, twitch_up: bool, twitch_down: bool) -> Result<(), AppError> {
+        let now = Instant::now();
+        let up = twitch_up.then(|| self.twitch_up.trigger(now)).flatten();
+        let down = twitch_down.then(|| self.twitch_down.trigger(now)).flatten();
+        send_raw_twitch_params(up, down, &self.osc_addresses, &mut self.sender)
+    }
+    // Ran once the earliest twitch release deadline passes
+    fn release_twitches(&mut self) -> Result<(), AppError> {
+        let now = Instant::now();
+        let up = self.twitch_up.release(now);
+        let down = self.twitch_down.release(now);
+        send_raw_twitch_params(up, down, &self.osc_addresses, &mut self.sender)
+    }
+    fn twitch_release_deadline(&self) -> Option<Instant> {
+        match (
+            self.twitch_up.release_deadline(),
+            self.twitch_down.release_deadline(),
+        ) {
+            (Some(up), Some(down)) => Some(up.min(down)),
+            (up, down) => up.or(down),
         }
-        Ok(())
     }
     fn mimic_tick(&mut self) -> Result<(), AppError> {
         if let Some(dc_timestamp) = self.disconnected_at {
@@ -200,19 +299,25 @@ impl OscActor {
                 && (self.hr_status.heart_rate_bpm > 0);
 
             if hiding_ble_disconnection {
-                let mimic = make_mimic_data(&self.hr_status);
+                let now = Instant::now();
+                let mimic = self.mimic.next(dc_timestamp, now, &mut rand::thread_rng());
+                // Keeps the avatar's heart beating along with the made up data
+                if let Some(rr) = mimic.latest_clean_rr() {
+                    self.beat_scheduler.set_period(rr, now);
+                }
                 send_raw_hr_status(
                     &mimic,
                     hiding_ble_disconnection,
                     self.delay_sending_connected,
-                    self.positive_float_bpm,
+                    &mut self.param_mapper,
                     &self.osc_addresses,
-                    &self.socket,
-                    self.target_addr,
+                    &mut self.sender,
                 )?;
+                self.trigger_twitches(mimic.twitch_up, mimic.twitch_down)?;
             } else {
                 // Alright, we're really disconnected now
                 self.hr_status = HeartRateStatus::default();
+                self.mimic.reset();
                 self.init_params()?;
             }
         }
@@ -226,17 +331,33 @@ impl OscActor {
         self.init_params()?;
 
         loop {
-            let heart_beat = self.heart_beat_ticker.tick();
+            let flush_deadline = self.sender.flush_deadline();
+            let twitch_deadline = self.twitch_release_deadline();
+            let heart_beat_deadline = self.heart_beat_deadline();
             let mimic = self.disconnect_update_interval.tick();
+            let resync = async {
+                match self.resync_interval.as_mut() {
+                    Some(interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
             tokio::select! {
                 hr_data = broadcast_rx.recv() => {
                     match hr_data {
                         Ok(AppUpdate::HeartRateStatus(data)) => {
                             self.handle_data(data)?;
                         },
-                        Ok(AppUpdate::ActivitySelected(index)) => {
+                        Ok(AppUpdate::ActivitySelected(index, _)) => {
                             self.activity = Some(index);
-                            send_raw_activity_param(index, &self.osc_addresses, &self.socket, self.target_addr)?;
+                            send_raw_activity_param(index, &self.osc_addresses, &mut self.sender)?;
+                        },
+                        Ok(AppUpdate::SessionStats(stats)) => {
+                            let messages = self.param_mapper.messages(MappingInput::SessionStats(&stats));
+                            self.sender.send(messages)?;
+                        },
+                        Ok(AppUpdate::Hrv(hrv)) => {
+                            let messages = self.param_mapper.messages(MappingInput::Hrv(&hrv));
+                            self.sender.send(messages)?;
                         },
                         Ok(_) => {},
                         Err(RecvError::Closed) => {
@@ -250,16 +371,31 @@ impl OscActor {
                     }
                 }
                 // Sending params for each heart beat, based on the measured interval
-                _ = heart_beat => {
+                _ = time::sleep_until(heart_beat_deadline.unwrap_or_else(Instant::now)), if heart_beat_deadline.is_some() => {
                     self.heart_beat()?;
                 }
                 // Sending mimic data when we're disconnected
                 _ = mimic => {
                     self.mimic_tick()?;
                 }
+                // Releasing twitches that were pulsed/held
+                _ = time::sleep_until(twitch_deadline.unwrap_or_else(Instant::now)), if twitch_deadline.is_some() => {
+                    self.release_twitches()?;
+                }
+                // Sending values that were held back by the rate limit
+                _ = time::sleep_until(flush_deadline.unwrap_or_else(Instant::now)), if flush_deadline.is_some() => {
+                    self.sender.flush()?;
+                }
+                _ = resync => {
+                    self.sender.resync()?;
+                    let counters = self.sender.counters();
+                    let messages = self.param_mapper.messages(MappingInput::SendCounters(&counters));
+                    self.sender.send(messages)?;
+                }
                 _ = cancel_token.cancelled() => {
                     info!("Shutting down OSC thread!");
                     self.init_params()?;
+                    self.sender.log_counters();
                     break;
                 }
             }
@@ -273,9 +409,10 @@ pub async fn osc_thread(
     broadcast_tx: BSender<AppUpdate>,
     initial_activity: Option<u8>,
     osc_settings: OscSettings,
+    zones: HrZones,
     cancel_token: CancellationToken,
 ) {
-    let mut osc = match OscActor::build(initial_activity, osc_settings) {
+    let mut osc = match OscActor::build(initial_activity, osc_settings, zones) {
         Ok(osc) => osc,
         Err(e) => {
             error!("Failed to set up OSC. {e}");
@@ -285,10 +422,6 @@ pub async fn osc_thread(
         }
     };
 
-    // TODO?
-    // Maybe option for twitches to be a toggle and/or pulse?
-    // Current implementation is a weird mix of both, but is simple to implement
-
     if let Err(e) = osc.rx_loop(broadcast_rx, cancel_token).await {
         error!("OSC Error: {e}");
         let message = "OSC Error";
@@ -0,0 +1,239 @@
+use rosc::encoder;
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddrV4, UdpSocket};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use super::OSC_NOW;
+use crate::errors::AppError;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SendCounters {
+    /// Messages that actually went out over the socket
+    pub sent: u64,
+    /// Messages skipped since the avatar already had that value
+    pub unchanged: u64,
+    /// Messages replaced by a newer value while waiting on the rate limit
+    pub rate_limited: u64,
+}
+
+/// Keeps track of what each OSC address was last sent,
+/// so we only spend the avatar's sync budget on values that actually changed.
+pub(super) struct OscSender {
+    socket: UdpSocket,
+    target_addr: SocketAddrV4,
+    only_send_changes: bool,
+    min_send_interval: Duration,
+    last_values: HashMap<String, OscType>,
+    // Sent with send_beat, resending one of these on resync would look like an extra beat
+    momentary: HashSet<String>,
+    // Insertion order is kept so values go out in the order they were given
+    pending: Vec<OscMessage>,
+    last_send: Option<Instant>,
+    counters: SendCounters,
+}
+
+impl OscSender {
+    pub fn new(
+        socket: UdpSocket,
+        target_addr: SocketAddrV4,
+        only_send_changes: bool,
+        min_send_interval: Duration,
+    ) -> Self {
+        Self {
+            socket,
+            target_addr,
+            only_send_changes,
+            min_send_interval,
+            last_values: HashMap::new(),
+            momentary: HashSet::new(),
+            pending: Vec::new(),
+            last_send: None,
+            counters: SendCounters::default(),
+        }
+    }
+    /// Queues messages to be sent as soon as the rate limit allows (which is usually right away)
+    pub fn send(&mut self, messages: Vec<OscMessage>) -> Result<(), AppError> {
+        for message in messages {
+            if let Some(queued) = self.pending.iter_mut().find(|m| m.addr == message.addr) {
+                self.counters.rate_limited += 1;
+                *queued = message;
+            } else {
+                self.pending.push(message);
+            }
+        }
+        if self.flush_deadline().is_some_and(|d| d <= Instant::now()) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    /// Skips the rate limit, for timing sensitive params like heart beats
+    pub fn send_now(&mut self, messages: Vec<OscMessage>) -> Result<(), AppError> {
+        let messages = self.filter_unchanged(messages);
+        self.send_bundle(messages)
+    }
+    /// Like `send_now`, but these are left out of resyncs
+    pub fn send_beat(&mut self, messages: Vec<OscMessage>) -> Result<(), AppError> {
+        for message in &messages {
+            self.momentary.insert(message.addr.clone());
+        }
+        self.send_now(messages)
+    }
+    /// When queued messages are allowed to go out, if there are any
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        match self.last_send {
+            Some(last_send) => Some(last_send + self.min_send_interval),
+            None => Some(Instant::now()),
+        }
+    }
+    pub fn flush(&mut self) -> Result<(), AppError> {
+        let pending = std::mem::take(&mut self.pending);
+        let messages = self.filter_unchanged(pending);
+        self.last_send = Some(Instant::now());
+        self.send_bundle(messages)
+    }
+    /// Sends every known value again (besides beats), in case the receiver restarted or missed a packet
+    pub fn resync(&mut self) -> Result<(), AppError> {
+        // Anything waiting on the rate limit is newer than what we have saved
+        self.flush()?;
+        let messages: Vec<OscMessage> = self
+            .last_values
+            .iter()
+            .filter(|(addr, _)| !self.momentary.contains(*addr))
+            .map(|(addr, value)| OscMessage {
+                addr: addr.clone(),
+                args: vec![value.clone()],
+            })
+            .collect();
+        debug!(
+            "OSC resync: {} params | {:?}",
+            messages.len(),
+            self.counters
+        );
+        self.send_bundle(messages)
+    }
+    pub fn counters(&self) -> SendCounters {
+        self.counters
+    }
+    pub fn log_counters(&self) {
+        let SendCounters {
+            sent,
+            unchanged,
+            rate_limited,
+        } = self.counters();
+        info!("OSC messages sent: {sent} | Unchanged (skipped): {unchanged} | Rate limited (skipped): {rate_limited}");
+    }
+    fn filter_unchanged(&mut self, messages: Vec<OscMessage>) -> Vec<OscMessage> {
+        messages
+            .into_iter()
+            .filter(|message| {
+                let Some(value) = message.args.first() else {
+                    return true;
+                };
+                let changed = self.last_values.get(&message.addr) != Some(value);
+                if changed {
+                    self.last_values.insert(message.addr.clone(), value.clone());
+                } else if self.only_send_changes {
+                    self.counters.unchanged += 1;
+                    return false;
+                }
+                true
+            })
+            .collect()
+    }
+    fn send_bundle(&mut self, messages: Vec<OscMessage>) -> Result<(), AppError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        self.counters.sent += messages.len() as u64;
+        let bundle = OscBundle {
+            timetag: OSC_NOW,
+            content: messages.into_iter().map(OscPacket::Message).collect(),
+        };
+        let msg_buf = encoder::encode(&OscPacket::Bundle(bundle))?;
+        self.socket.send_to(&msg_buf, self.target_addr)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn sender(only_send_changes: bool, min_send_interval: Duration) -> OscSender {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // Nobody's listening, but UDP doesn't mind
+        let target_addr = SocketAddrV4::from_str("127.0.0.1:9").unwrap();
+        OscSender::new(socket, target_addr, only_send_changes, min_send_interval)
+    }
+
+    fn message(addr: &str, value: i32) -> OscMessage {
+        OscMessage {
+            addr: addr.into(),
+            args: vec![OscType::Int(value)],
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged() {
+        let mut sender = sender(true, Duration::ZERO);
+        sender
+            .send(vec![message("/hr", 70), message("/battery", 90)])
+            .unwrap();
+        sender
+            .send(vec![message("/hr", 71), message("/battery", 90)])
+            .unwrap();
+        let counters = sender.counters();
+        assert_eq!(counters.sent, 3);
+        assert_eq!(counters.unchanged, 1);
+        // Resync ignores what was sent before
+        sender.resync().unwrap();
+        assert_eq!(sender.counters().sent, 5);
+    }
+
+    #[tokio::test]
+    async fn resync_skips_beats() {
+        let mut sender = sender(true, Duration::ZERO);
+        sender.send(vec![message("/hr", 70)]).unwrap();
+        sender.send_beat(vec![message("/beat", 1)]).unwrap();
+        // Such as a toggled twitch, which a receiver that joined late still needs
+        sender.send_now(vec![message("/twitch", 1)]).unwrap();
+        assert_eq!(sender.counters().sent, 3);
+        sender.resync().unwrap();
+        assert_eq!(sender.counters().sent, 5);
+    }
+
+    #[tokio::test]
+    async fn sends_everything_when_disabled() {
+        let mut sender = sender(false, Duration::ZERO);
+        sender.send(vec![message("/hr", 70)]).unwrap();
+        sender.send_now(vec![message("/hr", 70)]).unwrap();
+        assert_eq!(sender.counters().sent, 2);
+        assert_eq!(sender.counters().unchanged, 0);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_keeps_latest() {
+        let mut sender = sender(true, Duration::from_millis(50));
+        sender.send(vec![message("/hr", 70)]).unwrap();
+        assert_eq!(sender.flush_deadline(), None);
+        // Too soon, these are held back and the newest /hr wins
+        sender.send(vec![message("/hr", 71)]).unwrap();
+        sender.send(vec![message("/hr", 72)]).unwrap();
+        assert_eq!(sender.counters().sent, 1);
+        assert_eq!(sender.counters().rate_limited, 1);
+        let deadline = sender.flush_deadline().unwrap();
+        tokio::time::sleep_until(deadline).await;
+        sender.flush().unwrap();
+        assert_eq!(sender.counters().sent, 2);
+        assert_eq!(sender.flush_deadline(), None);
+        // Beats don't wait
+        sender.send_beat(vec![message("/beat", 1)]).unwrap();
+        assert_eq!(sender.counters().sent, 3);
+    }
+}
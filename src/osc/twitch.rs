@@ -0,0 +1,131 @@
+use tokio::time::{Duration, Instant};
+
+use crate::settings::TwitchMode;
+
+/// Drives a single twitch param on its own timer,
+/// instead of whenever the next HR update happens to go out.
+pub(super) struct TwitchDriver {
+    mode: TwitchMode,
+    length: Duration,
+    cooldown: Duration,
+    value: bool,
+    last_trigger: Option<Instant>,
+    // When the param should fall back to false (Pulse and Hold only)
+    release_at: Option<Instant>,
+}
+
+impl TwitchDriver {
+    pub fn new(mode: TwitchMode, length: Duration, cooldown: Duration) -> Self {
+        Self {
+            mode,
+            length,
+            cooldown,
+            value: false,
+            last_trigger: None,
+            release_at: None,
+        }
+    }
+    /// Returns the new value if the param needs to be sent
+    pub fn trigger(&mut self, now: Instant) -> Option<bool> {
+        if let Some(last) = self.last_trigger {
+            if now.saturating_duration_since(last) < self.cooldown {
+                return None;
+            }
+        }
+        match self.mode {
+            TwitchMode::Pulse => {
+                if self.value {
+                    return None;
+                }
+                self.value = true;
+                self.release_at = Some(now + self.length);
+            }
+            TwitchMode::Toggle => {
+                self.value = !self.value;
+            }
+            TwitchMode::Hold => {
+                self.release_at = Some(now + self.length);
+                if self.value {
+                    // Still held, just pushed back the release
+                    self.last_trigger = Some(now);
+                    return None;
+                }
+                self.value = true;
+            }
+        }
+        self.last_trigger = Some(now);
+        Some(self.value)
+    }
+    pub fn release_deadline(&self) -> Option<Instant> {
+        self.release_at
+    }
+    /// Returns the new value if the param was released
+    pub fn release(&mut self, now: Instant) -> Option<bool> {
+        match self.release_at {
+            Some(release_at) if release_at <= now => {
+                self.release_at = None;
+                self.value = false;
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+    pub fn reset(&mut self) {
+        self.value = false;
+        self.last_trigger = None;
+        self.release_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn pulse_ignores_retrigger() {
+        let start = Instant::now();
+        let mut twitch = TwitchDriver::new(TwitchMode::Pulse, MS * 100, Duration::ZERO);
+        assert_eq!(twitch.trigger(start), Some(true));
+        assert_eq!(twitch.trigger(start + MS * 50), None);
+        assert_eq!(twitch.release_deadline(), Some(start + MS * 100));
+        assert_eq!(twitch.release(start + MS * 99), None);
+        assert_eq!(twitch.release(start + MS * 100), Some(false));
+        assert_eq!(twitch.release_deadline(), None);
+        assert_eq!(twitch.trigger(start + MS * 150), Some(true));
+    }
+
+    #[test]
+    fn hold_extends() {
+        let start = Instant::now();
+        let mut twitch = TwitchDriver::new(TwitchMode::Hold, MS * 100, Duration::ZERO);
+        assert_eq!(twitch.trigger(start), Some(true));
+        assert_eq!(twitch.trigger(start + MS * 80), None);
+        assert_eq!(twitch.release(start + MS * 100), None);
+        assert!(twitch.value);
+        assert_eq!(twitch.release(start + MS * 180), Some(false));
+    }
+
+    #[test]
+    fn toggle_never_releases() {
+        let start = Instant::now();
+        let mut twitch = TwitchDriver::new(TwitchMode::Toggle, MS * 100, Duration::ZERO);
+        assert_eq!(twitch.trigger(start), Some(true));
+        assert_eq!(twitch.release_deadline(), None);
+        assert_eq!(twitch.trigger(start + MS), Some(false));
+        assert_eq!(twitch.trigger(start + MS * 2), Some(true));
+    }
+
+    #[test]
+    fn cooldown_blocks_triggers() {
+        let start = Instant::now();
+        let mut twitch = TwitchDriver::new(TwitchMode::Toggle, MS * 100, MS * 500);
+        assert_eq!(twitch.trigger(start), Some(true));
+        assert_eq!(twitch.trigger(start + MS * 499), None);
+        assert_eq!(twitch.trigger(start + MS * 500), Some(false));
+        twitch.reset();
+        assert!(!twitch.value);
+        assert_eq!(twitch.trigger(start + MS * 501), Some(true));
+    }
+}
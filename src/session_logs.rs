@@ -0,0 +1,541 @@
+use chrono::{DateTime, Local, NaiveDateTime};
+use ratatui::widgets::TableState;
+use serde_derive::Deserialize;
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tracing::{error, warn};
+
+use crate::app::{App, AppView, SubState};
+use crate::errors::AppError;
+use crate::logging::{CSV_DELIMITERS, SESSION_FILE_PREFIX};
+use crate::ui::table_state_scroll;
+use crate::widgets::heart_rate::charts::{ChartData, ComparedData};
+use crate::widgets::heart_rate_display::{CHART_BPM_VERT_MARGIN, CHART_RR_VERT_MARGIN};
+
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// Timestamps bigger than this are Unix milliseconds, anything below is elapsed seconds
+// (a session would have to run for over 3 years to get here)
+const MIN_UNIX_MS: f64 = 1e11;
+// Long sessions get thinned out to this many points, the chart can't show more anyway
+const MAX_CHART_POINTS: usize = 1000;
+
+// Only the columns we need, so logs from older versions can still be read
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    Timestamp: String,
+    BPM: u16,
+    #[serde(default)]
+    RR: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogSample {
+    /// Since the start of the session
+    pub secs: f64,
+    pub bpm: u16,
+    pub rr: Option<Duration>,
+}
+
+/// A past session, read back from its CSV log
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionLog {
+    pub name: String,
+    pub start: NaiveDateTime,
+    pub duration: Duration,
+    pub min_bpm: u16,
+    pub average_bpm: f32,
+    pub max_bpm: u16,
+    pub samples: Vec<LogSample>,
+}
+
+impl SessionLog {
+    /// Returns None if the log doesn't have any data in it
+    pub fn read<R: Read>(name: String, reader: R) -> Result<Option<Self>, AppError> {
+        let session_start = start_from_file_name(&name).unwrap_or_default();
+        let mut reader = csv_reader(reader)?;
+        let mut start = None;
+        let mut samples = Vec::new();
+        for row in reader.deserialize() {
+            let row: CsvRow = row?;
+            if row.BPM == 0 {
+                continue;
+            }
+            let timestamp = parse_timestamp(&row.Timestamp, session_start)?;
+            let start = *start.get_or_insert(timestamp);
+            samples.push(LogSample {
+                secs: (timestamp - start).num_milliseconds() as f64 / 1000.0,
+                bpm: row.BPM,
+                rr: (row.RR > 0).then(|| Duration::from_millis(row.RR as u64)),
+            });
+        }
+        let (Some(start), Some(last)) = (start, samples.last()) else {
+            return Ok(None);
+        };
+        let bpms = samples.iter().map(|sample| sample.bpm);
+        Ok(Some(Self {
+            name,
+            start,
+            duration: Duration::from_secs_f64(last.secs),
+            min_bpm: bpms.clone().min().unwrap_or_default(),
+            average_bpm: bpms.clone().map(|bpm| bpm as f32).sum::<f32>() / samples.len() as f32,
+            max_bpm: bpms.max().unwrap_or_default(),
+            samples,
+        }))
+    }
+    pub fn load(path: &Path) -> Result<Option<Self>, AppError> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file = File::open(path)?;
+        Self::read(name, file)
+    }
+}
+
+/// A CSV reader for session logs, using whichever `csv.delimiter` the log was written with
+pub fn csv_reader<R: Read>(mut reader: R) -> Result<csv::Reader<Cursor<String>>, AppError> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    let header = contents.lines().next().unwrap_or_default();
+    // The header's just column names, so the most common symbol in it is the delimiter
+    let delimiter = CSV_DELIMITERS
+        .into_iter()
+        .max_by_key(|delimiter| header.bytes().filter(|b| b == delimiter).count())
+        .filter(|delimiter| header.as_bytes().contains(delimiter))
+        .unwrap_or(b',');
+    Ok(csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(Cursor::new(contents)))
+}
+
+/// Reads timestamps in any `csv.timestamp_format`,
+/// `elapsed_secs` ones are counted from `session_start`
+pub fn parse_timestamp(
+    value: &str,
+    session_start: NaiveDateTime,
+) -> Result<NaiveDateTime, AppError> {
+    let local = match NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT) {
+        Ok(timestamp) => return Ok(timestamp),
+        Err(e) => e,
+    };
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Local).naive_local());
+    }
+    match value.parse::<f64>() {
+        Ok(number) if number >= MIN_UNIX_MS => DateTime::from_timestamp_millis(number as i64)
+            .map(|timestamp| timestamp.with_timezone(&Local).naive_local())
+            .ok_or(local.into()),
+        Ok(secs) if secs >= 0.0 => {
+            Ok(session_start + chrono::Duration::milliseconds((secs * 1000.0).round() as i64))
+        }
+        _ => Err(local.into()),
+    }
+}
+
+/// When a session started, going by its log's `nih-<date>_<time>` file name
+pub fn start_from_file_name(name: &str) -> Option<NaiveDateTime> {
+    // Rotated logs can have the activity and such after it
+    let start = name.strip_prefix(SESSION_FILE_PREFIX)?.get(..19)?;
+    NaiveDateTime::parse_from_str(start, "%Y-%m-%d_%H-%M-%S").ok()
+}
+
+/// Every readable session log in `folder`, newest first
+pub fn list_sessions(folder: &Path) -> Result<Vec<SessionLog>, AppError> {
+    if !folder.exists() {
+        return Ok(Vec::new());
+    }
+    let mut sessions = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        // Skipping sidecars like the RR series' `.rr.csv`
+        let is_session_log = path.extension().is_some_and(|ext| ext == "csv")
+            && path.file_stem().is_some_and(|stem| {
+                let stem = stem.to_string_lossy();
+                stem.starts_with(SESSION_FILE_PREFIX) && !stem.contains('.')
+            });
+        if !is_session_log {
+            continue;
+        }
+        match SessionLog::load(&path) {
+            Ok(Some(session)) => sessions.push(session),
+            Ok(None) => {}
+            Err(e) => warn!("Skipping session log {}: {e}", path.display()),
+        }
+    }
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.start));
+    Ok(sessions)
+}
+
+/// Chart datasets for the highlighted session (and the one it's compared against),
+/// only rebuilt when either changes
+#[derive(Debug, Default)]
+pub struct HistoryChart {
+    bpm: Vec<(f64, f64)>,
+    rr: Vec<(f64, f64)>,
+    compare_bpm: Vec<(f64, f64)>,
+    compare_rr: Vec<(f64, f64)>,
+    bpm_bounds: [f64; 2],
+    rr_bounds: [f64; 2],
+    x_max: f64,
+}
+
+impl HistoryChart {
+    pub fn build(shown: &SessionLog, compare: Option<&SessionLog>) -> Self {
+        let sessions: Vec<&SessionLog> = std::iter::once(shown).chain(compare).collect();
+        let samples = || sessions.iter().flat_map(|session| &session.samples);
+
+        let min_bpm = sessions.iter().map(|s| s.min_bpm).min().unwrap_or_default() as f64;
+        let max_bpm = sessions.iter().map(|s| s.max_bpm).max().unwrap_or_default() as f64;
+        let bpm_bounds = [
+            min_bpm - CHART_BPM_VERT_MARGIN,
+            max_bpm + CHART_BPM_VERT_MARGIN,
+        ];
+
+        let rr_secs = || samples().filter_map(|sample| sample.rr.map(|rr| rr.as_secs_f64()));
+        let rr_bounds = match (rr_secs().reduce(f64::min), rr_secs().reduce(f64::max)) {
+            (Some(low), Some(high)) => [
+                (low - CHART_RR_VERT_MARGIN).max(0.0),
+                high + CHART_RR_VERT_MARGIN,
+            ],
+            _ => [0.0, 1.0],
+        };
+
+        // RR gets drawn on the same scale as BPM, like the combined live chart
+        let scale_rr = |rr: Duration| {
+            let normalized = (rr.as_secs_f64() - rr_bounds[0]) / (rr_bounds[1] - rr_bounds[0]);
+            normalized * (bpm_bounds[1] - bpm_bounds[0]) + bpm_bounds[0]
+        };
+        let bpm_points = |session: &SessionLog| {
+            downsample(
+                session
+                    .samples
+                    .iter()
+                    .map(|sample| (sample.secs, sample.bpm as f64))
+                    .collect(),
+            )
+        };
+        let rr_points = |session: &SessionLog| {
+            downsample(
+                session
+                    .samples
+                    .iter()
+                    .filter_map(|sample| sample.rr.map(|rr| (sample.secs, scale_rr(rr))))
+                    .collect(),
+            )
+        };
+
+        Self {
+            bpm: bpm_points(shown),
+            rr: rr_points(shown),
+            compare_bpm: compare.map(bpm_points).unwrap_or_default(),
+            compare_rr: compare.map(rr_points).unwrap_or_default(),
+            bpm_bounds,
+            rr_bounds,
+            x_max: sessions
+                .iter()
+                .map(|session| session.duration.as_secs_f64())
+                .fold(1.0, f64::max),
+        }
+    }
+    pub fn data<'a>(&'a self, title: String, compare_name: Option<String>) -> ChartData<'a> {
+        ChartData {
+            title,
+            bpm: &self.bpm,
+            rr: &self.rr,
+            compare: compare_name.map(|name| ComparedData {
+                name,
+                bpm: &self.compare_bpm,
+                rr: &self.compare_rr,
+            }),
+            bpm_bounds: self.bpm_bounds,
+            mid_bpm: ((self.bpm_bounds[0] + self.bpm_bounds[1]) / 2.0).ceil(),
+            rr_bounds: self.rr_bounds,
+            mid_rr: (self.rr_bounds[0] + self.rr_bounds[1]) / 2.0,
+            x_bounds: Some([0.0, self.x_max]),
+        }
+    }
+}
+
+fn downsample(points: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    let step = points.len().div_ceil(MAX_CHART_POINTS).max(1);
+    points.into_iter().step_by(step).collect()
+}
+
+pub struct SessionBrowser {
+    pub sessions: Vec<SessionLog>,
+    pub table_state: TableState,
+    /// Index of the session drawn over the highlighted one
+    pub compare: Option<usize>,
+    pub chart: HistoryChart,
+    // Where to go back to once closed
+    return_view: AppView,
+    // Reading every log can take a while, so it's done off the UI thread
+    loading: Option<oneshot::Receiver<Result<Vec<SessionLog>, AppError>>>,
+}
+
+impl SessionBrowser {
+    /// Starts reading the logs in `folder` in the background, see [`Self::poll_loading`]
+    fn open(folder: PathBuf, return_view: AppView) -> Self {
+        let (loaded_tx, loaded_rx) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            // Only fails if the browser was closed before it finished
+            let _ = loaded_tx.send(list_sessions(&folder));
+        });
+        Self {
+            sessions: Vec::new(),
+            table_state: TableState::default(),
+            compare: None,
+            chart: HistoryChart::default(),
+            return_view,
+            loading: Some(loaded_rx),
+        }
+    }
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+    /// Fills in the sessions once they've been read
+    fn poll_loading(&mut self) {
+        let Some(loading) = self.loading.as_mut() else {
+            return;
+        };
+        self.sessions = match loading.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Ok(Ok(sessions)) => sessions,
+            Ok(Err(e)) => {
+                error!("Failed to list session logs: {e}");
+                Vec::new()
+            }
+            Err(TryRecvError::Closed) => {
+                error!("Failed to list session logs: the reading task stopped early");
+                Vec::new()
+            }
+        };
+        self.loading = None;
+        if !self.sessions.is_empty() {
+            self.table_state.select(Some(0));
+        }
+        self.rebuild_chart();
+    }
+    pub fn highlighted(&self) -> Option<&SessionLog> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.sessions.get(index))
+    }
+    pub fn compared(&self) -> Option<&SessionLog> {
+        self.compare.and_then(|index| self.sessions.get(index))
+    }
+    fn rebuild_chart(&mut self) {
+        self.chart = match self.highlighted() {
+            Some(shown) => HistoryChart::build(shown, self.compared()),
+            None => HistoryChart::default(),
+        };
+    }
+}
+
+impl App {
+    pub fn open_session_browser(&mut self) {
+        if self.view == AppView::SessionBrowser || self.sub_state != SubState::None {
+            return;
+        }
+        let folder = PathBuf::from(&self.settings.misc.log_sessions_csv_path);
+        self.session_browser = Some(SessionBrowser::open(folder, self.view));
+        self.view = AppView::SessionBrowser;
+    }
+    pub fn poll_session_browser(&mut self) {
+        if let Some(browser) = self.session_browser.as_mut() {
+            browser.poll_loading();
+        }
+    }
+    pub fn close_session_browser(&mut self) {
+        if let Some(browser) = self.session_browser.take() {
+            self.view = browser.return_view;
+        }
+    }
+    /// Switches views, or if the browser is open, where it'll go back to once closed
+    pub fn set_view(&mut self, view: AppView) {
+        match self.session_browser.as_mut() {
+            Some(browser) => browser.return_view = view,
+            None => self.view = view,
+        }
+    }
+    /// The view that's (or will be) shown outside of the browser
+    pub fn underlying_view(&self) -> AppView {
+        self.session_browser
+            .as_ref()
+            .map_or(self.view, |browser| browser.return_view)
+    }
+    pub fn session_browser_scroll(&mut self, up: bool) {
+        if let Some(browser) = self.session_browser.as_mut() {
+            let len = browser.sessions.len();
+            table_state_scroll(up, &mut browser.table_state, len);
+            browser.rebuild_chart();
+        }
+    }
+    /// Compares the highlighted session against the others, or stops comparing if it already was
+    pub fn session_browser_toggle_compare(&mut self) {
+        if let Some(browser) = self.session_browser.as_mut() {
+            let selected = browser.table_state.selected();
+            browser.compare = if browser.compare == selected {
+                None
+            } else {
+                selected
+            };
+            browser.rebuild_chart();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+Timestamp,BPM,RR,Artifacts,Battery,TwitchUp,TwitchDown,Activity,Zone
+2024-05-01 10:00:00,80,750,0,100,0,0,0,1
+2024-05-01 10:00:01,90,0,0,100,0,0,0,1
+2024-05-01 10:00:02,0,0,0,100,0,0,0,0
+2024-05-01 10:01:05,100,600,0,99,0,0,0,2
+";
+
+    fn session(name: &str, csv: &str) -> SessionLog {
+        SessionLog::read(name.into(), csv.as_bytes())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_log() {
+        let session = session("nih-test", CSV);
+        assert_eq!(session.duration, Duration::from_secs(65));
+        assert_eq!(
+            (session.min_bpm, session.average_bpm, session.max_bpm),
+            (80, 90.0, 100)
+        );
+        assert_eq!(session.samples.len(), 3);
+        assert_eq!(session.samples[1].rr, None);
+        assert_eq!(
+            session.samples[2],
+            LogSample {
+                secs: 65.0,
+                bpm: 100,
+                rr: Some(Duration::from_millis(600)),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_other_formats() {
+        let semicolons = "Timestamp;ActivityName;BPM\n0.000;Dancing;80\n1.500;Dancing;90\n";
+        let elapsed = session("nih-2024-05-01_10-00-00", semicolons);
+        assert_eq!(
+            elapsed.start,
+            start_from_file_name("nih-2024-05-01_10-00-00").unwrap()
+        );
+        assert_eq!(
+            start_from_file_name("nih-2024-05-01_10-00-00_Dancing-2"),
+            Some(elapsed.start)
+        );
+        assert_eq!(elapsed.samples[1].secs, 1.5);
+        assert_eq!(elapsed.samples[1].rr, None);
+
+        let start = elapsed.start;
+        assert_eq!(
+            parse_timestamp("2024-05-01T10:00:00.250+00:00", start).unwrap(),
+            DateTime::parse_from_rfc3339("2024-05-01T10:00:00.250Z")
+                .unwrap()
+                .with_timezone(&Local)
+                .naive_local()
+        );
+        assert_eq!(
+            parse_timestamp("1714557600250", start).unwrap(),
+            DateTime::from_timestamp_millis(1714557600250)
+                .unwrap()
+                .with_timezone(&Local)
+                .naive_local()
+        );
+        assert!(parse_timestamp("yesterday", start).is_err());
+
+        let tabs = "Timestamp\tBPM\tRR\n2024-05-01 10:00:00\t80\t750\n";
+        assert_eq!(
+            session("tabs", tabs).samples[0].rr,
+            Some(Duration::from_millis(750))
+        );
+    }
+
+    #[test]
+    fn empty_log() {
+        let header_only = "Timestamp,BPM,RR\n";
+        assert_eq!(
+            SessionLog::read("empty".into(), header_only.as_bytes()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn comparison_shares_bounds() {
+        let first = session("first", CSV);
+        let second = session(
+            "second",
+            "Timestamp,BPM,RR\n2024-05-02 09:00:00,120,500\n2024-05-02 09:03:20,130,460\n",
+        );
+        let chart = HistoryChart::build(&first, Some(&second));
+        assert_eq!(
+            chart.bpm_bounds,
+            [80.0 - CHART_BPM_VERT_MARGIN, 130.0 + CHART_BPM_VERT_MARGIN]
+        );
+        assert_eq!(chart.x_max, 200.0);
+        assert_eq!(chart.compare_bpm, vec![(0.0, 120.0), (200.0, 130.0)]);
+        // Longest RR sits at the top of the chart
+        let top = chart.rr.iter().map(|(_, y)| *y).fold(f64::MIN, f64::max);
+        let expected_top = (0.75 - chart.rr_bounds[0]) / (chart.rr_bounds[1] - chart.rr_bounds[0])
+            * (chart.bpm_bounds[1] - chart.bpm_bounds[0])
+            + chart.bpm_bounds[0];
+        assert!((top - expected_top).abs() < 1e-9);
+    }
+
+    #[test]
+    fn downsamples_long_sessions() {
+        let points: Vec<(f64, f64)> = (0..5000).map(|i| (i as f64, 80.0)).collect();
+        let thinned = downsample(points);
+        assert_eq!(thinned.len(), MAX_CHART_POINTS);
+        assert_eq!(thinned[1].0, 5.0);
+    }
+
+    #[test]
+    fn lists_newest_first() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(folder.path().join("nih-older.csv"), CSV).unwrap();
+        fs::write(
+            folder.path().join("nih-newer.csv"),
+            "Timestamp,BPM,RR\n2024-06-01 08:00:00,70,850\n",
+        )
+        .unwrap();
+        fs::write(folder.path().join("nih-newer.summary.json"), "{}").unwrap();
+        fs::write(folder.path().join("nih-newer.rr.csv"), "Timestamp,RR\n").unwrap();
+        fs::write(folder.path().join("other.csv"), CSV).unwrap();
+        let names: Vec<String> = list_sessions(folder.path())
+            .unwrap()
+            .into_iter()
+            .map(|session| session.name)
+            .collect();
+        assert_eq!(names, vec!["nih-newer", "nih-older"]);
+    }
+
+    #[tokio::test]
+    async fn browser_loads_in_background() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(folder.path().join("nih-test.csv"), CSV).unwrap();
+        let mut browser = SessionBrowser::open(folder.path().to_owned(), AppView::HeartRateView);
+        assert!(browser.is_loading());
+        while browser.is_loading() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            browser.poll_loading();
+        }
+        assert_eq!(browser.sessions.len(), 1);
+        assert_eq!(browser.highlighted().unwrap().name, "nih-test");
+    }
+}
@@ -16,9 +16,147 @@ pub struct MiscSettings {
     pub bpm_file_path: String,
     pub log_sessions_to_csv: bool,
     pub log_sessions_csv_path: String,
+    pub log_rr_series: bool,
+    pub write_session_summary: bool,
+    /// Converted from the session's CSV log once it's over
+    pub export_formats: Vec<ExportFormat>,
     pub vrcx_shortcut_prompt: bool,
 }
 
+/// Workout file formats that fitness platforms (Strava, Garmin Connect, etc.) accept
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Fit,
+    Tcx,
+    Gpx,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fit" => Ok(Self::Fit),
+            "tcx" => Ok(Self::Tcx),
+            "gpx" => Ok(Self::Gpx),
+            _ => Err(format!(
+                "unknown export format \"{s}\", expected fit, tcx or gpx"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CsvSettings {
+    /// Which columns the session CSV has, in order
+    pub columns: Vec<CsvColumn>,
+    pub timestamp_format: CsvTimestampFormat,
+    /// One of `,` `;` `\t` or `|`
+    pub delimiter: String,
+    /// Has to start with `{start}`, can also have `{activity}` (left out when there's no activity),
+    /// `{activity_index}` and `{part}`
+    pub file_name_template: String,
+    pub rotate_on_activity_change: bool,
+    /// 0 to disable
+    pub rotate_after_idle_mins: u32,
+    pub rotate_at_midnight: bool,
+    /// 0 to disable
+    pub rotate_at_size_kb: u64,
+    /// Set by `--csv-stdout`, rows go to stdout instead of a file
+    #[serde(skip)]
+    pub to_stdout: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Timestamp,
+    #[serde(rename = "BPM")]
+    Bpm,
+    #[serde(rename = "RR")]
+    Rr,
+    Artifacts,
+    Battery,
+    TwitchUp,
+    TwitchDown,
+    Activity,
+    ActivityName,
+    Zone,
+    #[serde(rename = "RMSSD")]
+    Rmssd,
+    #[serde(rename = "SDNN")]
+    Sdnn,
+    #[serde(rename = "pNN50")]
+    Pnn50,
+    StressIndex,
+    #[serde(rename = "LF")]
+    Lf,
+    #[serde(rename = "HF")]
+    Hf,
+    #[serde(rename = "LFHF")]
+    LfHf,
+    BreathsPerMin,
+}
+
+impl CsvColumn {
+    pub const ALL: [CsvColumn; 18] = [
+        CsvColumn::Timestamp,
+        CsvColumn::Bpm,
+        CsvColumn::Rr,
+        CsvColumn::Artifacts,
+        CsvColumn::Battery,
+        CsvColumn::TwitchUp,
+        CsvColumn::TwitchDown,
+        CsvColumn::Activity,
+        CsvColumn::ActivityName,
+        CsvColumn::Zone,
+        CsvColumn::Rmssd,
+        CsvColumn::Sdnn,
+        CsvColumn::Pnn50,
+        CsvColumn::StressIndex,
+        CsvColumn::Lf,
+        CsvColumn::Hf,
+        CsvColumn::LfHf,
+        CsvColumn::BreathsPerMin,
+    ];
+    /// Same as the name used in the config
+    pub fn header(&self) -> &'static str {
+        match self {
+            CsvColumn::Timestamp => "Timestamp",
+            CsvColumn::Bpm => "BPM",
+            CsvColumn::Rr => "RR",
+            CsvColumn::Artifacts => "Artifacts",
+            CsvColumn::Battery => "Battery",
+            CsvColumn::TwitchUp => "TwitchUp",
+            CsvColumn::TwitchDown => "TwitchDown",
+            CsvColumn::Activity => "Activity",
+            CsvColumn::ActivityName => "ActivityName",
+            CsvColumn::Zone => "Zone",
+            CsvColumn::Rmssd => "RMSSD",
+            CsvColumn::Sdnn => "SDNN",
+            CsvColumn::Pnn50 => "pNN50",
+            CsvColumn::StressIndex => "StressIndex",
+            CsvColumn::Lf => "LF",
+            CsvColumn::Hf => "HF",
+            CsvColumn::LfHf => "LFHF",
+            CsvColumn::BreathsPerMin => "BreathsPerMin",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvTimestampFormat {
+    /// Local time to the second, like `2024-05-01 10:00:00`
+    #[default]
+    Local,
+    /// Local time with milliseconds and the UTC offset, like `2024-05-01T10:00:00.250+02:00`
+    Iso8601,
+    /// Milliseconds since the Unix epoch
+    UnixMs,
+    /// Seconds since the first row
+    ElapsedSecs,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct TuiSettings {
     pub session_stats_use_12hr: bool,
@@ -35,7 +173,6 @@ pub struct BLESettings {
     pub never_ask_to_save: bool,
     pub saved_name: String,
     pub saved_address: String,
-    pub rr_ignore_after_empty: u16,
     pub packet_timeout_secs: u8,
 }
 
@@ -50,11 +187,22 @@ pub struct OscSettings {
     pub target_ip: String,
     pub port: u16,
     pub pulse_length_ms: u16,
-    pub only_positive_float_bpm: bool,
     pub hide_disconnections: bool,
     pub max_hide_disconnection_sec: u16,
+    pub mimic_strategy: MimicStrategy,
     pub twitch_rr_threshold_ms: u16,
+    pub twitch_mode: TwitchMode,
+    pub twitch_length_ms: u16,
+    pub twitch_cooldown_ms: u16,
+    pub only_send_changes: bool,
+    pub min_send_interval_ms: u16,
+    pub resync_interval_sec: u16,
     pub addresses: OscAddrConf,
+    #[serde(default = "default_osc_mappings")]
+    pub mappings: Vec<OscMapping>,
+    // Replaced by `mappings`, only read to migrate older configs
+    #[serde(default, skip_serializing)]
+    only_positive_float_bpm: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -62,17 +210,232 @@ pub struct OscAddrConf {
     pub prefix: String,
     pub hrm_connected: String,
     pub hiding_disconnect: String,
-    pub hrm_battery_int: String,
-    pub hrm_battery_float: String,
     pub beat_toggle: String,
     pub beat_pulse: String,
-    pub bpm_int: String,
-    pub bpm_float: String,
-    pub latest_rr_int: String,
     pub rr_twitch_up: String,
     pub rr_twitch_down: String,
     pub activity: String,
-    // TODO Session Max/Min/Avg Params?
+
+    // Replaced by `osc.mappings`, only read to migrate older configs
+    #[serde(default, skip_serializing)]
+    hrm_battery_int: Option<String>,
+    #[serde(default, skip_serializing)]
+    hrm_battery_float: Option<String>,
+    #[serde(default, skip_serializing)]
+    bpm_int: Option<String>,
+    #[serde(default, skip_serializing)]
+    bpm_float: Option<String>,
+    #[serde(default, skip_serializing)]
+    latest_rr_int: Option<String>,
+}
+
+/// What gets sent in place of real data while `hide_disconnections` is masking a disconnection
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MimicStrategy {
+    /// The last BPM with a few BPM of random noise, and the odd random twitch
+    #[default]
+    Jitter,
+    /// The last BPM and RR interval, unchanged
+    Hold,
+    /// Carries on with the recent trend, then settles towards `zones.resting_bpm`
+    Trend,
+    /// Loops the last minute of real data
+    Replay,
+}
+
+/// How the twitch params react when the RR interval jumps past the threshold
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TwitchMode {
+    /// True for `twitch_length_ms`, extra twitches in the meantime are ignored
+    #[default]
+    Pulse,
+    /// Flips on every twitch
+    Toggle,
+    /// True until `twitch_length_ms` after the most recent twitch
+    Hold,
+}
+
+/// A single OSC parameter whose value is derived from the latest heart rate data.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct OscMapping {
+    /// Appended to `osc.addresses.prefix`
+    pub address: String,
+    pub source: MappingSource,
+    pub osc_type: MappingType,
+    #[serde(default)]
+    pub transform: MappingTransform,
+    /// Weight given to the previous value, from 0.0 (no smoothing) up to (but not including) 1.0
+    #[serde(default)]
+    pub smoothing: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingSource {
+    Bpm,
+    /// Latest RR Interval, in milliseconds
+    Rr,
+    Battery,
+    /// Current HR zone, see [zones]
+    Zone,
+    /// Lowest/Highest BPM this session
+    SessionMinBpm,
+    SessionMaxBpm,
+    /// Average BPM over the last `stats.rolling_average_secs`
+    RollingAvgBpm,
+    /// Current BPM compared to `zones.max_bpm` (1.0 = 100%)
+    PercentOfMax,
+    /// Whole minutes spent in the current HR zone this session
+    MinutesInZone,
+    /// Session total in kcal, only sent once it's known
+    Calories,
+    /// Banister's training impulse, session total
+    Trimp,
+    /// RMSSD in milliseconds, see [hrv]
+    Rmssd,
+    /// SDNN in milliseconds
+    Sdnn,
+    /// Percentage (0 - 100) of successive RR intervals differing by over 50ms
+    Pnn50,
+    /// Baevsky's stress index, higher is more stressed
+    StressIndex,
+    /// Only sent once a full `hrv.frequency_window_secs` of beats is in
+    LfHfRatio,
+    /// Estimated breaths per minute, only sent when there's a clear rhythm
+    RespirationRate,
+    /// OSC messages sent/skipped as unchanged/replaced while rate limited this session,
+    /// sent with each resync
+    OscSent,
+    OscUnchanged,
+    OscRateLimited,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingType {
+    Int,
+    Float,
+    Bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(tag = "curve", rename_all = "snake_case")]
+pub enum MappingTransform {
+    /// Source value is sent as-is
+    #[default]
+    None,
+    /// Maps `in_min..=in_max` onto `out_min..=out_max`, clamping at the edges
+    Linear {
+        in_min: f32,
+        in_max: f32,
+        out_min: f32,
+        out_max: f32,
+    },
+    /// Like `Linear`, but changes near `in_min` have a larger effect on the output
+    Log {
+        in_min: f32,
+        in_max: f32,
+        out_min: f32,
+        out_max: f32,
+    },
+    /// Outputs how many thresholds the source value has reached (e.g. for zone-based ints)
+    Thresholds { thresholds: Vec<f32> },
+}
+
+impl OscMapping {
+    fn new(address: &str, source: MappingSource, osc_type: MappingType) -> Self {
+        Self {
+            address: address.to_owned(),
+            source,
+            osc_type,
+            transform: MappingTransform::None,
+            smoothing: 0.0,
+        }
+    }
+    fn with_linear(mut self, in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> Self {
+        self.transform = MappingTransform::Linear {
+            in_min,
+            in_max,
+            out_min,
+            out_max,
+        };
+        self
+    }
+}
+
+// Matches the parameters that were sent before mappings were configurable
+fn default_osc_mappings() -> Vec<OscMapping> {
+    use MappingSource::*;
+    use MappingType::*;
+    vec![
+        OscMapping::new("HR", Bpm, Int),
+        OscMapping::new("floatHR", Bpm, Float).with_linear(0.0, 255.0, -1.0, 1.0),
+        OscMapping::new("RRInterval", Rr, Int),
+        OscMapping::new("HRBattery", Battery, Int),
+        OscMapping::new("HRBatteryFloat", Battery, Float).with_linear(0.0, 100.0, 0.0, 1.0),
+    ]
+}
+
+impl OscSettings {
+    /// Carries over parameter names and the float BPM range from configs made before `mappings` existed
+    fn migrate_legacy_addresses(&mut self) {
+        let legacy = [
+            (
+                self.addresses.bpm_int.take(),
+                "HR",
+                MappingSource::Bpm,
+                MappingType::Int,
+            ),
+            (
+                self.addresses.bpm_float.take(),
+                "floatHR",
+                MappingSource::Bpm,
+                MappingType::Float,
+            ),
+            (
+                self.addresses.latest_rr_int.take(),
+                "RRInterval",
+                MappingSource::Rr,
+                MappingType::Int,
+            ),
+            (
+                self.addresses.hrm_battery_int.take(),
+                "HRBattery",
+                MappingSource::Battery,
+                MappingType::Int,
+            ),
+            (
+                self.addresses.hrm_battery_float.take(),
+                "HRBatteryFloat",
+                MappingSource::Battery,
+                MappingType::Float,
+            ),
+        ];
+        for (old_address, default_address, source, osc_type) in legacy {
+            let Some(old_address) = old_address else {
+                continue;
+            };
+            if let Some(mapping) = self.mappings.iter_mut().find(|m| {
+                m.address == default_address && m.source == source && m.osc_type == osc_type
+            }) {
+                info!("Migrating OSC address \"{old_address}\" to mappings");
+                mapping.address = old_address;
+            }
+        }
+        if let Some(true) = self.only_positive_float_bpm.take() {
+            for mapping in self
+                .mappings
+                .iter_mut()
+                .filter(|m| m.source == MappingSource::Bpm && m.osc_type == MappingType::Float)
+            {
+                if let MappingTransform::Linear { out_min, .. } = &mut mapping.transform {
+                    *out_min = 0.0;
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -104,11 +467,85 @@ pub struct PrometheusSettings {
     pub enabled: bool,
     pub url: String,
     pub header: String,
-    // Unused for now, maybe someone'll ask for it.
-    // pub batch_size: usize,
+    /// Serve the metrics at `/metrics` for Prometheus to scrape
+    pub scrape_enabled: bool,
+    pub scrape_addr: String,
+    pub protocol: PrometheusProtocol,
+    /// Updates sent per POST. Batched text needs an endpoint that takes timestamped samples,
+    /// Pushgateway doesn't
+    pub batch_size: usize,
+    /// Batches kept around while the server can't be reached, oldest are dropped first
+    pub retry_queue_size: usize,
     pub metrics: PrometheusMetrics,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PrometheusProtocol {
+    /// Text Exposition format, for Pushgateway, VictoriaMetrics' import, etc.
+    #[default]
+    Text,
+    /// Protobuf + snappy, for anything that takes Prometheus' remote-write
+    RemoteWrite,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct InfluxSettings {
+    pub enabled: bool,
+    pub url: String,
+    /// `"Key: Value"` pairs
+    pub headers: Vec<String>,
+    pub measurement: String,
+    /// Updates sent per POST
+    pub batch_size: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: String,
+    pub password: String,
+    /// Everything's published under `<topic_prefix>/`
+    pub topic_prefix: String,
+    /// 0, 1 or 2, for the sensor values
+    pub qos: u8,
+    pub keep_alive_secs: u16,
+    /// Home Assistant MQTT discovery
+    pub discovery: bool,
+    pub discovery_prefix: String,
+}
+
+const DEFAULT_WEBHOOK_BODY: &str = r#"{"bpm": {bpm}, "rr_ms": {rr_ms}, "zone": {zone}, "activity": {activity}, "device": {device}, "timestamp": {timestamp}}"#;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct WebhookSettings {
+    pub enabled: bool,
+    pub url: String,
+    /// `"Key: Value"` pairs
+    pub headers: Vec<String>,
+    /// JSON with `{placeholders}` for the latest values
+    pub body: String,
+    /// Minimum time between POSTs, 0 sends every update
+    pub interval_secs: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SqliteSettings {
+    pub enabled: bool,
+    pub path: String,
+    /// Every RR interval with its beat time, not just one per update
+    pub log_rr_beats: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ParquetSettings {
+    pub enabled: bool,
+    pub row_group_rows: usize,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct PrometheusMetrics {
     pub bpm: String,
@@ -117,6 +554,96 @@ pub struct PrometheusMetrics {
     pub twitch_up: String,
     pub twitch_down: String,
     pub activity: String,
+    pub zone: String,
+    pub hrv_rmssd: String,
+    pub hrv_sdnn: String,
+    pub hrv_pnn50: String,
+    pub hrv_stress_index: String,
+    pub hrv_lf_power: String,
+    pub hrv_hf_power: String,
+    pub hrv_lf_hf_ratio: String,
+    pub calories: String,
+    pub trimp: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct StatsSettings {
+    pub rolling_average_secs: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ArtifactSettings {
+    pub method: ArtifactMethod,
+    /// How far (in percent) an RR interval can stray from the reference, for `Malik` and `Median`
+    pub threshold_percent: u8,
+    /// Allowed difference at 60 BPM, for `Kubios`
+    pub kubios_threshold_ms: u16,
+    /// Replace artifacts with an estimate instead of just flagging them
+    pub correct: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactMethod {
+    /// Don't check for artifacts
+    None,
+    /// Compared against the previous beat
+    #[default]
+    Malik,
+    /// Compared against the recent average, with a threshold that scales with heart rate
+    Kubios,
+    /// Compared against the recent median
+    Median,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct HrvSettings {
+    pub window_secs: u16,
+    /// Window for LF/HF, clamped to 120 - 300
+    pub frequency_window_secs: u16,
+    /// Window for the breathing rate estimate, clamped to 30 - 120
+    pub respiration_window_secs: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ProfileSettings {
+    /// Used for calorie estimates, and to estimate `zones.max_bpm` when it's 0
+    pub age: u8,
+    pub weight_kg: f32,
+    pub sex: Sex,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Sex {
+    /// Formulas use the average of the male and female variants
+    #[default]
+    Unspecified,
+    Male,
+    Female,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ZoneSettings {
+    pub method: ZoneMethod,
+    pub max_bpm: u16,
+    pub resting_bpm: u16,
+    /// Percentages (of `max_bpm` or the heart rate reserve) that start each zone
+    pub percentages: Vec<u8>,
+    /// BPMs that start each zone, for `ZoneMethod::Custom`
+    pub custom_thresholds: Vec<u16>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneMethod {
+    /// Zones start at a percentage of max BPM
+    #[default]
+    PercentMax,
+    /// Zones start at a percentage of the heart rate reserve (max - resting) above resting
+    Karvonen,
+    /// Zones start at the BPMs in `custom_thresholds`
+    Custom,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -132,11 +659,22 @@ pub struct Settings {
     pub ble: BLESettings,
     pub websocket: WebSocketSettings,
     pub misc: MiscSettings,
+    pub csv: CsvSettings,
     pub dummy: DummySettings,
     pub tui: TuiSettings,
     pub updates: AutoUpdateSettings,
     pub activities: ActivitiesSettings,
     pub prometheus: PrometheusSettings,
+    pub influx: InfluxSettings,
+    pub webhook: WebhookSettings,
+    pub mqtt: MqttSettings,
+    pub sqlite: SqliteSettings,
+    pub parquet: ParquetSettings,
+    pub stats: StatsSettings,
+    pub zones: ZoneSettings,
+    pub hrv: HrvSettings,
+    pub artifacts: ArtifactSettings,
+    pub profile: ProfileSettings,
 }
 
 impl Settings {
@@ -162,7 +700,7 @@ impl Settings {
         // TODO: New way of doing defaults
         // Either use serde's defaults and skip the extra config crate entirely (doesn't look like it supports serde defaults?)
         // or switch to something more sane like figment or confique
-        let settings = Config::builder()
+        let mut settings: Settings = Config::builder()
             // Start off by merging in the "default" configuration file
             .add_source(ConfigFile::from(config_path).required(required))
             .set_default("osc.enabled", true)?
@@ -170,27 +708,27 @@ impl Settings {
             .set_default("osc.target_ip", "127.0.0.1")?
             .set_default("osc.port", 9000)?
             .set_default("osc.pulse_length_ms", 100)?
-            .set_default("osc.only_positive_float_bpm", false)?
             .set_default("osc.hide_disconnections", false)?
             .set_default("osc.max_hide_disconnection_sec", 60)?
+            .set_default("osc.mimic_strategy", "jitter")?
             .set_default("osc.twitch_rr_threshold_ms", 50)?
+            .set_default("osc.twitch_mode", "pulse")?
+            .set_default("osc.twitch_length_ms", 250)?
+            .set_default("osc.twitch_cooldown_ms", 0)?
+            .set_default("osc.only_send_changes", true)?
+            .set_default("osc.min_send_interval_ms", 0)?
+            .set_default("osc.resync_interval_sec", 10)?
             .set_default("osc.addresses.prefix", "/avatar/parameters/")?
             .set_default("osc.addresses.hrm_connected", "isHRConnected")?
             .set_default("osc.addresses.hiding_disconnect", "isHRReconnecting")?
-            .set_default("osc.addresses.hrm_battery_int", "HRBattery")?
-            .set_default("osc.addresses.hrm_battery_float", "HRBatteryFloat")?
             .set_default("osc.addresses.beat_toggle", "HeartBeatToggle")?
             .set_default("osc.addresses.beat_pulse", "isHRBeat")?
-            .set_default("osc.addresses.bpm_int", "HR")?
-            .set_default("osc.addresses.bpm_float", "floatHR")?
-            .set_default("osc.addresses.latest_rr_int", "RRInterval")?
             .set_default("osc.addresses.rr_twitch_up", "HRTwitchUp")?
             .set_default("osc.addresses.rr_twitch_down", "HRTwitchDown")?
             .set_default("osc.addresses.activity", "HRActivity")?
             .set_default("ble.never_ask_to_save", false)?
             .set_default("ble.saved_address", "")?
             .set_default("ble.saved_name", "")?
-            .set_default("ble.rr_ignore_after_empty", 0)?
             .set_default("ble.packet_timeout_secs", 30)?
             .set_default("websocket.enabled", false)?
             .set_default("websocket.port", 5566)?
@@ -201,7 +739,24 @@ impl Settings {
             .set_default("misc.bpm_file_path", default_bpm_txt_path)?
             .set_default("misc.log_sessions_to_csv", false)?
             .set_default("misc.log_sessions_csv_path", default_session_log_path)?
+            .set_default("misc.log_rr_series", false)?
+            .set_default("misc.write_session_summary", true)?
+            .set_default("misc.export_formats", Vec::<String>::new())?
             .set_default("misc.vrcx_shortcut_prompt", true)?
+            .set_default(
+                "csv.columns",
+                CsvColumn::ALL
+                    .iter()
+                    .map(CsvColumn::header)
+                    .collect::<Vec<_>>(),
+            )?
+            .set_default("csv.timestamp_format", "local")?
+            .set_default("csv.delimiter", ",")?
+            .set_default("csv.file_name_template", "{start}_{activity}")?
+            .set_default("csv.rotate_on_activity_change", false)?
+            .set_default("csv.rotate_after_idle_mins", 0)?
+            .set_default("csv.rotate_at_midnight", false)?
+            .set_default("csv.rotate_at_size_kb", 0)?
             .set_default("updates.update_check_prompt", true)?
             .set_default("updates.allow_checking_for_updates", false)?
             .set_default("updates.version_skipped", "")?
@@ -219,19 +774,86 @@ impl Settings {
             .set_default("dummy.loops_before_dc", 2)?
             .set_default("activities.enabled", false)?
             .set_default("activities.remember_last", true)?
+            .set_default("sqlite.enabled", false)?
+            .set_default("sqlite.path", "session_logs/iron-heart.sqlite3")?
+            .set_default("sqlite.log_rr_beats", true)?
+            .set_default("parquet.enabled", false)?
+            .set_default("parquet.row_group_rows", 3600)?
             .set_default("prometheus.enabled", false)?
             .set_default("prometheus.url", "localhost:9000")?
             .set_default("prometheus.header", "")?
+            .set_default("prometheus.scrape_enabled", false)?
+            .set_default("prometheus.scrape_addr", "127.0.0.1:9898")?
             .set_default("prometheus.metrics.bpm", "heart_rate_bpm")?
             .set_default("prometheus.metrics.rr", "heart_rate_rr")?
             .set_default("prometheus.metrics.battery", "heart_rate_battery")?
             .set_default("prometheus.metrics.twitch_up", "heart_rate_twitch_up")?
             .set_default("prometheus.metrics.twitch_down", "heart_rate_twitch_down")?
             .set_default("prometheus.metrics.activity", "heart_rate_activity")?
-            // .set_default("prometheus.batch_size", 30)?
+            .set_default("prometheus.metrics.zone", "heart_rate_zone")?
+            .set_default("prometheus.metrics.hrv_rmssd", "heart_rate_hrv_rmssd")?
+            .set_default("prometheus.metrics.hrv_sdnn", "heart_rate_hrv_sdnn")?
+            .set_default("prometheus.metrics.hrv_pnn50", "heart_rate_hrv_pnn50")?
+            .set_default(
+                "prometheus.metrics.hrv_stress_index",
+                "heart_rate_hrv_stress_index",
+            )?
+            .set_default("prometheus.metrics.hrv_lf_power", "heart_rate_hrv_lf_power")?
+            .set_default("prometheus.metrics.hrv_hf_power", "heart_rate_hrv_hf_power")?
+            .set_default(
+                "prometheus.metrics.hrv_lf_hf_ratio",
+                "heart_rate_hrv_lf_hf_ratio",
+            )?
+            .set_default("prometheus.metrics.calories", "heart_rate_calories")?
+            .set_default("prometheus.metrics.trimp", "heart_rate_trimp")?
+            .set_default("prometheus.protocol", "text")?
+            .set_default("prometheus.batch_size", 1)?
+            .set_default("prometheus.retry_queue_size", 120)?
+            .set_default("influx.enabled", false)?
+            .set_default(
+                "influx.url",
+                "localhost:8086/api/v2/write?org=iron-heart&bucket=iron-heart",
+            )?
+            .set_default("influx.headers", Vec::<String>::new())?
+            .set_default("influx.measurement", "heart_rate")?
+            .set_default("influx.batch_size", 10)?
+            .set_default("webhook.enabled", false)?
+            .set_default("webhook.url", "localhost:8123/api/webhook/iron-heart")?
+            .set_default("webhook.headers", Vec::<String>::new())?
+            .set_default("webhook.body", DEFAULT_WEBHOOK_BODY)?
+            .set_default("webhook.interval_secs", 5.0)?
+            .set_default("mqtt.enabled", false)?
+            .set_default("mqtt.host", "localhost")?
+            .set_default("mqtt.port", 1883)?
+            .set_default("mqtt.client_id", "iron-heart")?
+            .set_default("mqtt.username", "")?
+            .set_default("mqtt.password", "")?
+            .set_default("mqtt.topic_prefix", "iron-heart")?
+            .set_default("mqtt.qos", 0)?
+            .set_default("mqtt.keep_alive_secs", 30)?
+            .set_default("mqtt.discovery", true)?
+            .set_default("mqtt.discovery_prefix", "homeassistant")?
+            .set_default("stats.rolling_average_secs", 60)?
+            .set_default("hrv.window_secs", 60)?
+            .set_default("artifacts.method", "malik")?
+            .set_default("artifacts.threshold_percent", 20)?
+            .set_default("artifacts.kubios_threshold_ms", 250)?
+            .set_default("artifacts.correct", true)?
+            .set_default("hrv.frequency_window_secs", 180)?
+            .set_default("hrv.respiration_window_secs", 60)?
+            .set_default("profile.age", 0)?
+            .set_default("profile.weight_kg", 0.0)?
+            .set_default("profile.sex", "unspecified")?
+            .set_default("zones.method", "percent_max")?
+            .set_default("zones.max_bpm", 190)?
+            .set_default("zones.resting_bpm", 60)?
+            .set_default("zones.percentages", vec![50, 60, 70, 80, 90])?
+            .set_default("zones.custom_thresholds", Vec::<u16>::new())?
             .build()?
             .try_deserialize()?;
 
+        settings.osc.migrate_legacy_addresses();
+
         Ok(settings)
     }
 
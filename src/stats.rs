@@ -0,0 +1,350 @@
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{Receiver as BReceiver, Sender as BSender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::app::{AppUpdate, ErrorPopup};
+use crate::broadcast;
+use crate::errors::AppError;
+use crate::heart_rate::HeartRateStatus;
+use crate::settings::{ProfileSettings, StatsSettings};
+use crate::training::{kcal_per_minute, trimp_per_minute, EnergyCounter};
+use crate::zones::HrZones;
+
+// Gaps longer than this between updates aren't counted towards any zone
+const MAX_ZONE_GAP: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStats {
+    pub min_bpm: (u16, DateTime<Local>),
+    pub max_bpm: (u16, DateTime<Local>),
+    pub average_bpm: f32,
+    pub rolling_average_bpm: f32,
+    /// Latest BPM as a fraction of the configured max BPM (1.0 = 100%)
+    pub percent_of_max: f32,
+    pub zone: u8,
+    /// Indexed by zone
+    pub time_in_zones: Vec<Duration>,
+    /// Estimated from the profile until the monitor reports energy expended,
+    /// after which the monitor's readings are added on top
+    pub calories: Option<f32>,
+    /// Banister's training impulse
+    pub trimp: f32,
+}
+
+impl SessionStats {
+    pub fn time_in_current_zone(&self) -> Duration {
+        self.time_in_zones
+            .get(self.zone as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Keeps track of the session's stats without needing the TUI
+pub struct StatsEngine {
+    zones: HrZones,
+    profile: ProfileSettings,
+    rolling_window: chrono::Duration,
+    recent: VecDeque<(DateTime<Local>, u16)>,
+    bpm_sum: u64,
+    bpm_count: u64,
+    // Timestamp, zone and BPM of the previous update, None after a disconnection
+    last_update: Option<(DateTime<Local>, u8, u16)>,
+    monitor_energy: EnergyCounter,
+    estimated_kcal: Option<f32>,
+    stats: SessionStats,
+}
+
+impl StatsEngine {
+    pub fn new(stats_settings: &StatsSettings, profile: &ProfileSettings, zones: HrZones) -> Self {
+        let rolling_window = chrono::Duration::seconds(stats_settings.rolling_average_secs as i64);
+        let stats = SessionStats {
+            time_in_zones: vec![Duration::ZERO; zones.count()],
+            ..Default::default()
+        };
+        Self {
+            zones,
+            profile: profile.clone(),
+            rolling_window,
+            recent: VecDeque::new(),
+            bpm_sum: 0,
+            bpm_count: 0,
+            last_update: None,
+            monitor_energy: EnergyCounter::default(),
+            estimated_kcal: None,
+            stats,
+        }
+    }
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+    /// Returns the updated stats, or None if nothing changed (disconnected)
+    pub fn update(&mut self, hr_status: &HeartRateStatus) -> Option<&SessionStats> {
+        let bpm = hr_status.heart_rate_bpm;
+        let timestamp = hr_status.timestamp;
+        if bpm == 0 {
+            self.last_update = None;
+            return None;
+        }
+
+        let stats = &mut self.stats;
+        if self.bpm_count == 0 || bpm < stats.min_bpm.0 {
+            stats.min_bpm = (bpm, timestamp);
+        }
+        if self.bpm_count == 0 || bpm > stats.max_bpm.0 {
+            stats.max_bpm = (bpm, timestamp);
+        }
+        self.bpm_sum += bpm as u64;
+        self.bpm_count += 1;
+        stats.average_bpm = self.bpm_sum as f32 / self.bpm_count as f32;
+
+        self.recent.push_back((timestamp, bpm));
+        while let Some((oldest, _)) = self.recent.front() {
+            if timestamp - *oldest > self.rolling_window {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+        stats.rolling_average_bpm =
+            self.recent.iter().map(|(_, bpm)| *bpm as f32).sum::<f32>() / self.recent.len() as f32;
+
+        stats.percent_of_max = bpm as f32 / self.zones.max_bpm() as f32;
+
+        // Time since the last update goes to the zone (and heart rate) we were at until now
+        if let Some((last_timestamp, last_zone, last_bpm)) = self.last_update {
+            if let Ok(elapsed) = (timestamp - last_timestamp).to_std() {
+                if elapsed <= MAX_ZONE_GAP {
+                    stats.time_in_zones[last_zone as usize] += elapsed;
+
+                    let minutes = elapsed.as_secs_f32() / 60.0;
+                    let reserve = self.zones.reserve_fraction(last_bpm);
+                    stats.trimp += minutes * trimp_per_minute(reserve, self.profile.sex);
+                    // Monitor's readings take over once it starts reporting them
+                    if self.monitor_energy.kcal().is_none() {
+                        if let Some(kcal) = kcal_per_minute(last_bpm, &self.profile) {
+                            *self.estimated_kcal.get_or_insert(0.0) += minutes * kcal;
+                        }
+                    }
+                }
+            }
+        }
+        stats.zone = self.zones.zone_of(bpm);
+        self.last_update = Some((timestamp, stats.zone, bpm));
+
+        if let Some(energy_expended) = hr_status.energy_expended {
+            self.monitor_energy.update(energy_expended);
+        }
+        stats.calories = match (self.estimated_kcal, self.monitor_energy.kcal()) {
+            (Some(estimated), Some(monitor)) => Some(estimated + monitor),
+            (estimated, monitor) => estimated.or(monitor),
+        };
+
+        Some(&self.stats)
+    }
+}
+
+struct StatsActor {
+    engine: StatsEngine,
+    broadcast_tx: BSender<AppUpdate>,
+}
+
+impl StatsActor {
+    fn log_summary(&self) {
+        let stats = self.engine.stats();
+        for (zone, time) in stats.time_in_zones.iter().enumerate() {
+            let secs = time.as_secs();
+            info!("Time in Zone {zone}: {}m {}s", secs / 60, secs % 60);
+        }
+        if let Some(calories) = stats.calories {
+            info!("Calories: {calories:.0} kcal");
+        }
+        info!("TRIMP: {:.1}", stats.trimp);
+    }
+    async fn rx_loop(
+        &mut self,
+        mut broadcast_rx: BReceiver<AppUpdate>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), AppError> {
+        loop {
+            tokio::select! {
+                hr_data = broadcast_rx.recv() => {
+                    match hr_data {
+                        Ok(AppUpdate::HeartRateStatus(data)) => {
+                            if let Some(stats) = self.engine.update(&data) {
+                                broadcast!(self.broadcast_tx, AppUpdate::SessionStats(stats.clone()));
+                            }
+                        },
+                        Ok(_) => {},
+                        Err(RecvError::Closed) => {
+                            error!("Stats: Channel closed");
+                            break;
+                        },
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Stats: Lagged! Missed {count} messages");
+                        }
+                    }
+                }
+                _ = cancel_token.cancelled() => {
+                    info!("Shutting down Stats thread!");
+                    self.log_summary();
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn stats_thread(
+    broadcast_rx: BReceiver<AppUpdate>,
+    broadcast_tx: BSender<AppUpdate>,
+    stats_settings: StatsSettings,
+    profile: ProfileSettings,
+    zones: HrZones,
+    cancel_token: CancellationToken,
+) {
+    let mut stats = StatsActor {
+        engine: StatsEngine::new(&stats_settings, &profile, zones),
+        broadcast_tx: broadcast_tx.clone(),
+    };
+
+    if let Err(e) = stats.rx_loop(broadcast_rx, cancel_token).await {
+        error!("Stats Error: {e}");
+        let message = "Stats Error";
+        broadcast!(broadcast_tx, ErrorPopup::detailed(message, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Sex, ZoneMethod, ZoneSettings};
+
+    fn engine(rolling_average_secs: u16) -> StatsEngine {
+        engine_with_profile(rolling_average_secs, ProfileSettings::default())
+    }
+
+    fn engine_with_profile(rolling_average_secs: u16, profile: ProfileSettings) -> StatsEngine {
+        let stats_settings = StatsSettings {
+            rolling_average_secs,
+        };
+        let zone_settings = ZoneSettings {
+            method: ZoneMethod::PercentMax,
+            max_bpm: 200,
+            resting_bpm: 60,
+            percentages: vec![50, 60, 70, 80, 90],
+            custom_thresholds: vec![],
+        };
+        let zones = HrZones::build(&zone_settings, profile.age).unwrap();
+        StatsEngine::new(&stats_settings, &profile, zones)
+    }
+
+    fn status(bpm: u16, start: DateTime<Local>, secs: i64) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            timestamp: start + chrono::Duration::seconds(secs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn min_max_average() {
+        let start = Local::now();
+        let mut engine = engine(60);
+        for (secs, bpm) in [(0, 80), (1, 120), (2, 70), (3, 90)] {
+            engine.update(&status(bpm, start, secs));
+        }
+        let stats = engine.update(&status(100, start, 4)).unwrap();
+        assert_eq!(stats.min_bpm, (70, start + chrono::Duration::seconds(2)));
+        assert_eq!(stats.max_bpm, (120, start + chrono::Duration::seconds(1)));
+        assert_eq!(stats.average_bpm, 92.0);
+        assert_eq!(stats.percent_of_max, 0.5);
+    }
+
+    #[test]
+    fn rolling_average_drops_old() {
+        let start = Local::now();
+        let mut engine = engine(10);
+        engine.update(&status(200, start, 0));
+        engine.update(&status(100, start, 5));
+        let stats = engine.update(&status(60, start, 15)).unwrap();
+        assert_eq!(stats.rolling_average_bpm, 80.0);
+        assert_eq!(stats.average_bpm, 120.0);
+    }
+
+    #[test]
+    fn time_in_zone_skips_disconnects() {
+        let start = Local::now();
+        let mut engine = engine(60);
+        // Zone 1 (100..120) for 2 seconds
+        engine.update(&status(110, start, 0));
+        engine.update(&status(115, start, 1));
+        // Zone 3 (140..160) from here on
+        engine.update(&status(150, start, 2));
+        assert!(engine.update(&status(0, start, 3)).is_none());
+        // Disconnected time isn't counted
+        engine.update(&status(150, start, 4));
+        let stats = engine.update(&status(155, start, 5)).unwrap();
+        assert_eq!(stats.zone, 3);
+        assert_eq!(stats.time_in_zones[1], Duration::from_secs(2));
+        assert_eq!(stats.time_in_current_zone(), Duration::from_secs(1));
+        assert_eq!(
+            stats.time_in_zones.iter().sum::<Duration>(),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn training_load() {
+        let start = Local::now();
+        let profile = ProfileSettings {
+            age: 30,
+            weight_kg: 70.0,
+            sex: Sex::Male,
+        };
+        let mut engine = engine_with_profile(60, profile.clone());
+        // A minute at 130 BPM, halfway into the (60 - 200) reserve
+        for secs in 0..=60 {
+            engine.update(&status(130, start, secs));
+        }
+        let stats = engine.stats();
+        let expected_trimp = trimp_per_minute(0.5, Sex::Male);
+        assert!((stats.trimp - expected_trimp).abs() < 0.001);
+        let expected_kcal = kcal_per_minute(130, &profile).unwrap();
+        assert!((stats.calories.unwrap() - expected_kcal).abs() < 0.001);
+    }
+
+    #[test]
+    fn monitor_energy_mid_session() {
+        let start = Local::now();
+        let profile = ProfileSettings {
+            age: 30,
+            weight_kg: 70.0,
+            sex: Sex::Male,
+        };
+        let mut engine = engine_with_profile(60, profile.clone());
+        for secs in 0..=60 {
+            engine.update(&status(130, start, secs));
+        }
+        let estimated = kcal_per_minute(130, &profile).unwrap();
+
+        // Monitor starts reporting a minute in, its first reading is just the starting point
+        let mut with_energy = status(130, start, 60);
+        with_energy.energy_expended = Some(1000);
+        let stats = engine.update(&with_energy).unwrap();
+        assert!((stats.calories.unwrap() - estimated).abs() < 0.001);
+
+        // Only the monitor's deltas are added from here on
+        with_energy = status(130, start, 61);
+        with_energy.energy_expended = Some(1021);
+        let stats = engine.update(&with_energy).unwrap();
+        assert!((stats.calories.unwrap() - (estimated + 21.0 / 4.184)).abs() < 0.001);
+        let stats = engine.update(&status(130, start, 62)).unwrap();
+        assert!((stats.calories.unwrap() - (estimated + 21.0 / 4.184)).abs() < 0.001);
+    }
+}
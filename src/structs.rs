@@ -3,6 +3,26 @@ use std::collections::HashMap;
 use btleplug::api::CharPropFlags;
 use uuid::Uuid;
 
+/// Where heart rate data is coming from, for loggers that keep track of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrSource {
+    /// "ble", "websocket" or "dummy"
+    pub kind: &'static str,
+    pub name: String,
+    pub address: String,
+}
+
+impl HrSource {
+    /// Name if there is one, otherwise the address
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.address
+        } else {
+            &self.name
+        }
+    }
+}
+
 /// A struct to hold the information of a Bluetooth device.
 #[derive(Debug, Clone, Default)]
 #[allow(dead_code)]
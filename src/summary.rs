@@ -0,0 +1,487 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{create_dir_all, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::errors::AppError;
+use crate::heart_rate::{BatteryLevel, HeartRateStatus};
+use crate::hrv::HrvMetrics;
+use crate::stats::SessionStats;
+
+// Gaps longer than this between updates aren't counted towards any activity
+const MAX_ACTIVITY_GAP: Duration = Duration::from_secs(5);
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BpmAt {
+    pub bpm: u16,
+    /// RFC 3339
+    pub at: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ActivityTime {
+    pub index: u8,
+    pub name: String,
+    pub secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct HrvSummary {
+    pub average_rmssd_ms: f32,
+    pub min_rmssd_ms: f32,
+    pub max_rmssd_ms: f32,
+    pub average_sdnn_ms: f32,
+    pub average_pnn50: f32,
+    pub average_stress_index: f32,
+    pub average_breaths_per_min: Option<f32>,
+    pub artifacts: u32,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatteryDrain {
+    pub start_percent: u8,
+    pub end_percent: u8,
+    pub percent_per_hour: f32,
+}
+
+/// Written next to the session's CSV when the app closes, and shown in the TUI
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SessionSummary {
+    /// RFC 3339
+    pub start: String,
+    /// RFC 3339
+    pub end: String,
+    pub duration_secs: u64,
+    pub min_bpm: BpmAt,
+    pub average_bpm: f32,
+    pub max_bpm: BpmAt,
+    /// Indexed by zone
+    pub time_in_zones_secs: Vec<u64>,
+    pub calories: Option<f32>,
+    pub trimp: f32,
+    pub activities: Vec<ActivityTime>,
+    pub hrv: Option<HrvSummary>,
+    pub disconnects: u32,
+    /// Time spent hiding disconnections from OSC
+    pub masked_secs: u64,
+    pub battery: Option<BatteryDrain>,
+}
+
+/// Follows the session's updates to build a [`SessionSummary`] once it's over
+#[derive(Debug, Default)]
+pub struct SessionRecorder {
+    hide_disconnections: bool,
+    max_hide_disconnection: Duration,
+    start: Option<DateTime<Local>>,
+    last_update: Option<DateTime<Local>>,
+    connected: bool,
+    activity: u8,
+    activity_time: BTreeMap<u8, Duration>,
+    disconnected_at: Option<DateTime<Local>>,
+    disconnects: u32,
+    masked: Duration,
+    first_battery: Option<u8>,
+    last_battery: Option<u8>,
+    hrv_updates: u32,
+    rmssd_sum: f32,
+    rmssd_range: Option<(f32, f32)>,
+    sdnn_sum: f32,
+    pnn50_sum: f32,
+    stress_index_sum: f32,
+    respiration_sum: f32,
+    respiration_updates: u32,
+    artifacts: u32,
+}
+
+impl SessionRecorder {
+    pub fn new(hide_disconnections: bool, max_hide_disconnection: Duration) -> Self {
+        Self {
+            hide_disconnections,
+            max_hide_disconnection,
+            ..Default::default()
+        }
+    }
+    pub fn record_activity(&mut self, index: u8) {
+        self.activity = index;
+    }
+    pub fn record_status(&mut self, hr_status: &HeartRateStatus) {
+        let timestamp = hr_status.timestamp;
+        if hr_status.heart_rate_bpm == 0 {
+            if self.connected {
+                self.connected = false;
+                self.disconnects += 1;
+                self.disconnected_at = Some(timestamp);
+            }
+            return;
+        }
+
+        self.start.get_or_insert(timestamp);
+        if let Some(disconnected_at) = self.disconnected_at.take() {
+            self.add_masked(disconnected_at, timestamp);
+        }
+        if let (true, Some(last)) = (self.connected, self.last_update) {
+            if let Ok(elapsed) = (timestamp - last).to_std() {
+                if elapsed <= MAX_ACTIVITY_GAP {
+                    *self.activity_time.entry(self.activity).or_default() += elapsed;
+                }
+            }
+        }
+        self.connected = true;
+        self.last_update = Some(timestamp);
+
+        if let BatteryLevel::Level(level) = hr_status.battery_level {
+            self.first_battery.get_or_insert(level);
+            self.last_battery = Some(level);
+        }
+    }
+    pub fn record_hrv(&mut self, hrv: &HrvMetrics) {
+        // Already a running total for the session
+        self.artifacts = self.artifacts.max(hrv.artifacts);
+        if hrv.beats == 0 {
+            return;
+        }
+        self.hrv_updates += 1;
+        self.rmssd_sum += hrv.rmssd_ms;
+        self.rmssd_range = Some(match self.rmssd_range {
+            Some((min, max)) => (min.min(hrv.rmssd_ms), max.max(hrv.rmssd_ms)),
+            None => (hrv.rmssd_ms, hrv.rmssd_ms),
+        });
+        self.sdnn_sum += hrv.sdnn_ms;
+        self.pnn50_sum += hrv.pnn50;
+        self.stress_index_sum += hrv.stress_index;
+        if let Some(rate) = hrv.respiration_rate {
+            self.respiration_sum += rate;
+            self.respiration_updates += 1;
+        }
+    }
+    /// None if we never got any heart rate data
+    pub fn finish(
+        &mut self,
+        stats: &SessionStats,
+        activity_names: &BTreeMap<u8, String>,
+        now: DateTime<Local>,
+    ) -> Option<SessionSummary> {
+        let start = self.start?;
+        let end = self.last_update.unwrap_or(now);
+        // Still hiding a disconnection when the app closed
+        if let Some(disconnected_at) = self.disconnected_at.take() {
+            self.add_masked(disconnected_at, now);
+        }
+        let duration = (end - start).to_std().unwrap_or_default();
+
+        let activities = self
+            .activity_time
+            .iter()
+            .map(|(index, time)| ActivityTime {
+                index: *index,
+                name: activity_names.get(index).cloned().unwrap_or_default(),
+                secs: time.as_secs(),
+            })
+            .collect();
+
+        let hrv = self.rmssd_range.map(|(min_rmssd_ms, max_rmssd_ms)| {
+            let updates = self.hrv_updates as f32;
+            HrvSummary {
+                average_rmssd_ms: self.rmssd_sum / updates,
+                min_rmssd_ms,
+                max_rmssd_ms,
+                average_sdnn_ms: self.sdnn_sum / updates,
+                average_pnn50: self.pnn50_sum / updates,
+                average_stress_index: self.stress_index_sum / updates,
+                average_breaths_per_min: (self.respiration_updates > 0)
+                    .then(|| self.respiration_sum / self.respiration_updates as f32),
+                artifacts: self.artifacts,
+            }
+        });
+
+        let battery =
+            self.first_battery
+                .zip(self.last_battery)
+                .map(|(start_percent, end_percent)| {
+                    let hours = duration.as_secs_f32() / 3600.0;
+                    let drained = start_percent.saturating_sub(end_percent) as f32;
+                    BatteryDrain {
+                        start_percent,
+                        end_percent,
+                        percent_per_hour: if hours > 0.0 { drained / hours } else { 0.0 },
+                    }
+                });
+
+        Some(SessionSummary {
+            start: start.to_rfc3339(),
+            end: end.to_rfc3339(),
+            duration_secs: duration.as_secs(),
+            min_bpm: BpmAt {
+                bpm: stats.min_bpm.0,
+                at: stats.min_bpm.1.to_rfc3339(),
+            },
+            average_bpm: stats.average_bpm,
+            max_bpm: BpmAt {
+                bpm: stats.max_bpm.0,
+                at: stats.max_bpm.1.to_rfc3339(),
+            },
+            time_in_zones_secs: stats.time_in_zones.iter().map(|t| t.as_secs()).collect(),
+            calories: stats.calories,
+            trimp: stats.trimp,
+            activities,
+            hrv,
+            disconnects: self.disconnects,
+            masked_secs: self.masked.as_secs(),
+            battery,
+        })
+    }
+    fn add_masked(&mut self, from: DateTime<Local>, to: DateTime<Local>) {
+        if !self.hide_disconnections {
+            return;
+        }
+        let disconnected = (to - from).to_std().unwrap_or_default();
+        self.masked += disconnected.min(self.max_hide_disconnection);
+    }
+}
+
+impl SessionSummary {
+    /// Label and value pairs, in the order they're shown
+    pub fn rows(&self) -> Vec<(String, String)> {
+        let mut rows = vec![
+            ("Started".into(), local_time(&self.start)),
+            ("Ended".into(), local_time(&self.end)),
+            ("Duration".into(), format_secs(self.duration_secs)),
+            (
+                "Min BPM".into(),
+                format!("{} at {}", self.min_bpm.bpm, local_time(&self.min_bpm.at)),
+            ),
+            ("Avg BPM".into(), format!("{:.1}", self.average_bpm)),
+            (
+                "Max BPM".into(),
+                format!("{} at {}", self.max_bpm.bpm, local_time(&self.max_bpm.at)),
+            ),
+        ];
+        for (zone, secs) in self.time_in_zones_secs.iter().enumerate() {
+            rows.push((format!("Zone {zone}"), format_secs(*secs)));
+        }
+        if let Some(calories) = self.calories {
+            rows.push(("Calories".into(), format!("{calories:.0} kcal")));
+        }
+        rows.push(("TRIMP".into(), format!("{:.1}", self.trimp)));
+        for activity in &self.activities {
+            rows.push((
+                format!("Activity: {}", activity.name),
+                format_secs(activity.secs),
+            ));
+        }
+        if let Some(hrv) = &self.hrv {
+            rows.push((
+                "RMSSD".into(),
+                format!(
+                    "{:.1}ms (range {:.1} - {:.1})",
+                    hrv.average_rmssd_ms, hrv.min_rmssd_ms, hrv.max_rmssd_ms
+                ),
+            ));
+            rows.push(("SDNN".into(), format!("{:.1}ms", hrv.average_sdnn_ms)));
+            rows.push(("pNN50".into(), format!("{:.1}%", hrv.average_pnn50)));
+            rows.push((
+                "Stress Index".into(),
+                format!("{:.1}", hrv.average_stress_index),
+            ));
+            if let Some(rate) = hrv.average_breaths_per_min {
+                rows.push(("Breathing".into(), format!("{rate:.1}/min")));
+            }
+            rows.push(("RR Artifacts".into(), hrv.artifacts.to_string()));
+        }
+        rows.push(("Disconnects".into(), self.disconnects.to_string()));
+        rows.push(("Masked Time".into(), format_secs(self.masked_secs)));
+        if let Some(battery) = &self.battery {
+            rows.push((
+                "Battery".into(),
+                format!(
+                    "{}% -> {}% ({:.1}%/hour)",
+                    battery.start_percent, battery.end_percent, battery.percent_per_hour
+                ),
+            ));
+        }
+        rows
+    }
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# Session Summary\n\n| | |\n|---|---|\n");
+        for (label, value) in self.rows() {
+            // Writing to a String can't fail
+            let _ = writeln!(markdown, "| {label} | {value} |");
+        }
+        markdown
+    }
+    /// Writes `<stem>.summary.md` and `<stem>.summary.json` into `folder`
+    pub async fn write_files(&self, folder: &Path, stem: &str) -> Result<PathBuf, AppError> {
+        if !folder.exists() {
+            create_dir_all(folder)
+                .await
+                .map_err(|e| AppError::CreateDir {
+                    path: folder.to_owned(),
+                    source: e,
+                })?;
+        }
+        let markdown_path = folder.join(format!("{stem}.summary.md"));
+        let json_path = folder.join(format!("{stem}.summary.json"));
+        write_file(&markdown_path, self.to_markdown().as_bytes()).await?;
+        write_file(&json_path, &serde_json::to_vec_pretty(self)?).await?;
+        Ok(markdown_path)
+    }
+}
+
+async fn write_file(path: &Path, contents: &[u8]) -> Result<(), AppError> {
+    let mut file = File::create(path).await.map_err(|e| AppError::CreateFile {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    file.write_all(contents)
+        .await
+        .map_err(|e| AppError::WriteFile {
+            path: path.to_owned(),
+            source: e,
+        })?;
+    file.flush().await.map_err(|e| AppError::WriteFile {
+        path: path.to_owned(),
+        source: e,
+    })
+}
+
+fn local_time(rfc3339: &str) -> String {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .map(|time| time.with_timezone(&Local).format(TIME_FORMAT).to_string())
+        .unwrap_or_else(|_| rfc3339.to_owned())
+}
+
+fn format_secs(secs: u64) -> String {
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn status(secs: i64, bpm: u16, battery: u8) -> HeartRateStatus {
+        HeartRateStatus {
+            heart_rate_bpm: bpm,
+            battery_level: BatteryLevel::Level(battery),
+            timestamp: at(secs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn activities_and_disconnects() {
+        let mut recorder = SessionRecorder::new(true, Duration::from_secs(10));
+        recorder.record_activity(1);
+        for secs in 0..=4 {
+            recorder.record_status(&status(secs, 80, 90));
+        }
+        recorder.record_activity(2);
+        for secs in 5..=6 {
+            recorder.record_status(&status(secs, 100, 90));
+        }
+        // 30 second disconnection, only 10 seconds of it can be masked
+        recorder.record_status(&status(7, 0, 90));
+        for secs in 37..=40 {
+            recorder.record_status(&status(secs, 90, 88));
+        }
+
+        let names = BTreeMap::from([(1, "Walking".to_string()), (2, "Running".to_string())]);
+        let summary = recorder
+            .finish(&SessionStats::default(), &names, at(41))
+            .unwrap();
+        assert_eq!(summary.duration_secs, 40);
+        assert_eq!(
+            summary.activities,
+            vec![
+                ActivityTime {
+                    index: 1,
+                    name: "Walking".into(),
+                    secs: 4
+                },
+                ActivityTime {
+                    index: 2,
+                    name: "Running".into(),
+                    secs: 5
+                },
+            ]
+        );
+        assert_eq!(summary.disconnects, 1);
+        assert_eq!(summary.masked_secs, 10);
+        let battery = summary.battery.unwrap();
+        assert_eq!((battery.start_percent, battery.end_percent), (90, 88));
+        assert!((battery.percent_per_hour - 180.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn still_disconnected_at_close() {
+        let mut recorder = SessionRecorder::new(true, Duration::from_secs(60));
+        recorder.record_status(&status(0, 80, 50));
+        recorder.record_status(&status(1, 0, 50));
+        let summary = recorder
+            .finish(&SessionStats::default(), &BTreeMap::new(), at(21))
+            .unwrap();
+        assert_eq!(summary.disconnects, 1);
+        assert_eq!(summary.masked_secs, 20);
+    }
+
+    #[test]
+    fn hrv_averages() {
+        let mut recorder = SessionRecorder::new(false, Duration::ZERO);
+        recorder.record_status(&status(0, 60, 100));
+        for (rmssd, rate, artifacts) in [(30.0, None, 1), (50.0, Some(12.0), 3)] {
+            recorder.record_hrv(&HrvMetrics {
+                rmssd_ms: rmssd,
+                beats: 20,
+                artifacts,
+                respiration_rate: rate,
+                ..Default::default()
+            });
+        }
+        // Waiting for beats, shouldn't drag the averages down
+        recorder.record_hrv(&HrvMetrics::default());
+        let summary = recorder
+            .finish(&SessionStats::default(), &BTreeMap::new(), at(1))
+            .unwrap();
+        let hrv = summary.hrv.unwrap();
+        assert_eq!(hrv.average_rmssd_ms, 40.0);
+        assert_eq!((hrv.min_rmssd_ms, hrv.max_rmssd_ms), (30.0, 50.0));
+        assert_eq!(hrv.average_breaths_per_min, Some(12.0));
+        assert_eq!(hrv.artifacts, 3);
+        assert_eq!(summary.masked_secs, 0);
+    }
+
+    #[test]
+    fn nothing_recorded() {
+        let mut recorder = SessionRecorder::default();
+        recorder.record_status(&status(0, 0, 100));
+        assert_eq!(
+            recorder.finish(&SessionStats::default(), &BTreeMap::new(), at(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn markdown() {
+        let mut recorder = SessionRecorder::default();
+        recorder.record_status(&status(0, 70, 100));
+        recorder.record_status(&status(3725, 70, 100));
+        let summary = recorder
+            .finish(&SessionStats::default(), &BTreeMap::new(), at(3725))
+            .unwrap();
+        let markdown = summary.to_markdown();
+        assert!(markdown.starts_with("# Session Summary\n"));
+        assert!(
+            markdown.contains("| Duration | 1h 02m 05s |\n"),
+            "{markdown}"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::settings::{ProfileSettings, Sex};
+
+const KJ_PER_KCAL: f32 = 4.184;
+
+/// Estimated energy expenditure at a given heart rate, in kcal per minute.
+///
+/// Uses Keytel et al. (2005), which needs the profile's age and weight to be filled in.
+pub fn kcal_per_minute(bpm: u16, profile: &ProfileSettings) -> Option<f32> {
+    if profile.age == 0 || profile.weight_kg <= 0.0 {
+        return None;
+    }
+    let hr = bpm as f32;
+    let weight = profile.weight_kg;
+    let age = profile.age as f32;
+    let male = || -55.0969 + 0.6309 * hr + 0.1988 * weight + 0.2017 * age;
+    let female = || -20.4022 + 0.4472 * hr - 0.1263 * weight + 0.074 * age;
+    let kj_per_minute = match profile.sex {
+        Sex::Male => male(),
+        Sex::Female => female(),
+        Sex::Unspecified => (male() + female()) / 2.0,
+    };
+    // The formula goes negative at low heart rates
+    Some(kj_per_minute.max(0.0) / KJ_PER_KCAL)
+}
+
+/// Banister's TRIMP for one minute spent at `reserve_fraction` of the heart rate reserve
+pub fn trimp_per_minute(reserve_fraction: f32, sex: Sex) -> f32 {
+    let reserve_fraction = reserve_fraction.clamp(0.0, 1.0);
+    let male = || 0.64 * (1.92 * reserve_fraction).exp();
+    let female = || 0.86 * (1.67 * reserve_fraction).exp();
+    let weighting = match sex {
+        Sex::Male => male(),
+        Sex::Female => female(),
+        Sex::Unspecified => (male() + female()) / 2.0,
+    };
+    reserve_fraction * weighting
+}
+
+/// Turns the monitor's running `energy_expended` total (kJ) into a session total
+#[derive(Debug, Default)]
+pub struct EnergyCounter {
+    last_reading: Option<u16>,
+    total_kj: u32,
+}
+
+impl EnergyCounter {
+    pub fn update(&mut self, reading: u16) {
+        if let Some(last) = self.last_reading {
+            self.total_kj += if reading >= last {
+                (reading - last) as u32
+            } else {
+                // The monitor reset its total (or it maxed out and wrapped)
+                reading as u32
+            };
+        }
+        self.last_reading = Some(reading);
+    }
+    /// None if the monitor never reported its energy expended
+    pub fn kcal(&self) -> Option<f32> {
+        self.last_reading
+            .map(|_| self.total_kj as f32 / KJ_PER_KCAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(sex: Sex) -> ProfileSettings {
+        ProfileSettings {
+            age: 30,
+            weight_kg: 70.0,
+            sex,
+        }
+    }
+
+    #[test]
+    fn keytel() {
+        // (-55.0969 + 0.6309 * 150 + 0.1988 * 70 + 0.2017 * 30) / 4.184
+        let male = kcal_per_minute(150, &profile(Sex::Male)).unwrap();
+        assert!((male - 14.222).abs() < 0.01, "{male}");
+        // (-20.4022 + 0.4472 * 150 - 0.1263 * 70 + 0.074 * 30) / 4.184
+        let female = kcal_per_minute(150, &profile(Sex::Female)).unwrap();
+        assert!((female - 9.574).abs() < 0.01, "{female}");
+        let unspecified = kcal_per_minute(150, &profile(Sex::Unspecified)).unwrap();
+        assert!((unspecified - (male + female) / 2.0).abs() < 0.01);
+
+        assert_eq!(kcal_per_minute(40, &profile(Sex::Female)), Some(0.0));
+        assert_eq!(kcal_per_minute(150, &ProfileSettings::default()), None);
+    }
+
+    #[test]
+    fn banister() {
+        // An hour at 50% of reserve
+        let male = trimp_per_minute(0.5, Sex::Male) * 60.0;
+        assert!((male - 0.5 * 0.64 * 0.96f32.exp() * 60.0).abs() < 0.01);
+        let female = trimp_per_minute(0.5, Sex::Female) * 60.0;
+        assert!((female - 0.5 * 0.86 * 0.835f32.exp() * 60.0).abs() < 0.01);
+        assert_eq!(trimp_per_minute(-0.2, Sex::Male), 0.0);
+    }
+
+    #[test]
+    fn energy_counter() {
+        let mut counter = EnergyCounter::default();
+        assert_eq!(counter.kcal(), None);
+        // First reading is just the starting point
+        counter.update(100);
+        assert_eq!(counter.kcal(), Some(0.0));
+        counter.update(142);
+        // Monitor reset
+        counter.update(10);
+        assert!((counter.kcal().unwrap() - 52.0 / KJ_PER_KCAL).abs() < 0.001);
+    }
+}
@@ -25,6 +25,8 @@ use crate::widgets::device_table::device_table;
 use crate::widgets::heart_rate_display::heart_rate_display;
 use crate::widgets::inspect_overlay::inspect_overlay;
 use crate::widgets::prompts::save_prompt;
+use crate::widgets::session_browser::render_session_browser;
+use crate::widgets::session_summary::render_session_summary;
 
 use ratatui::layout::{Constraint, Direction, Layout};
 
@@ -32,6 +34,11 @@ use std::sync::atomic::Ordering;
 
 /// Renders the user interface widgets.
 pub fn render(app: &mut App, f: &mut Frame) {
+    // Nothing else is running anymore at this point
+    if let Some(summary) = app.session_summary.as_ref() {
+        render_session_summary(summary, f);
+        return;
+    }
     //app.frame_count = f.count();
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -76,6 +83,9 @@ pub fn render(app: &mut App, f: &mut Frame) {
         AppView::HeartRateView => {
             heart_rate_display(app, f);
         }
+        AppView::SessionBrowser => {
+            render_session_browser(app, f);
+        }
         AppView::WaitingForWebsocket => {
             // TODO Move out to a function
             let area = centered_rect(60, 60, f.area());
@@ -30,6 +30,7 @@ pub fn action_bar(
         } else {
             text!["[c → load characteristics]".to_string()]
         },
+        text!["[l → session logs]"],
         text![cargo_crate_version!()].right_aligned(),
     ])
     .style(Style::default().fg(Color::DarkGray))];
@@ -41,6 +42,7 @@ pub fn action_bar(
             Constraint::Length(20),
             Constraint::Length(17),
             Constraint::Length(30),
+            Constraint::Length(18),
             Constraint::Fill(1),
         ],
     )
@@ -11,6 +11,7 @@ use ratatui_macros::{line, span};
 
 use crate::{
     app::App,
+    hrv::{FrequencyMetrics, HF_BAND, LF_BAND},
     widgets::heart_rate_display::{CHART_BPM_MAX_ELEMENTS, CHART_RR_MAX_ELEMENTS},
 };
 
@@ -20,6 +21,43 @@ pub enum ChartType {
     Combined,
 }
 
+/// What a BPM/RR chart gets drawn from
+pub struct ChartData<'a> {
+    pub title: String,
+    pub bpm: &'a [(f64, f64)],
+    pub rr: &'a [(f64, f64)],
+    // A second session drawn over the first, for comparison
+    pub compare: Option<ComparedData<'a>>,
+    pub bpm_bounds: [f64; 2],
+    pub mid_bpm: f64,
+    pub rr_bounds: [f64; 2],
+    pub mid_rr: f64,
+    // None for the live charts, which hold a fixed number of elements
+    pub x_bounds: Option<[f64; 2]>,
+}
+
+pub struct ComparedData<'a> {
+    pub name: String,
+    pub bpm: &'a [(f64, f64)],
+    pub rr: &'a [(f64, f64)],
+}
+
+impl<'a> ChartData<'a> {
+    pub fn live(app: &'a App) -> Self {
+        Self {
+            title: "Histogram".into(),
+            bpm: &app.bpm_dataset,
+            rr: &app.rr_dataset,
+            compare: None,
+            bpm_bounds: [app.chart_low_bpm, app.chart_high_bpm],
+            mid_bpm: app.chart_mid_bpm,
+            rr_bounds: [app.chart_low_rr, app.chart_high_rr],
+            mid_rr: app.chart_mid_rr,
+            x_bounds: None,
+        }
+    }
+}
+
 fn legend_rect(width: u16, height: u16, graph_area: Rect) -> Rect {
     let popup_size = Rect {
         width: width + 2,
@@ -33,8 +71,12 @@ fn legend_rect(width: u16, height: u16, graph_area: Rect) -> Rect {
     }
 }
 
-fn bpm_rr_legend(chart_type: &ChartType, graph_area: Rect) -> (Paragraph, Rect) {
-    let text = match chart_type {
+fn bpm_rr_legend<'a>(
+    chart_type: &ChartType,
+    compare: Option<&ComparedData>,
+    graph_area: Rect,
+) -> (Paragraph<'a>, Rect) {
+    let mut text = match chart_type {
         ChartType::Combined => {
             vec![
                 line![span!(Color::Red; "BPM")],
@@ -48,6 +90,9 @@ fn bpm_rr_legend(chart_type: &ChartType, graph_area: Rect) -> (Paragraph, Rect)
             vec![line![span!(Color::Blue; "(RR)")]]
         }
     };
+    if let Some(compare) = compare {
+        text.push(line![span!(Color::LightMagenta; compare.name.clone())]);
+    }
     let max_line_length = text
         .iter()
         .map(|line| line.width())
@@ -91,38 +136,61 @@ fn styled_label(bpm: f64, rr: f64, chart_type: &ChartType, allow_space: bool) ->
     }
 }
 
-pub fn render_combined_chart(f: &mut Frame, area: Rect, app: &App, mut chart_type: ChartType) {
+pub fn render_combined_chart(
+    f: &mut Frame,
+    area: Rect,
+    data: &ChartData,
+    mut chart_type: ChartType,
+) {
     let mut datasets = Vec::new();
 
-    let rr_bounds = [app.chart_low_rr, app.chart_high_rr];
-    let mid_rr = app.chart_mid_rr;
-    let bpm_bounds = [app.chart_low_bpm, app.chart_high_bpm];
-    let mid_bpm = app.chart_mid_bpm;
+    let rr_bounds = data.rr_bounds;
+    let mid_rr = data.mid_rr;
+    let bpm_bounds = data.bpm_bounds;
+    let mid_bpm = data.mid_bpm;
 
     // Don't render combined chart if we don't have RR data.
-    if matches!(chart_type, ChartType::Combined) && app.rr_dataset.is_empty() {
+    if matches!(chart_type, ChartType::Combined) && data.rr.is_empty() {
         chart_type = ChartType::Bpm;
     }
 
     if (matches!(chart_type, ChartType::Combined)) || matches!(chart_type, ChartType::Rr) {
+        if let Some(compare) = data.compare.as_ref() {
+            datasets.push(
+                Dataset::default()
+                    .graph_type(GraphType::Line)
+                    .marker(symbols::Marker::Dot)
+                    .style(Style::default().fg(Color::LightCyan))
+                    .data(compare.rr),
+            );
+        }
         datasets.push(
             Dataset::default()
                 .name("(RR)")
                 .graph_type(GraphType::Line)
                 .marker(symbols::Marker::Dot)
                 .style(Style::default().fg(Color::Blue))
-                .data(&app.rr_dataset),
+                .data(data.rr),
         );
     }
 
     if matches!(chart_type, ChartType::Combined) || matches!(chart_type, ChartType::Bpm) {
+        if let Some(compare) = data.compare.as_ref() {
+            datasets.push(
+                Dataset::default()
+                    .graph_type(GraphType::Line)
+                    .marker(symbols::Marker::Dot)
+                    .style(Style::default().fg(Color::LightMagenta))
+                    .data(compare.bpm),
+            );
+        }
         datasets.push(
             Dataset::default()
                 .name("BPM")
                 .graph_type(GraphType::Line)
                 .marker(symbols::Marker::Dot)
                 .style(Style::default().fg(Color::Red))
-                .data(&app.bpm_dataset),
+                .data(data.bpm),
         );
     }
 
@@ -139,18 +207,21 @@ pub fn render_combined_chart(f: &mut Frame, area: Rect, app: &App, mut chart_typ
         ChartType::Rr => rr_bounds,
     };
 
-    let x_bound_top = match chart_type {
-        ChartType::Combined => CHART_BPM_MAX_ELEMENTS.max(CHART_RR_MAX_ELEMENTS),
-        ChartType::Bpm => CHART_BPM_MAX_ELEMENTS,
-        ChartType::Rr => CHART_RR_MAX_ELEMENTS,
-    };
+    let x_bounds = data.x_bounds.unwrap_or_else(|| {
+        let x_bound_top = match chart_type {
+            ChartType::Combined => CHART_BPM_MAX_ELEMENTS.max(CHART_RR_MAX_ELEMENTS),
+            ChartType::Bpm => CHART_BPM_MAX_ELEMENTS,
+            ChartType::Rr => CHART_RR_MAX_ELEMENTS,
+        };
+        [0.0, x_bound_top as f64]
+    });
 
     let chart = Chart::new(datasets)
-        .block(Block::bordered().title("Histogram".cyan().bold()))
+        .block(Block::bordered().title(data.title.clone().cyan().bold()))
         .x_axis(
             Axis::default()
                 .style(Style::default().fg(Color::Gray))
-                .bounds([0.0, x_bound_top as f64]),
+                .bounds(x_bounds),
         )
         .y_axis(
             Axis::default()
@@ -162,7 +233,80 @@ pub fn render_combined_chart(f: &mut Frame, area: Rect, app: &App, mut chart_typ
     f.render_widget(chart, area);
     // Temporarily making our own legend while we wait for Ratatui issue #1290 (https://github.com/ratatui-org/ratatui/issues/1290)
     // to allow us to change order of legend elements
-    let (legend, legend_area) = bpm_rr_legend(&chart_type, area);
+    let (legend, legend_area) = bpm_rr_legend(&chart_type, data.compare.as_ref(), area);
     f.render_widget(Clear, legend_area);
     f.render_widget(legend, legend_area);
 }
+
+pub fn render_spectrum_chart(f: &mut Frame, area: Rect, app: &App) {
+    let Some(FrequencyMetrics {
+        lf_hf_ratio,
+        spectrum,
+        ..
+    }) = app.hrv.frequency.as_ref()
+    else {
+        let waiting = Paragraph::new("Collecting RR intervals, this takes a few minutes...")
+            .block(Block::bordered().title("HRV Spectrum".cyan().bold()))
+            .wrap(Wrap { trim: true });
+        f.render_widget(waiting, area);
+        return;
+    };
+
+    let in_band = |(low, high): (f64, f64)| -> Vec<(f64, f64)> {
+        spectrum
+            .iter()
+            .filter(|(hz, _)| (low..=high).contains(hz))
+            .copied()
+            .collect()
+    };
+    let lf = in_band(LF_BAND);
+    let hf = in_band(HF_BAND);
+    let max_density = spectrum
+        .iter()
+        .map(|(_, density)| *density)
+        .fold(0.0, f64::max);
+    let max_hz = spectrum.last().map(|(hz, _)| *hz).unwrap_or(HF_BAND.1);
+
+    let datasets = vec![
+        Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(Color::DarkGray))
+            .data(spectrum),
+        Dataset::default()
+            .name("LF")
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(Color::Yellow))
+            .data(&lf),
+        Dataset::default()
+            .name("HF")
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(Color::Green))
+            .data(&hf),
+    ];
+
+    let title = format!("HRV Spectrum (LF/HF {lf_hf_ratio:.2})");
+    let chart = Chart::new(datasets)
+        .block(Block::bordered().title(title.cyan().bold()))
+        .x_axis(
+            Axis::default()
+                .title("Hz")
+                .style(Style::default().fg(Color::Gray))
+                .labels(vec![
+                    line!["0"],
+                    line![format!("{:.2}", max_hz / 2.0)],
+                    line![format!("{max_hz:.2}")],
+                ])
+                .bounds([0.0, max_hz]),
+        )
+        .y_axis(
+            Axis::default()
+                .title("ms²/Hz")
+                .style(Style::default().fg(Color::Gray))
+                .labels(vec![line!["0"], line![format!("{:.0}", max_density)]])
+                .bounds([0.0, max_density.max(1.0)]),
+        );
+    f.render_widget(chart, area);
+}
@@ -18,6 +18,10 @@ pub fn render_table(f: &mut Frame, area: Rect, app: &App) {
         line!["Battery Level"],
         line!["Session High"],
         line!["Session Low"],
+        line!["Recent Avg"],
+        line!["Calories / TRIMP"],
+        line![span!(Modifier::UNDERLINED; "H"), span!("RV")],
+        line!["Breathing"],
     ];
 
     let heart_rate_status = &app.heart_rate_status;
@@ -39,39 +43,87 @@ pub fn render_table(f: &mut Frame, area: Rect, app: &App) {
         }),
     };
 
+    let bpm = heart_rate_status.heart_rate_bpm;
+    let (bpm_string, bpm_style) = match app.hr_zones.as_ref() {
+        Some(zones) if bpm > 0 => {
+            let zone = zones.zone_of(bpm);
+            (
+                format!("{bpm} (Zone {zone})"),
+                Style::default().fg(zone_color(zone, zones.count())),
+            )
+        }
+        _ => (bpm.to_string(), Style::default()),
+    };
+
     let time_format = if app.settings.tui.session_stats_use_12hr {
         "%-I:%M %p"
     } else {
         "%H:%M"
     };
 
-    let rr_string = format!(
-        "{:.3?}",
+    // Not every packet has new beats, keep showing the last one
+    let rr_intervals: Vec<f32> = if heart_rate_status.rr_intervals.is_empty() {
+        heart_rate_status
+            .last_rr
+            .iter()
+            .map(|rr| rr.as_secs_f32())
+            .collect()
+    } else {
         heart_rate_status
             .rr_intervals
             .iter()
             .map(|rr| rr.as_secs_f32())
-            .collect::<Vec<f32>>()
-    );
+            .collect()
+    };
+    let rr_string = format!("{rr_intervals:.3?}");
+
+    let session_stats = &app.session_stats;
 
     let high_string = format!(
-        "{:.0} BPM @ {}",
-        app.session_high_bpm.0,
-        app.session_high_bpm.1.format(time_format)
+        "{} BPM @ {}",
+        session_stats.max_bpm.0,
+        session_stats.max_bpm.1.format(time_format)
     );
 
     let low_string = format!(
-        "{:.0} BPM @ {}",
-        app.session_low_bpm.0,
-        app.session_low_bpm.1.format(time_format)
+        "{} BPM @ {}",
+        session_stats.min_bpm.0,
+        session_stats.min_bpm.1.format(time_format)
+    );
+
+    let average_string = format!(
+        "{:.0} BPM ({:.0}%)",
+        session_stats.rolling_average_bpm,
+        session_stats.percent_of_max * 100.0
     );
 
+    let training_string = match session_stats.calories {
+        Some(calories) => format!("{calories:.0} kcal / {:.0}", session_stats.trimp),
+        None => format!("N/A / {:.0}", session_stats.trimp),
+    };
+
+    let hrv = &app.hrv;
+    let hrv_string = if hrv.beats > 0 {
+        format!("RMSSD {:.0}ms", hrv.rmssd_ms)
+    } else {
+        "N/A".into()
+    };
+
+    let respiration_string = match hrv.respiration_rate {
+        Some(rate) => format!("{rate:.1}/min"),
+        None => "N/A".into(),
+    };
+
     let mut content = vec![
-        Cell::from(heart_rate_status.heart_rate_bpm.to_string()),
+        Cell::from(bpm_string).style(bpm_style),
         Cell::from(rr_string),
         Cell::from(battery_string).style(battery_style),
         Cell::from(high_string),
         Cell::from(low_string),
+        Cell::from(average_string),
+        Cell::from(training_string),
+        Cell::from(hrv_string),
+        Cell::from(respiration_string),
     ];
 
     let mut constraints = vec![
@@ -80,6 +132,10 @@ pub fn render_table(f: &mut Frame, area: Rect, app: &App) {
         Constraint::Length(15),
         Constraint::Length(20),
         Constraint::Length(20),
+        Constraint::Length(16),
+        Constraint::Length(16),
+        Constraint::Length(14),
+        Constraint::Length(10),
     ];
 
     if app.settings.activities.enabled {
@@ -104,3 +160,21 @@ pub fn render_table(f: &mut Frame, area: Rect, app: &App) {
 
     f.render_widget(table, area);
 }
+
+/// Cooler colors for lower zones, with the top zone always being red
+fn zone_color(zone: u8, zone_count: usize) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Gray,
+        Color::Blue,
+        Color::Green,
+        Color::Yellow,
+        Color::LightRed,
+        Color::Red,
+    ];
+    if zone_count <= 1 {
+        return COLORS[0];
+    }
+    // Spread however many zones there are across the palette
+    let index = zone as usize * (COLORS.len() - 1) / (zone_count - 1);
+    COLORS[index.min(COLORS.len() - 1)]
+}
@@ -5,7 +5,10 @@ use ratatui::{
 
 use crate::{
     app::App,
-    widgets::heart_rate::{charts::render_combined_chart, charts::ChartType, tables::render_table},
+    widgets::heart_rate::{
+        charts::render_combined_chart, charts::render_spectrum_chart, charts::ChartData,
+        charts::ChartType, tables::render_table,
+    },
 };
 
 // TODO Ascii Heart Beat Animation
@@ -30,15 +33,18 @@ pub fn heart_rate_display(app: &App, frame: &mut Frame) {
     let bpm_chart = app.settings.tui.chart_bpm_enabled;
     let rr_chart = app.settings.tui.chart_rr_enabled;
     let combined = app.settings.tui.charts_combine;
+    let chart_data = ChartData::live(app);
 
-    if combined && bpm_chart && rr_chart {
-        render_combined_chart(frame, shared_chart, app, ChartType::Combined);
+    if app.show_hrv_spectrum {
+        render_spectrum_chart(frame, shared_chart, app);
+    } else if combined && bpm_chart && rr_chart {
+        render_combined_chart(frame, shared_chart, &chart_data, ChartType::Combined);
     } else if bpm_chart && rr_chart {
-        render_combined_chart(frame, bpm_history, app, ChartType::Bpm);
-        render_combined_chart(frame, rr_history, app, ChartType::Rr);
+        render_combined_chart(frame, bpm_history, &chart_data, ChartType::Bpm);
+        render_combined_chart(frame, rr_history, &chart_data, ChartType::Rr);
     } else if bpm_chart {
-        render_combined_chart(frame, shared_chart, app, ChartType::Bpm);
+        render_combined_chart(frame, shared_chart, &chart_data, ChartType::Bpm);
     } else if rr_chart {
-        render_combined_chart(frame, shared_chart, app, ChartType::Rr);
+        render_combined_chart(frame, shared_chart, &chart_data, ChartType::Rr);
     }
 }
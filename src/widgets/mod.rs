@@ -5,3 +5,5 @@ pub mod heart_rate;
 pub mod heart_rate_display;
 pub mod inspect_overlay;
 pub mod prompts;
+pub mod session_browser;
+pub mod session_summary;
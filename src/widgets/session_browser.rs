@@ -0,0 +1,90 @@
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    widgets::{Block, Borders, Paragraph, Row, Table},
+    Frame,
+};
+use ratatui_macros::row;
+
+use crate::app::App;
+use crate::widgets::heart_rate::charts::{render_combined_chart, ChartType};
+
+/// Past sessions read from their CSV logs, with the highlighted one charted below.
+pub fn render_session_browser(app: &App, f: &mut Frame) {
+    let folder = &app.settings.misc.log_sessions_csv_path;
+    let Some(browser) = app.session_browser.as_ref() else {
+        return;
+    };
+    let vertical = Layout::vertical([
+        Constraint::Percentage(35),
+        Constraint::Fill(1),
+        Constraint::Length(1),
+    ]);
+    let [table_area, chart_area, hint_area] = vertical.areas(f.area());
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Session Logs".cyan().bold());
+    if browser.is_loading() {
+        let loading = Paragraph::new(format!("Reading session logs in \"{folder}\"..."))
+            .centered()
+            .block(block);
+        f.render_widget(loading, table_area);
+    } else if browser.sessions.is_empty() {
+        let empty = Paragraph::new(format!("No session logs found in \"{folder}\""))
+            .centered()
+            .block(block);
+        f.render_widget(empty, table_area);
+    } else {
+        let rows: Vec<Row> = browser
+            .sessions
+            .iter()
+            .enumerate()
+            .map(|(index, session)| {
+                let secs = session.duration.as_secs();
+                let row = row![
+                    session.name.clone(),
+                    session.start.format("%Y-%m-%d %H:%M").to_string(),
+                    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+                    session.min_bpm.to_string(),
+                    format!("{:.1}", session.average_bpm),
+                    session.max_bpm.to_string(),
+                ];
+                if browser.compare == Some(index) {
+                    row.style(Style::default().fg(Color::LightMagenta))
+                } else {
+                    row
+                }
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+                Constraint::Length(12),
+                Constraint::Length(5),
+                Constraint::Length(6),
+                Constraint::Length(5),
+            ],
+        )
+        .header(
+            row!["Session", "Started", "Duration", "Min", "Avg", "Max"]
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">> ")
+        .block(block);
+        f.render_stateful_widget(table, table_area, &mut browser.table_state.clone());
+    }
+
+    if let Some(shown) = browser.highlighted() {
+        let compare_name = browser.compared().map(|session| session.name.clone());
+        let data = browser.chart.data(shown.name.clone(), compare_name);
+        render_combined_chart(f, chart_area, &data, ChartType::Combined);
+    }
+
+    let hints = Paragraph::new("[esc → back]  [up/down → select]  [c → compare with selected]")
+        .style(Style::default().fg(Color::DarkGray));
+    f.render_widget(hints, hint_area);
+}
@@ -0,0 +1,28 @@
+use ratatui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Clear, Row, Table},
+    Frame,
+};
+
+use crate::summary::SessionSummary;
+use crate::utils::centered_rect;
+
+/// Shown after the threads have stopped, until a key is pressed.
+pub fn render_session_summary(summary: &SessionSummary, f: &mut Frame) {
+    let area = centered_rect(60, 90, f.area());
+    let rows: Vec<Row> = summary
+        .rows()
+        .into_iter()
+        .map(|(label, value)| Row::new(vec![label, value]).style(Style::default().fg(Color::White)))
+        .collect();
+    let table = Table::new(rows, [Constraint::Length(20), Constraint::Fill(1)]).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Session Summary")
+            .title_bottom("Press any key to exit")
+            .border_style(Style::default().add_modifier(Modifier::BOLD)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(table, area);
+}
@@ -0,0 +1,176 @@
+use crate::errors::AppError;
+use crate::settings::{ZoneMethod, ZoneSettings};
+
+/// Heart rate zones, as BPM thresholds.
+///
+/// Zone 0 is anything below the first threshold, and each threshold reached adds one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HrZones {
+    max_bpm: u16,
+    resting_bpm: u16,
+    thresholds: Vec<u16>,
+}
+
+impl HrZones {
+    /// `age` (from the profile) is only used to estimate max BPM when it's not set
+    pub fn build(settings: &ZoneSettings, age: u8) -> Result<Self, AppError> {
+        let max_bpm = match (settings.max_bpm, age) {
+            (0, 0) => {
+                return Err(AppError::Zones(
+                    "either max_bpm or profile.age must be above 0".into(),
+                ))
+            }
+            // The classic (if rough) estimate
+            (0, age) => 220u16.saturating_sub(age as u16),
+            (max_bpm, _) => max_bpm,
+        };
+        let thresholds = match settings.method {
+            ZoneMethod::PercentMax => percentages_of(&settings.percentages, 0, max_bpm)?,
+            ZoneMethod::Karvonen => {
+                if settings.resting_bpm == 0 || settings.resting_bpm >= max_bpm {
+                    return Err(AppError::Zones(
+                        "resting_bpm must be above 0 and below max_bpm".into(),
+                    ));
+                }
+                percentages_of(&settings.percentages, settings.resting_bpm, max_bpm)?
+            }
+            ZoneMethod::Custom => {
+                if settings.custom_thresholds.is_empty() {
+                    return Err(AppError::Zones("custom_thresholds is empty".into()));
+                }
+                if !ascending(&settings.custom_thresholds) {
+                    return Err(AppError::Zones(
+                        "custom_thresholds must be in ascending order".into(),
+                    ));
+                }
+                settings.custom_thresholds.clone()
+            }
+        };
+        Ok(Self {
+            max_bpm,
+            resting_bpm: settings.resting_bpm,
+            thresholds,
+        })
+    }
+    pub fn max_bpm(&self) -> u16 {
+        self.max_bpm
+    }
+    pub fn resting_bpm(&self) -> u16 {
+        self.resting_bpm
+    }
+    /// How far `bpm` is into the heart rate reserve (0.0 at resting, 1.0 at max)
+    pub fn reserve_fraction(&self, bpm: u16) -> f32 {
+        if self.resting_bpm >= self.max_bpm {
+            return 0.0;
+        }
+        let reserve = (self.max_bpm - self.resting_bpm) as f32;
+        ((bpm as f32 - self.resting_bpm as f32) / reserve).clamp(0.0, 1.0)
+    }
+    /// How many zones there are, including zone 0
+    pub fn count(&self) -> usize {
+        self.thresholds.len() + 1
+    }
+    pub fn zone_of(&self, bpm: u16) -> u8 {
+        self.thresholds.iter().take_while(|&&t| bpm >= t).count() as u8
+    }
+}
+
+fn ascending<T: PartialOrd>(values: &[T]) -> bool {
+    values.windows(2).all(|w| w[0] < w[1])
+}
+
+/// Percentages of the range between `low` and `high`, as BPMs
+fn percentages_of(percentages: &[u8], low: u16, high: u16) -> Result<Vec<u16>, AppError> {
+    if percentages.is_empty() {
+        return Err(AppError::Zones("percentages is empty".into()));
+    }
+    if !ascending(percentages) {
+        return Err(AppError::Zones(
+            "percentages must be in ascending order".into(),
+        ));
+    }
+    let range = (high - low) as f32;
+    Ok(percentages
+        .iter()
+        .map(|&percent| low + (range * percent as f32 / 100.0).round() as u16)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(method: ZoneMethod) -> ZoneSettings {
+        ZoneSettings {
+            method,
+            max_bpm: 200,
+            resting_bpm: 60,
+            percentages: vec![50, 60, 70, 80, 90],
+            custom_thresholds: vec![],
+        }
+    }
+
+    #[test]
+    fn percent_of_max() {
+        let zones = HrZones::build(&settings(ZoneMethod::PercentMax), 0).unwrap();
+        assert_eq!(zones.count(), 6);
+        assert_eq!(zones.zone_of(0), 0);
+        assert_eq!(zones.zone_of(99), 0);
+        assert_eq!(zones.zone_of(100), 1);
+        assert_eq!(zones.zone_of(139), 2);
+        assert_eq!(zones.zone_of(180), 5);
+        assert_eq!(zones.zone_of(250), 5);
+    }
+
+    #[test]
+    fn karvonen() {
+        // Reserve of 140 BPM, so zones start at 130, 144, 158, 172, 186
+        let zones = HrZones::build(&settings(ZoneMethod::Karvonen), 0).unwrap();
+        assert_eq!(zones.thresholds, vec![130, 144, 158, 172, 186]);
+        assert_eq!(zones.zone_of(129), 0);
+        assert_eq!(zones.zone_of(150), 2);
+    }
+
+    #[test]
+    fn max_from_age() {
+        let mut settings = settings(ZoneMethod::PercentMax);
+        settings.max_bpm = 0;
+        let zones = HrZones::build(&settings, 30).unwrap();
+        assert_eq!(zones.max_bpm(), 190);
+        assert_eq!(zones.thresholds[0], 95);
+    }
+
+    #[test]
+    fn custom() {
+        let mut settings = settings(ZoneMethod::Custom);
+        settings.custom_thresholds = vec![100, 140];
+        let zones = HrZones::build(&settings, 0).unwrap();
+        assert_eq!(zones.count(), 3);
+        assert_eq!(zones.zone_of(120), 1);
+        assert_eq!(zones.zone_of(140), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "ascending")]
+    fn unordered_percentages() {
+        let mut settings = settings(ZoneMethod::PercentMax);
+        settings.percentages = vec![60, 50];
+        HrZones::build(&settings, 0).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "resting_bpm")]
+    fn karvonen_needs_resting() {
+        let mut settings = settings(ZoneMethod::Karvonen);
+        settings.resting_bpm = 0;
+        HrZones::build(&settings, 0).unwrap();
+    }
+
+    #[test]
+    fn reserve() {
+        let zones = HrZones::build(&settings(ZoneMethod::PercentMax), 0).unwrap();
+        assert_eq!(zones.reserve_fraction(130), 0.5);
+        assert_eq!(zones.reserve_fraction(40), 0.0);
+        assert_eq!(zones.reserve_fraction(220), 1.0);
+    }
+}
@@ -18,6 +18,7 @@ async fn misspelled_bool() {
         no_save: true,
         subcommands: None,
         skip_prompts: true,
+        csv_stdout: false,
     };
 
     iron_heart::run_headless(arg_config, parent_token)
@@ -39,6 +40,7 @@ async fn missing_end_quote() {
         no_save: true,
         subcommands: None,
         skip_prompts: true,
+        csv_stdout: false,
     };
 
     iron_heart::run_headless(arg_config, parent_token)